@@ -0,0 +1,161 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::simul::node_types::{Hash256, NodeID, NodePublicKey, NodeSecret, Signature};
+
+/// HTTP header holding the hexadecimal public key of the calling node.
+pub const KEY_HEADER: &str = "X-Node-Key";
+/// HTTP header holding the time of the request in milliseconds since the epoch.
+pub const TIME_HEADER: &str = "X-Node-Time";
+/// HTTP header holding the hexadecimal signature of the request.
+pub const SIGNATURE_HEADER: &str = "X-Node-Signature";
+
+/// How far the time of a signed request may be away from the time of the
+/// server, in milliseconds.
+/// Within this window, `UsedSignatures` refuses a second request with the
+/// same key and time.
+pub const MAX_CLOCK_SKEW: u128 = 60_000;
+
+/// Authenticates an HTTP request without sending the secret of the node.
+/// It is sent in the `KEY_HEADER`, `TIME_HEADER`, and `SIGNATURE_HEADER`,
+/// or as JSON for the websocket, where browsers cannot set headers.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone)]
+pub struct HttpSignature {
+    pub key: NodePublicKey,
+    pub time: u128,
+    /// Signature over `HttpSignature::message` with the key.
+    pub signature: Signature,
+}
+
+impl HttpSignature {
+    /// Signs a request with the given method, path including the query, and
+    /// body at the given time.
+    pub fn new(secret: &NodeSecret, method: &str, path: &str, body: &[u8], time: u128) -> Self {
+        Self {
+            key: secret.public_key(),
+            time,
+            signature: secret.sign(&Self::message(method, path, body, time)),
+        }
+    }
+
+    /// Returns the bytes to be signed for a request.
+    /// The body is included by its SHA-256 hash, and is empty for requests
+    /// without a body.
+    pub fn message(method: &str, path: &str, body: &[u8], time: u128) -> Vec<u8> {
+        let mut msg = b"cybernode-http".to_vec();
+        msg.extend_from_slice(&time.to_be_bytes());
+        msg.extend_from_slice(&Hash256::digest(body).to_bytes());
+        msg.extend_from_slice(method.as_bytes());
+        msg.push(b' ');
+        msg.extend_from_slice(path.as_bytes());
+        msg
+    }
+
+    /// Returns the ID of the signing node if the signature matches the
+    /// request and is not older or newer than `MAX_CLOCK_SKEW`.
+    pub fn verify(&self, method: &str, path: &str, body: &[u8], now: u128) -> Option<NodeID> {
+        (self.time.abs_diff(now) <= MAX_CLOCK_SKEW
+            && self.key.verify(
+                &Self::message(method, path, body, self.time),
+                &self.signature,
+            ))
+        .then(|| self.key.into())
+    }
+
+    /// Returns the headers to be added to the request.
+    pub fn headers(&self) -> [(&'static str, String); 3] {
+        [
+            (KEY_HEADER, format!("{:#x}", self.key)),
+            (TIME_HEADER, self.time.to_string()),
+            (SIGNATURE_HEADER, format!("{:#x}", self.signature)),
+        ]
+    }
+}
+
+/// Remembers the key and time of the signed requests which are still within
+/// `MAX_CLOCK_SKEW`, so that a captured request cannot be replayed.
+/// A node must therefore use a new time for every request.
+#[derive(Debug, Default)]
+pub struct UsedSignatures(BTreeSet<(u128, NodeID)>);
+
+impl UsedSignatures {
+    /// Marks the signature as used, and returns false if it was used before.
+    /// Signatures which expired before `now` are forgotten.
+    pub fn insert(&mut self, sig: &HttpSignature, now: u128) -> bool {
+        let oldest = now.saturating_sub(MAX_CLOCK_SKEW);
+        self.0 = self.0.split_off(&(oldest, NodeID::zero()));
+        self.0.insert((sig.time, sig.key.into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let secret = NodeSecret::random();
+        let id = NodeID::from(secret);
+        let sig = HttpSignature::new(&secret, "PUT", "/v1/preferences?a=1", b"{}", 1_000_000);
+        let verify = |method, path, body: &[u8], now| sig.verify(method, path, body, now);
+        assert_eq!(
+            Some(id),
+            verify("PUT", "/v1/preferences?a=1", b"{}", 1_000_000)
+        );
+        assert_eq!(
+            Some(id),
+            verify(
+                "PUT",
+                "/v1/preferences?a=1",
+                b"{}",
+                1_000_000 + MAX_CLOCK_SKEW
+            )
+        );
+
+        // Other requests, bodies, queries, expired signatures, and other keys
+        // are refused.
+        assert_eq!(
+            None,
+            verify("POST", "/v1/preferences?a=1", b"{}", 1_000_000)
+        );
+        assert_eq!(None, verify("PUT", "/v1/register?a=1", b"{}", 1_000_000));
+        assert_eq!(None, verify("PUT", "/v1/preferences", b"{}", 1_000_000));
+        assert_eq!(None, verify("PUT", "/v1/preferences?a=2", b"{}", 1_000_000));
+        assert_eq!(None, verify("PUT", "/v1/preferences?a=1", b"[]", 1_000_000));
+        assert_eq!(
+            None,
+            verify(
+                "PUT",
+                "/v1/preferences?a=1",
+                b"{}",
+                1_000_001 + MAX_CLOCK_SKEW
+            )
+        );
+        let mut forged = sig.clone();
+        forged.key = NodeSecret::random().public_key();
+        assert_eq!(
+            None,
+            forged.verify("PUT", "/v1/preferences?a=1", b"{}", 1_000_000)
+        );
+    }
+
+    #[test]
+    fn test_used_signatures() {
+        let secret = NodeSecret::random();
+        let sig = |time| HttpSignature::new(&secret, "GET", "/v1/alive", b"", time);
+        let mut used = UsedSignatures::default();
+        assert!(used.insert(&sig(1_000_000), 1_000_000));
+        assert!(!used.insert(&sig(1_000_000), 1_000_000));
+        assert!(used.insert(&sig(1_000_001), 1_000_000));
+        assert!(used.insert(
+            &HttpSignature::new(&NodeSecret::random(), "GET", "/v1/alive", b"", 1_000_000),
+            1_000_000
+        ));
+
+        // Expired signatures are forgotten, as they fail the verification.
+        used.insert(&sig(2_000_000), 2_000_000);
+        assert_eq!(1, used.0.len());
+    }
+}
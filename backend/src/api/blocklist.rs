@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::simul::node_types::Hash256;
+
+use super::reputation::Category;
+
+/// The pages and names the operator of this server blocks, like a DNS0
+/// filter for safe surfing.
+///
+/// The blocklist file has one entry per line, followed by an optional
+/// reason. An entry is a domain, a SHA-256 hash of the content of a page
+/// starting with '0x', or a category of the reputation network starting
+/// with '@'. Everything after a '#' is ignored:
+///
+/// ```text
+/// # Phishing sites
+/// fake-bank        phishing
+/// 0x5d41...        malware download
+/// @malware
+/// ```
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Blocklist {
+    /// Blocked domains and names, with the reason.
+    pub domains: BTreeMap<String, String>,
+    /// Blocked pages by the hash of their content, with the reason.
+    #[schema(value_type = BTreeMap<String, String>)]
+    pub hashes: BTreeMap<Hash256, String>,
+    /// Groups flagged for one of these categories by the reputation network
+    /// are blocked.
+    pub categories: Vec<Category>,
+}
+
+impl Blocklist {
+    /// Parses the blocklist file, failing with the number of the first
+    /// invalid line.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut list = Blocklist::default();
+        for (nbr, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((entry, reason)) = line
+                .split_once(char::is_whitespace)
+                .or((!line.is_empty()).then_some((line, "")))
+            else {
+                continue;
+            };
+            let reason = reason.trim().to_string();
+            let invalid = |e: String| format!("line {}: {e}", nbr + 1);
+            if let Some(category) = entry.strip_prefix('@') {
+                let category = serde_json::from_value(category.into())
+                    .map_err(|_| invalid(format!("unknown category '{category}'")))?;
+                list.categories.push(category);
+            } else if entry.starts_with("0x") && entry.len() == 66 {
+                list.hashes.insert(entry.parse().map_err(invalid)?, reason);
+            } else {
+                list.domains.insert(entry.to_ascii_lowercase(), reason);
+            }
+        }
+        Ok(list)
+    }
+
+    /// Returns why the domain is blocked, if it is.
+    pub fn domain(&self, domain: &str) -> Option<String> {
+        self.domains
+            .get(&domain.to_ascii_lowercase())
+            .map(|reason| Self::reason(reason, "blocked domain"))
+    }
+
+    /// Returns why the content with this hash is blocked, if it is.
+    pub fn content(&self, hash: &Hash256) -> Option<String> {
+        self.hashes
+            .get(hash)
+            .map(|reason| Self::reason(reason, "blocked content"))
+    }
+
+    /// Returns why a group flagged for the categories is blocked, if it is.
+    pub fn flagged(&self, flagged: &[Category]) -> Option<String> {
+        flagged
+            .iter()
+            .find(|c| self.categories.contains(c))
+            .map(|c| format!("flagged as {}", c.name()))
+    }
+
+    // Entries without a reason get a generic one.
+    fn reason(reason: &str, default: &str) -> String {
+        match reason {
+            "" => default.to_string(),
+            reason => reason.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let hash = Hash256::digest(b"<h1>Evil</h1>");
+        let text = format!(
+            "# Blocked by the operator\n\
+             Fake-Bank   phishing  # since yesterday\n\
+             \n\
+             spam-site\n\
+             0x{} malware download\n\
+             @malware\n",
+            hash.to_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>()
+        );
+        let list = Blocklist::parse(&text).unwrap();
+        assert_eq!(Some("phishing".into()), list.domain("fake-bank"));
+        assert_eq!(Some("blocked domain".into()), list.domain("Spam-Site"));
+        assert_eq!(None, list.domain("ineiti"));
+        assert_eq!(Some("malware download".into()), list.content(&hash));
+        assert_eq!(None, list.content(&Hash256::zero()));
+        assert_eq!(
+            Some("flagged as malware".into()),
+            list.flagged(&[Category::Nsfw, Category::Malware])
+        );
+        assert_eq!(None, list.flagged(&[Category::Nsfw]));
+
+        assert!(Blocklist::parse("@unknown").is_err());
+        assert!(Blocklist::parse(&format!("0x{}", "g".repeat(64))).is_err());
+
+        // The blocklist is sent as JSON by the admin endpoint.
+        let json = serde_json::to_string(&list).unwrap();
+        assert_eq!(list, serde_json::from_str(&json).unwrap());
+    }
+}
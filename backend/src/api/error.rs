@@ -0,0 +1,16 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Body of every error returned by the HTTP API.
+#[derive(ToSchema, Serialize, Debug)]
+pub struct ErrorReply {
+    pub error: String,
+}
+
+/// Body of the replies for pages and names blocked by the operator.
+#[derive(ToSchema, Serialize, Debug)]
+pub struct BlockedReply {
+    pub error: String,
+    /// Why the operator blocks the page or the name.
+    pub reason: String,
+}
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::simul::node_types::NodeID;
+
+/// A fault injected into the simulated network.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum Fault {
+    /// Messages only go between nodes of the same partition. The nodes
+    /// which are in none of the partitions form one more partition.
+    Partition { partitions: Vec<Vec<NodeID>> },
+    /// The node can neither send nor receive messages.
+    Isolate { id: NodeID },
+    /// The messages matching the filter are lost.
+    Drop { filter: MsgFilter },
+    /// The messages matching the filter are delivered twice.
+    Duplicate { filter: MsgFilter },
+}
+
+/// Selects messages. Fields which are not set match all messages.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MsgFilter {
+    pub from: Option<NodeID>,
+    pub to: Option<NodeID>,
+    /// The type of the message, like "PageRequest". Unknown types are refused.
+    pub msg: Option<String>,
+}
+
+/// A fault to inject.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FaultRequest {
+    pub fault: Fault,
+    /// The time of the tick which heals the fault. If not set, the fault
+    /// stays until it is removed.
+    pub heal_at: Option<u128>,
+}
+
+/// A fault which is active in the network.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActiveFault {
+    pub id: u64,
+    pub fault: Fault,
+    pub heal_at: Option<u128>,
+}
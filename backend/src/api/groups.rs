@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::simul::node_types::{GroupID, NodeID};
+
+/// The members of a group which are currently online.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupMembers {
+    pub group: GroupID,
+    pub members: Vec<NodeID>,
+}
+
+#[derive(Debug, derive_more::Display, derive_more::Error, Clone, PartialEq)]
+pub enum GroupError {
+    #[display(fmt = "The node is not registered.")]
+    UnknownNode,
+    #[display(fmt = "The node is not a member of this group.")]
+    NotMember,
+}
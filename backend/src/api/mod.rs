@@ -1 +1,11 @@
-pub mod stats;
\ No newline at end of file
+pub mod auth;
+pub mod blocklist;
+pub mod error;
+pub mod faults;
+pub mod groups;
+pub mod names;
+pub mod reputation;
+pub mod stats;
+pub mod storage;
+pub mod updates;
+pub mod wallet;
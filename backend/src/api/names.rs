@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::simul::node_types::{GroupID, Mana, NodeID};
+
+/// The longest name which can be registered, as for DNS labels.
+pub const NAME_MAX_LEN: usize = 63;
+
+/// A name registered by a node, pointing to the group hosting its content,
+/// so that `cyno://name/` can be resolved.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NameRecord {
+    pub name: String,
+    pub owner: NodeID,
+    pub group: GroupID,
+    /// Time after which the name doesn't resolve anymore, unless it is
+    /// renewed by the owner.
+    pub expires: u128,
+}
+
+/// Registers or renews a name for the calling node.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NameRequest {
+    pub group: GroupID,
+}
+
+/// Gives a name of the calling node to another node.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NameTransferRequest {
+    pub to: NodeID,
+}
+
+/// A resolved name, with the members of its group which are online.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NameResolution {
+    pub record: NameRecord,
+    pub members: Vec<NodeID>,
+}
+
+#[derive(Debug, derive_more::Display, derive_more::Error, Clone, PartialEq)]
+pub enum NameError {
+    #[display(
+        fmt = "Names must be 1 to {} letters, digits, or '-', and cannot start or end with '-'.",
+        NAME_MAX_LEN
+    )]
+    InvalidName,
+    #[display(fmt = "The name belongs to another node.")]
+    Taken,
+    #[display(fmt = "Only the owner can change the name.")]
+    NotOwner,
+    #[display(fmt = "Not enough mana, the name costs {}.", fee)]
+    NotEnoughMana { fee: Mana },
+    #[display(fmt = "The node is not registered.")]
+    UnknownNode,
+    #[display(fmt = "The new owner is not registered.")]
+    UnknownReceiver,
+    #[display(fmt = "There is no such name.")]
+    UnknownName,
+    #[display(fmt = "The name is blocked: {}", _0)]
+    Blocked(#[error(not(source))] String),
+}
+
+/// Returns the name in lowercase, if it is a valid DNS label.
+pub fn normalize_name(name: &str) -> Result<String, NameError> {
+    let valid = !name.is_empty()
+        && name.len() <= NAME_MAX_LEN
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid
+        .then(|| name.to_ascii_lowercase())
+        .ok_or(NameError::InvalidName)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(Ok("ineiti".to_string()), normalize_name("Ineiti"));
+        assert_eq!(Ok("my-site2".to_string()), normalize_name("my-site2"));
+        for name in ["", "-site", "site-", "my.site", "my site", "été"] {
+            assert_eq!(Err(NameError::InvalidName), normalize_name(name));
+        }
+        assert!(normalize_name(&"a".repeat(NAME_MAX_LEN)).is_ok());
+        assert!(normalize_name(&"a".repeat(NAME_MAX_LEN + 1)).is_err());
+    }
+}
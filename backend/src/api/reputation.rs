@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::simul::node_types::{GroupID, Mana, NodeID, NodePublicKey, NodeSecret, Signature};
+
+/// A category of undesirable content.
+#[derive(
+    ToSchema, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Nsfw,
+    Violence,
+    Scam,
+    Malware,
+    Spam,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [
+        Category::Nsfw,
+        Category::Violence,
+        Category::Scam,
+        Category::Malware,
+        Category::Spam,
+    ];
+
+    /// Returns the name of the category, as used in the JSON.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Nsfw => "nsfw",
+            Category::Violence => "violence",
+            Category::Scam => "scam",
+            Category::Malware => "malware",
+            Category::Spam => "spam",
+        }
+    }
+}
+
+/// A vote whether the content of a group belongs to a category, signed by
+/// the voter. A later vote of the same voter replaces the earlier one.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReputationVote {
+    pub voter: NodeID,
+    pub group: GroupID,
+    pub category: Category,
+    /// True if the content belongs to the category, false to vote against
+    /// the flag.
+    pub flag: bool,
+    /// Must not be lower than the nonce of the next vote of the voter in
+    /// Trusted, so that votes cannot be replayed.
+    pub nonce: u64,
+    /// Signature over `ReputationVote::message` with the key of the voter.
+    pub signature: Signature,
+}
+
+impl ReputationVote {
+    /// Creates a vote signed by the voter with the given secret.
+    pub fn new(
+        secret: &NodeSecret,
+        group: GroupID,
+        category: Category,
+        flag: bool,
+        nonce: u64,
+    ) -> Self {
+        let voter = (*secret).into();
+        Self {
+            voter,
+            group,
+            category,
+            flag,
+            nonce,
+            signature: secret.sign(&Self::message(&voter, &group, category, flag, nonce)),
+        }
+    }
+
+    /// Returns the bytes to be signed for a vote.
+    pub fn message(
+        voter: &NodeID,
+        group: &GroupID,
+        category: Category,
+        flag: bool,
+        nonce: u64,
+    ) -> Vec<u8> {
+        let mut msg = b"cybernode-vote".to_vec();
+        msg.extend_from_slice(&voter.to_bytes());
+        msg.extend_from_slice(&group.to_bytes());
+        msg.extend_from_slice(category.name().as_bytes());
+        msg.push(flag as u8);
+        msg.extend_from_slice(&nonce.to_be_bytes());
+        msg
+    }
+
+    /// Returns true if the key belongs to the voter and the vote has been
+    /// signed with it.
+    pub fn verify(&self, key: &NodePublicKey) -> bool {
+        NodeID::from(*key) == self.voter
+            && key.verify(
+                &Self::message(
+                    &self.voter,
+                    &self.group,
+                    self.category,
+                    self.flag,
+                    self.nonce,
+                ),
+                &self.signature,
+            )
+    }
+}
+
+/// The votes for one category of a group, weighted by the mana of the
+/// voters.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CategoryScore {
+    pub category: Category,
+    /// Mana of the voters flagging the group.
+    pub flag: Mana,
+    /// Mana of the voters against the flag.
+    pub clear: Mana,
+    /// True if the flags outweigh the votes against them, and have at least
+    /// the minimum weight.
+    pub flagged: bool,
+}
+
+/// The reputation of a group, for every category somebody voted on.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupReputation {
+    pub group: GroupID,
+    pub categories: Vec<CategoryScore>,
+}
+
+impl GroupReputation {
+    /// Returns the categories the group is flagged for.
+    pub fn flagged(&self) -> Vec<Category> {
+        self.categories
+            .iter()
+            .filter(|score| score.flagged)
+            .map(|score| score.category)
+            .collect()
+    }
+}
+
+/// What a node wants to happen with pages of groups flagged for a category.
+/// Categories in neither list are served without warning.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContentPolicy {
+    /// The pages are not served.
+    pub refuse: Vec<Category>,
+    /// The pages are served with a warning.
+    pub warn: Vec<Category>,
+}
+
+/// By default, pages of all flagged groups are served with a warning.
+impl Default for ContentPolicy {
+    fn default() -> Self {
+        Self {
+            refuse: vec![],
+            warn: Category::ALL.to_vec(),
+        }
+    }
+}
+
+/// What to do with a page, following a `ContentPolicy`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentCheck {
+    Serve,
+    /// The operator blocks the page, with the reason.
+    Blocked(String),
+    Warn(Vec<Category>),
+    Refuse(Vec<Category>),
+}
+
+impl ContentPolicy {
+    /// Checks the categories a group is flagged for against this policy.
+    pub fn check(&self, flagged: &[Category]) -> ContentCheck {
+        let matching = |list: &[Category]| -> Vec<Category> {
+            flagged
+                .iter()
+                .filter(|c| list.contains(c))
+                .copied()
+                .collect()
+        };
+        let refuse = matching(&self.refuse);
+        if !refuse.is_empty() {
+            return ContentCheck::Refuse(refuse);
+        }
+        let warn = matching(&self.warn);
+        if !warn.is_empty() {
+            return ContentCheck::Warn(warn);
+        }
+        ContentCheck::Serve
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vote_signature() {
+        let secret = NodeSecret::random();
+        let group = GroupID::random();
+        let vote = ReputationVote::new(&secret, group, Category::Scam, true, 3);
+        assert!(vote.verify(&secret.public_key()));
+        assert!(!vote.verify(&NodeSecret::random().public_key()));
+        let changed = ReputationVote {
+            flag: false,
+            ..vote
+        };
+        assert!(!changed.verify(&secret.public_key()));
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = ContentPolicy {
+            refuse: vec![Category::Malware],
+            warn: vec![Category::Nsfw, Category::Malware],
+        };
+        assert_eq!(ContentCheck::Serve, policy.check(&[]));
+        assert_eq!(ContentCheck::Serve, policy.check(&[Category::Spam]));
+        assert_eq!(
+            ContentCheck::Warn(vec![Category::Nsfw]),
+            policy.check(&[Category::Nsfw, Category::Spam])
+        );
+        assert_eq!(
+            ContentCheck::Refuse(vec![Category::Malware]),
+            policy.check(&[Category::Nsfw, Category::Malware])
+        );
+    }
+}
@@ -1,9 +1,30 @@
 use serde::Serialize;
 use utoipa::ToSchema;
 
-use crate::simul::node_types::NodeID;
+use crate::simul::node_types::{Mana, NodeID};
 
 #[derive(ToSchema, Serialize)]
 pub struct StatsReply {
    pub ids: Vec<NodeID>,
 }
+
+/// Overview of the network as seen by Trusted.
+/// The field names match the `NetworkStatus` of the frontend.
+#[derive(ToSchema, Serialize, Debug, Clone)]
+pub struct NetworkStatus {
+    /// All nodes registered with Trusted, active or not.
+    pub users_total: usize,
+    /// Nodes which sent an 'alive' message recently.
+    pub users_active: usize,
+    /// Sum of the mana of all registered nodes.
+    pub mana_total: Mana,
+    pub nodes: Vec<NodeSummary>,
+}
+
+#[derive(ToSchema, Serialize, Debug, Clone)]
+pub struct NodeSummary {
+    pub id: NodeID,
+    pub name: String,
+    pub mana: Mana,
+    pub active: bool,
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::simul::node_types::NodeID;
+
+/// How much of the storage of a node is used, in bytes.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct StorageUsage {
+    pub used: u64,
+    pub quota: u64,
+}
+
+/// The files of a domain, and the storage usage of the node holding them.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DomainFiles {
+    pub usage: StorageUsage,
+    pub files: Vec<FileInfo>,
+}
+
+/// A file stored in a domain.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileInfo {
+    pub path: String,
+    pub content_type: String,
+    pub size: u64,
+}
+
+/// The domains which are stored on fewer nodes than the replication factor.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplicationReport {
+    pub factor: usize,
+    pub under_replicated: Vec<DomainReplicas>,
+}
+
+/// The online nodes holding an up-to-date copy of a domain.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DomainReplicas {
+    pub domain: String,
+    pub holders: Vec<NodeID>,
+}
+
+/// The content of an owner who cannot pay the rent for it anymore.
+/// It is deleted from all nodes after `delete_after`.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeletableContent {
+    pub owner: NodeID,
+    pub since: u128,
+    pub delete_after: u128,
+}
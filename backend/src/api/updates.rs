@@ -0,0 +1,165 @@
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::trace;
+use utoipa::ToSchema;
+
+use crate::simul::{
+    broker::BrokerEvent,
+    node::Msg,
+    node_types::{Mana, NodeID},
+};
+
+/// Updates pushed to a node over its websocket.
+#[derive(ToSchema, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum NodeUpdate {
+    /// The mana of the subscribed node changed.
+    Mana { mana: Mana },
+    /// A node joined the network.
+    NodeJoined { id: NodeID },
+    /// A node left the network.
+    NodeLeft { id: NodeID },
+    /// The subscribed node received a message.
+    Message {
+        from: NodeID,
+        #[schema(value_type = Object)]
+        msg: Msg,
+    },
+    /// A message of the subscribed node could not be delivered, because the
+    /// receiver is not in the network.
+    Undelivered {
+        to: NodeID,
+        #[schema(value_type = Object)]
+        msg: Msg,
+    },
+}
+
+/// Keeps track of the websockets of the nodes and dispatches the
+/// `BrokerEvent`s to them.
+/// A node can have more than one websocket open.
+#[derive(Default)]
+pub struct Subscribers {
+    subs: Vec<Subscriber>,
+}
+
+struct Subscriber {
+    id: NodeID,
+    // The last mana sent to this subscriber.
+    mana: Option<Mana>,
+    tx: UnboundedSender<NodeUpdate>,
+}
+
+impl Subscribers {
+    pub fn add(&mut self, id: NodeID, tx: UnboundedSender<NodeUpdate>) {
+        self.subs.push(Subscriber { id, mana: None, tx });
+    }
+
+    /// Removes the subscriber with this channel.
+    pub fn remove(&mut self, tx: &UnboundedSender<NodeUpdate>) {
+        self.subs.retain(|s| !s.tx.same_channel(tx));
+    }
+
+    /// Returns the ids of all subscribed nodes.
+    pub fn ids(&self) -> Vec<NodeID> {
+        let mut ids: Vec<NodeID> = vec![];
+        for sub in &self.subs {
+            if !ids.contains(&sub.id) {
+                ids.push(sub.id);
+            }
+        }
+        ids
+    }
+
+    /// Sends the mana to all subscribers of this node, if it changed
+    /// since the last call.
+    pub fn mana(&mut self, id: NodeID, mana: Mana) {
+        self.subs.retain_mut(|s| {
+            if s.id != id || s.mana == Some(mana) {
+                return true;
+            }
+            s.mana = Some(mana);
+            s.tx.send(NodeUpdate::Mana { mana }).is_ok()
+        });
+    }
+
+    /// Forwards an event to the concerned subscribers.
+    /// Joining and leaving nodes are sent to everybody, while messages
+    /// are only sent to the subscribers of the receiving node, and
+    /// undelivered messages to the subscribers of the sending node.
+    pub fn event(&mut self, event: BrokerEvent) {
+        trace!("Dispatching {event:?}");
+        let (to, update) = match event {
+            BrokerEvent::NodeAdd(id) => (None, NodeUpdate::NodeJoined { id }),
+            BrokerEvent::NodeDel(id) => (None, NodeUpdate::NodeLeft { id }),
+            BrokerEvent::NodeMsg(msg) => (
+                Some(msg.to),
+                NodeUpdate::Message {
+                    from: msg.from,
+                    msg: msg.msg,
+                },
+            ),
+            BrokerEvent::Undelivered(msg) => (
+                Some(msg.from),
+                NodeUpdate::Undelivered {
+                    to: msg.to,
+                    msg: msg.msg,
+                },
+            ),
+        };
+        self.subs.retain(|s| {
+            if to.is_some_and(|to| to != s.id) {
+                return true;
+            }
+            s.tx.send(update.clone()).is_ok()
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::simul::{node::NodeMsg, node_types::NodeSecret};
+
+    #[test]
+    fn test_dispatch() {
+        let mut subs = Subscribers::default();
+        let (id1, id2) = (NodeID::random(), NodeID::random());
+        let (tx1, mut rx1) = unbounded_channel();
+        let (tx2, mut rx2) = unbounded_channel();
+        subs.add(id1, tx1.clone());
+        subs.add(id2, tx2);
+        assert_eq!(2, subs.ids().len());
+
+        // Mana is only sent when it changes.
+        subs.mana(id1, 1.into());
+        subs.mana(id1, 1.into());
+        assert_eq!(Ok(NodeUpdate::Mana { mana: 1.into() }), rx1.try_recv());
+        assert!(rx1.try_recv().is_err());
+        assert!(rx2.try_recv().is_err());
+
+        // Joining nodes are broadcast, messages only go to the receiver.
+        let secret3 = NodeSecret::random();
+        let id3 = NodeID::from(secret3);
+        subs.event(BrokerEvent::NodeAdd(id3));
+        assert_eq!(Ok(NodeUpdate::NodeJoined { id: id3 }), rx1.try_recv());
+        assert_eq!(Ok(NodeUpdate::NodeJoined { id: id3 }), rx2.try_recv());
+        subs.event(BrokerEvent::NodeMsg(NodeMsg::new(&secret3, id2, Msg::Ping)));
+        assert!(rx1.try_recv().is_err());
+        assert_matches!(rx2.try_recv(), Ok(NodeUpdate::Message { from, .. }) if from == id3);
+        // Undelivered messages go back to the sender.
+        let mut msg = NodeMsg::new(&secret3, id3, Msg::Ping);
+        msg.from = id2;
+        subs.event(BrokerEvent::Undelivered(msg));
+        assert!(rx1.try_recv().is_err());
+        assert_matches!(rx2.try_recv(), Ok(NodeUpdate::Undelivered { to, .. }) if to == id3);
+
+        // Closed channels are removed.
+        drop(rx2);
+        subs.event(BrokerEvent::NodeDel(id3));
+        assert_eq!(vec![id1], subs.ids());
+        subs.remove(&tx1);
+        assert!(subs.ids().is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::simul::node_types::{Mana, NodeID, NodeSecret, Signature};
+
+/// A transfer of mana, signed by the sender.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone)]
+pub struct TransferRequest {
+    pub from: NodeID,
+    pub to: NodeID,
+    pub amount: Mana,
+    /// Must be equal to the `nonce` of the `TransferHistory` of the sender.
+    pub nonce: u64,
+    /// Signature over `TransferRequest::message` with the key of the sender.
+    pub signature: Signature,
+}
+
+impl TransferRequest {
+    /// Creates a new transfer signed by the secret.
+    pub fn new(secret: &NodeSecret, to: NodeID, amount: Mana, nonce: u64) -> Self {
+        let from = (*secret).into();
+        Self {
+            from,
+            to,
+            amount,
+            nonce,
+            signature: secret.sign(&Self::message(&from, &to, &amount, nonce)),
+        }
+    }
+
+    /// Returns the bytes to be signed for a transfer.
+    pub fn message(from: &NodeID, to: &NodeID, amount: &Mana, nonce: u64) -> Vec<u8> {
+        let mut msg = b"cybernode-transfer".to_vec();
+        msg.extend_from_slice(&from.to_bytes());
+        msg.extend_from_slice(&to.to_bytes());
+        msg.extend_from_slice(&amount.to_bytes());
+        msg.extend_from_slice(&nonce.to_be_bytes());
+        msg
+    }
+}
+
+/// A transfer which has been accepted by Trusted.
+#[derive(ToSchema, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransferRecord {
+    /// Time of the last tick before the transfer.
+    pub time: u128,
+    pub from: NodeID,
+    pub to: NodeID,
+    pub amount: Mana,
+    pub nonce: u64,
+}
+
+/// All transfers from and to a node.
+#[derive(ToSchema, Serialize, Debug, Clone)]
+pub struct TransferHistory {
+    /// The nonce to be used for the next transfer of this node.
+    pub nonce: u64,
+    pub transfers: Vec<TransferRecord>,
+}
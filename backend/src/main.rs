@@ -4,105 +4,478 @@
 // as the 'Broker' can only do one request at a time.
 
 use std::{
+    env,
     error::Error,
-    sync::mpsc::{channel, Sender},
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Mutex,
+    },
     thread,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use actix_cors::Cors;
 use actix_web::{
-    error, get,
+    delete, error, get,
     http::{header::ContentType, StatusCode},
-    middleware, web, App, HttpResponse, HttpServer, Result,
+    middleware, post, put, web, App, HttpRequest, HttpResponse, HttpServer, Result,
 };
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
 use backend::{
-    api::stats::StatsReply,
+    api::{
+        auth::{HttpSignature, UsedSignatures, KEY_HEADER, SIGNATURE_HEADER, TIME_HEADER},
+        blocklist::Blocklist,
+        error::{BlockedReply, ErrorReply},
+        faults::{ActiveFault, Fault, FaultRequest, MsgFilter},
+        groups::{GroupError, GroupMembers},
+        names::{NameError, NameRecord, NameRequest, NameResolution, NameTransferRequest},
+        reputation::{
+            Category, CategoryScore, ContentCheck, ContentPolicy, GroupReputation, ReputationVote,
+        },
+        stats::{NetworkStatus, NodeSummary, StatsReply},
+        storage::{
+            DeletableContent, DomainFiles, DomainReplicas, FileInfo, ReplicationReport,
+            StorageUsage,
+        },
+        updates::{NodeUpdate, Subscribers},
+        wallet::{TransferHistory, TransferRecord, TransferRequest},
+    },
     simul::{
         broker::Broker,
-        node::NodeInfo,
-        node_types::{Mana, NodeSecret},
+        ledger::{Block, LedgerStatus, Transition},
+        node::{NodeInfo, Receipt, ReceiptRequest, StorageError, STORAGE_QUOTA},
+        node_types::{GroupID, Hash256, Mana, NodeID, NodePublicKey, NodeSecret, Signature},
+        page::{Page, PageFile},
+        simulator, trusted,
+        web::ServedPage,
     },
 };
 use derive_more::Display;
-use tracing::error;
+use ring::constant_time;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{debug, error, warn};
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+    Modify, OpenApi,
+};
 
-#[get("/v1/stats")]
-async fn greet() -> HttpResponse {
-    HttpResponse::Ok().json(StatsReply { ids: vec![] })
+/// Configuration of the server.
+#[derive(Debug, Clone)]
+struct Config {
+    /// Real time between two calls to `Broker::tick`.
+    tick_interval: Duration,
+    /// Where Trusted stores its state. If None, it is lost on restart.
+    data_dir: Option<PathBuf>,
+    /// The pages hosted by the simulated nodes, one directory per domain.
+    pages_dir: Option<PathBuf>,
+    /// The token in the `ADMIN_HEADER` which gives access to the admin
+    /// endpoints. If None, they are disabled.
+    admin_token: Option<String>,
+    /// The file with the blocklist of pages and names, see `Blocklist`.
+    blocklist_file: Option<PathBuf>,
+    /// The origins of the frontend, which may call the API from the browser.
+    cors_origins: Vec<String>,
+    /// Also accepts the secret of the node in the `SECRET_HEADER` and on
+    /// the websocket, instead of only a `HttpSignature`.
+    allow_secret: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tick_interval: Duration::from_secs(1),
+            data_dir: None,
+            pages_dir: None,
+            admin_token: None,
+            blocklist_file: None,
+            cors_origins: DEFAULT_CORS_ORIGINS.iter().map(|o| o.to_string()).collect(),
+            allow_secret: false,
+        }
+    }
+}
+
+impl Config {
+    /// Returns the default configuration, overwritten by the following
+    /// environment variables, if present:
+    /// - CYBERNODE_TICK_MS - milliseconds between two ticks
+    /// - CYBERNODE_DATA_DIR - directory to store the state of Trusted
+    /// - CYBERNODE_PAGES_DIR - directory with the pages hosted by the nodes,
+    ///   by default the static pages of the frontend, if they are found
+    /// - CYBERNODE_ADMIN_TOKEN - enables the admin endpoints for this token
+    /// - CYBERNODE_BLOCKLIST - file with the blocked pages and names
+    /// - CYBERNODE_CORS_ORIGINS - comma-separated origins of the frontend
+    /// - CYBERNODE_ALLOW_SECRET - if "true", nodes may send their secret
+    fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(tick) = env::var("CYBERNODE_TICK_MS") {
+            match tick.parse::<u64>() {
+                Ok(ms) if ms > 0 => config.tick_interval = Duration::from_millis(ms),
+                _ => warn!("Ignoring invalid CYBERNODE_TICK_MS={tick}"),
+            }
+        }
+        if let Ok(dir) = env::var("CYBERNODE_DATA_DIR") {
+            config.data_dir = Some(dir.into());
+        }
+        if let Ok(token) = env::var("CYBERNODE_ADMIN_TOKEN") {
+            config.admin_token = Some(token);
+        }
+        if let Ok(file) = env::var("CYBERNODE_BLOCKLIST") {
+            config.blocklist_file = Some(file.into());
+        }
+        if let Ok(origins) = env::var("CYBERNODE_CORS_ORIGINS") {
+            config.cors_origins = origins
+                .split(',')
+                .map(|o| o.trim().to_string())
+                .filter(|o| !o.is_empty())
+                .collect();
+        }
+        if let Ok(allow) = env::var("CYBERNODE_ALLOW_SECRET") {
+            match allow.parse() {
+                Ok(allow) => config.allow_secret = allow,
+                _ => warn!("Ignoring invalid CYBERNODE_ALLOW_SECRET={allow}"),
+            }
+        }
+        match env::var("CYBERNODE_PAGES_DIR") {
+            Ok(dir) => config.pages_dir = Some(dir.into()),
+            Err(_) => {
+                let dir = PathBuf::from(DEFAULT_PAGES_DIR);
+                config.pages_dir = dir.is_dir().then_some(dir);
+            }
+        }
+        config
+    }
 }
 
 struct Main {
     tx: Sender<FromWeb>,
+    admin_token: Option<String>,
+    blocklist_file: Option<PathBuf>,
+    allow_secret: bool,
+    used_signatures: Mutex<UsedSignatures>,
 }
 
 impl Main {
-    fn new() -> Self {
-        Self { tx: Self::listen() }
+    fn new(config: Config) -> io::Result<Self> {
+        Ok(Self {
+            admin_token: config.admin_token.clone(),
+            blocklist_file: config.blocklist_file.clone(),
+            allow_secret: config.allow_secret,
+            used_signatures: Mutex::default(),
+            tx: Self::listen(config)?,
+        })
     }
 
-    fn listen() -> Sender<FromWeb> {
+    /// Starts the thread holding the only `Broker`.
+    /// Requests from the web are handled as they come in, and in between
+    /// the broker is ticked every `config.tick_interval`.
+    /// Returns an error if the broker cannot be started, e.g., because its
+    /// stored state cannot be read.
+    fn listen(config: Config) -> io::Result<Sender<FromWeb>> {
         let (tx, rx) = channel::<FromWeb>();
+        let (started_tx, started_rx) = channel::<Result<(), String>>();
 
-        thread::spawn(move || loop {
-            let broker = Broker::default(Self::_now()).expect("Couldn't start broker");
-            if let Ok(msg) = rx.recv() {
-                if let Err(e) = Main::handle_msg(broker, msg.clone()) {
-                    error!("While treating {msg:?}: {e:?}");
+        thread::spawn(move || {
+            let trusted_config = trusted::Config {
+                data_dir: config.data_dir.clone(),
+                ..trusted::Config::default()
+            };
+            let simulator_config = simulator::Config {
+                pages_dir: config.pages_dir.clone(),
+                ..simulator::Config::default()
+            };
+            let mut broker = match Broker::new(trusted_config, simulator_config, Self::now()) {
+                Ok(broker) => broker,
+                Err(e) => {
+                    let _ = started_tx.send(Err(e.to_string()));
+                    return;
+                }
+            };
+            let _ = started_tx.send(Ok(()));
+            if let Some(file) = &config.blocklist_file {
+                match Self::load_blocklist(file) {
+                    Ok(blocklist) => broker.set_blocklist(blocklist),
+                    Err(e) => warn!("Ignoring blocklist {file:?}: {e}"),
+                }
+            }
+            let mut subs = Subscribers::default();
+            let mut next_tick = Instant::now() + config.tick_interval;
+            loop {
+                match rx.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+                    Ok(msg) => {
+                        if let Err(e) = Main::handle_msg(&mut broker, &mut subs, msg.clone()) {
+                            error!("While treating {msg:?}: {e:?}");
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                if Instant::now() >= next_tick {
+                    broker.tick(Self::now());
+                    for id in subs.ids() {
+                        if let Ok(ni) = broker.get_node_info(id) {
+                            subs.mana(id, ni.mana);
+                        }
+                    }
+                    next_tick = Instant::now() + config.tick_interval;
+                }
+                for event in broker.events() {
+                    subs.event(event);
                 }
-            } else {
-                return;
             }
         });
 
-        tx
+        started_rx
+            .recv()
+            .map_err(io::Error::other)?
+            .map_err(|e| io::Error::other(format!("Couldn't start broker: {e}")))?;
+        Ok(tx)
     }
 
-    fn handle_msg(mut broker: Broker, msg: FromWeb) -> Result<(), Box<dyn Error>> {
-        Ok(match msg {
-            FromWeb::Register(tx, secret) => {
-                let id = broker.register(secret);
-                let ni = broker.get_node_info(id).unwrap();
+    fn handle_msg(
+        broker: &mut Broker,
+        subs: &mut Subscribers,
+        msg: FromWeb,
+    ) -> Result<(), Box<dyn Error>> {
+        match msg {
+            FromWeb::Register(tx, caller) => {
+                let id = match caller {
+                    Caller::Secret(secret) => broker.register(secret),
+                    Caller::Signed(public_key) => broker.register_public_key(public_key),
+                };
+                let ni = broker.get_node_info(id)?;
                 tx.send(ni)?
             }
             FromWeb::Alive(tx, id) => {
-                let mana = broker.alive(id.into())?;
+                let mana = broker.alive(id).ok();
+                if let Some(mana) = mana {
+                    subs.mana(id, mana);
+                }
                 tx.send(mana)?
             }
-        })
+            FromWeb::Heartbeat(id) => subs.mana(id, broker.alive(id)?),
+            FromWeb::Subscribe(tx, id, updates_tx) => {
+                let ni = broker.get_node_info(id).ok();
+                if let Some(ni) = &ni {
+                    subs.add(id, updates_tx);
+                    subs.mana(id, ni.mana);
+                }
+                tx.send(ni)?
+            }
+            FromWeb::Unsubscribe(updates_tx) => subs.remove(&updates_tx),
+            FromWeb::NetworkStatus(tx) => tx.send(broker.network_status()?)?,
+            FromWeb::LedgerStatus(tx) => tx.send(broker.ledger_status()?)?,
+            FromWeb::Block(tx, height) => tx.send(broker.get_block(height)?)?,
+            FromWeb::Transfer(tx, req) => {
+                tx.send(broker.transfer(req).map_err(|e| e.to_string()))?
+            }
+            FromWeb::Transfers(tx, id) => tx.send(broker.transfers(id)?)?,
+            FromWeb::Page(tx, viewer, domain, path) => {
+                broker.request_page(viewer, &domain, &path, tx)
+            }
+            FromWeb::Receipt(tx, receipt) => {
+                tx.send(broker.submit_receipt(receipt).map_err(|e| e.to_string()))?
+            }
+            FromWeb::StoreFile(tx, owner, file) => tx.send(broker.store_file(owner, file))?,
+            FromWeb::DeleteFile(tx, owner, domain, path) => {
+                tx.send(broker.delete_file(owner, &domain, &path))?
+            }
+            FromWeb::ListFiles(tx, owner, domain) => tx.send(broker.list_files(owner, &domain))?,
+            FromWeb::Replication(tx) => tx.send(broker.replication())?,
+            FromWeb::Deletable(tx) => tx.send(broker.deletable()?)?,
+            FromWeb::Faults(tx) => tx.send(broker.faults())?,
+            FromWeb::AddFault(tx, req) => tx.send(broker.add_fault(req))?,
+            FromWeb::HealFault(tx, id) => tx.send(broker.heal_fault(id))?,
+            FromWeb::HealFaults(tx) => tx.send(broker.heal_faults())?,
+            FromWeb::GroupJoin(tx, id, group) => tx.send(broker.group_join(id, group)?)?,
+            FromWeb::GroupLeave(tx, id, group) => tx.send(broker.group_leave(id, group)?)?,
+            FromWeb::GroupMembers(tx, group) => tx.send(broker.group_members(group)?)?,
+            FromWeb::RegisterName(tx, owner, name, group) => {
+                tx.send(broker.register_name(owner, &name, group)?)?
+            }
+            FromWeb::TransferName(tx, from, name, to) => {
+                tx.send(broker.transfer_name(from, &name, to)?)?
+            }
+            FromWeb::Resolve(tx, name) => tx.send(broker.resolve(&name)?)?,
+            FromWeb::Vote(tx, vote) => tx.send(broker.vote(vote).map_err(|e| e.to_string()))?,
+            FromWeb::Reputation(tx, group) => tx.send(broker.reputation(group)?)?,
+            FromWeb::SetPreferences(tx, id, policy) => {
+                tx.send(broker.set_preferences(id, policy)?)?
+            }
+            FromWeb::Preferences(tx, id) => tx.send(broker.preferences(id)?)?,
+            FromWeb::ContentCheck(tx, viewer, domain) => {
+                tx.send(broker.content_check(viewer, &domain)?)?
+            }
+            FromWeb::BlockedContent(tx, hash) => tx.send(broker.blocked_content(&hash))?,
+            FromWeb::Blocklist(tx) => tx.send(broker.blocklist().clone())?,
+            FromWeb::SetBlocklist(tx, blocklist) => {
+                broker.set_blocklist(blocklist);
+                tx.send(broker.blocklist().clone())?
+            }
+        }
+        Ok(())
     }
 
     fn config(config: &mut web::ServiceConfig) {
-        config.service(
-            web::scope("")
-                .app_data(web::Data::new(Main::new()))
-                .service(web::resource("/v1/register").route(web::get().to(Self::register)))
-                .service(web::resource("/v1/alive").route(web::get().to(Self::alive))),
-        );
+        config.service(api_services(
+            web::scope("").app_data(web::PayloadConfig::new(STORAGE_QUOTA as usize)),
+        ));
+    }
+
+    /// Allows the frontend at the given origins to call the API, including
+    /// the headers for the authentication of the nodes.
+    fn cors(origins: &[String]) -> Cors {
+        origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allowed_methods(["GET", "POST", "PUT", "DELETE"])
+            .allow_any_header()
+            .expose_headers([CONTENT_WARNING_HEADER, RECEIPTS_HEADER])
+            .max_age(3_600)
     }
 
-    async fn alive(state: web::Data<Main>) -> Result<HttpResponse> {
+    /// Sends a request to the broker thread and waits for the reply.
+    /// The wait runs on the blocking thread pool, so that slow requests like
+    /// pages don't keep the worker from serving other requests.
+    async fn request<T: Send + 'static>(
+        &self,
+        msg: impl FnOnce(Sender<T>) -> FromWeb,
+    ) -> Result<T, UserError> {
         let (tx, rx) = channel();
-        // TODO: get secret here
-        state
-            .tx
-            .send(FromWeb::Alive(tx, NodeSecret::zero()))
+        self.tx
+            .send(msg(tx))
             .map_err(|_| UserError::InternalError)?;
-        let ni = rx.recv().map_err(|_| UserError::InternalError)?;
-        Ok(HttpResponse::Ok().json(ni))
+        web::block(move || rx.recv())
+            .await
+            .map_err(|_| UserError::InternalError)?
+            .map_err(|_| UserError::InternalError)
     }
 
-    async fn register(state: web::Data<Main>) -> Result<HttpResponse> {
-        let (tx, rx) = channel();
-        // TODO: get secret here
-        state
-            .tx
-            .send(FromWeb::Register(tx, NodeSecret::zero()))
+    /// Reads and parses the blocklist file.
+    fn load_blocklist(file: &Path) -> Result<Blocklist, String> {
+        let text = fs::read_to_string(file).map_err(|e| e.to_string())?;
+        Blocklist::parse(&text)
+    }
+
+    /// Checks that the request has the admin token.
+    fn admin(&self, req: &HttpRequest) -> Result<(), UserError> {
+        // The comparison takes the same time for all wrong tokens of the
+        // same length, so the token cannot be guessed byte by byte.
+        let token = req.headers().get(ADMIN_HEADER).map(|t| t.as_bytes());
+        match (&self.admin_token, token) {
+            (Some(admin), Some(token))
+                if constant_time::verify_slices_are_equal(admin.as_bytes(), token).is_ok() =>
+            {
+                Ok(())
+            }
+            _ => Err(UserError::NotAdmin),
+        }
+    }
+
+    /// Authenticates the calling node by the signature headers over the
+    /// request with the given body, or by its secret if `allow_secret` is set.
+    /// Handlers without a body pass an empty one.
+    fn caller(&self, req: &HttpRequest, body: &[u8]) -> Result<Caller, UserError> {
+        if req.headers().contains_key(SECRET_HEADER) {
+            if !self.allow_secret {
+                return Err(UserError::SecretDisabled);
+            }
+            return Main::secret(req).map(Caller::Secret);
+        }
+        if !req.headers().contains_key(KEY_HEADER) {
+            return Err(UserError::MissingSignature);
+        }
+        let sig = HttpSignature {
+            key: Main::header(req, KEY_HEADER)?,
+            time: Main::header(req, TIME_HEADER)?,
+            signature: Main::header(req, SIGNATURE_HEADER)?,
+        };
+        let path = req
+            .uri()
+            .path_and_query()
+            .map_or(req.path(), |pq| pq.as_str());
+        sig.verify(req.method().as_str(), path, body, Main::now())
+            .ok_or(UserError::InvalidSignature)?;
+        self.check_replay(&sig)?;
+        Ok(Caller::Signed(sig.key))
+    }
+
+    // Refuses a signature which has already been used.
+    fn check_replay(&self, sig: &HttpSignature) -> Result<(), UserError> {
+        let mut used = self
+            .used_signatures
+            .lock()
             .map_err(|_| UserError::InternalError)?;
-        let ni = rx.recv().map_err(|_| UserError::InternalError)?;
-        Ok(HttpResponse::Ok().json(ni))
+        used.insert(sig, Main::now())
+            .then_some(())
+            .ok_or(UserError::InvalidSignature)
+    }
+
+    // Parses one of the signature headers.
+    fn header<T: FromStr>(req: &HttpRequest, name: &str) -> Result<T, UserError> {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .ok_or(UserError::InvalidSignature)
+    }
+
+    /// Returns the calling node if the request is authenticated, or None
+    /// for anonymous requests.
+    fn viewer(&self, req: &HttpRequest) -> Result<Option<NodeID>, UserError> {
+        if !req.headers().contains_key(SECRET_HEADER) && !req.headers().contains_key(KEY_HEADER) {
+            return Ok(None);
+        }
+        Ok(Some(self.caller(req, &[])?.id()))
+    }
+
+    /// Parses the JSON body of a request, which is read as bytes so that its
+    /// signature can be checked.
+    fn json<T: DeserializeOwned>(body: &[u8]) -> Result<T, UserError> {
+        serde_json::from_slice(body).map_err(|e| UserError::InvalidBody(e.to_string()))
+    }
+
+    /// Parses the group ID of a path.
+    fn group(group: &str) -> Result<GroupID, UserError> {
+        group.parse().map_err(UserError::InvalidGroup)
+    }
+
+    /// Extracts the secret of the calling node from the `SECRET_HEADER`.
+    fn secret(req: &HttpRequest) -> Result<NodeSecret, UserError> {
+        let header = req
+            .headers()
+            .get(SECRET_HEADER)
+            .ok_or(UserError::MissingSignature)?;
+        header
+            .to_str()
+            .map_err(|_| UserError::InvalidSecret)?
+            .parse()
+            .map_err(|_| UserError::InvalidSecret)
     }
 
-    fn _now() -> u128 {
+    /// Authenticates the first message of the websocket, which is the JSON
+    /// of a `HttpSignature` of "GET /v1/updates", or the secret if
+    /// `allow_secret` is set.
+    fn ws_caller(&self, text: &str) -> Result<NodeID, UserError> {
+        if let Ok(secret) = text.parse::<NodeSecret>() {
+            return match self.allow_secret {
+                true => Ok(secret.into()),
+                false => Err(UserError::SecretDisabled),
+            };
+        }
+        let sig =
+            serde_json::from_str::<HttpSignature>(text).map_err(|_| UserError::InvalidSignature)?;
+        let id = sig
+            .verify("GET", "/v1/updates", &[], Main::now())
+            .ok_or(UserError::InvalidSignature)?;
+        self.check_replay(&sig)?;
+        Ok(id)
+    }
+
+    fn now() -> u128 {
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -110,10 +483,1065 @@ impl Main {
     }
 }
 
+/// Registers the calling node, or returns the existing node if it is
+/// already registered.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "The node is registered", body = NodeInfo),
+        (status = 400, description = "Malformed secret", body = ErrorReply),
+        (status = 401, description = "Missing signature", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[get("/v1/register")]
+async fn register(req: HttpRequest, state: web::Data<Main>) -> Result<HttpResponse> {
+    let caller = state.caller(&req, &[])?;
+    let ni = state.request(|tx| FromWeb::Register(tx, caller)).await?;
+    Ok(HttpResponse::Ok().json(ni))
+}
+
+/// Keeps the calling node active and returns its current mana.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Current mana of the node", body = Mana),
+        (status = 400, description = "Malformed secret", body = ErrorReply),
+        (status = 401, description = "Missing signature or unknown node", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[get("/v1/alive")]
+async fn alive(req: HttpRequest, state: web::Data<Main>) -> Result<HttpResponse> {
+    let id = state.caller(&req, &[])?.id();
+    let mana = state
+        .request(|tx| FromWeb::Alive(tx, id))
+        .await?
+        .ok_or(UserError::UnknownNode)?;
+    Ok(HttpResponse::Ok().json(mana))
+}
+
+/// Returns the IDs of all registered nodes.
+#[utoipa::path(
+    responses((status = 200, description = "All registered nodes", body = StatsReply))
+)]
+#[get("/v1/stats")]
+async fn stats(state: web::Data<Main>) -> Result<HttpResponse> {
+    let ns = state.request(FromWeb::NetworkStatus).await?;
+    Ok(HttpResponse::Ok().json(StatsReply {
+        ids: ns.nodes.iter().map(|n| n.id).collect(),
+    }))
+}
+
+/// Returns statistics over all registered nodes.
+#[utoipa::path(
+    responses((status = 200, description = "Status of the network", body = NetworkStatus))
+)]
+#[get("/v1/network")]
+async fn network(state: web::Data<Main>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(state.request(FromWeb::NetworkStatus).await?))
+}
+
+/// Returns the height of the ledger of Trusted, and verifies its chain
+/// of blocks.
+#[utoipa::path(
+    responses((status = 200, description = "Status of the ledger", body = LedgerStatus))
+)]
+#[get("/v1/ledger")]
+async fn ledger(state: web::Data<Main>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(state.request(FromWeb::LedgerStatus).await?))
+}
+
+/// Returns the block of the ledger at the given height.
+#[utoipa::path(
+    params(("height" = u64, Path, description = "Height of the block, starting at 0")),
+    responses(
+        (status = 200, description = "The block", body = Block),
+        (status = 404, description = "No block at this height", body = ErrorReply),
+    )
+)]
+#[get("/v1/ledger/{height}")]
+async fn block(height: web::Path<u64>, state: web::Data<Main>) -> Result<HttpResponse> {
+    let height = height.into_inner();
+    let block = state
+        .request(|tx| FromWeb::Block(tx, height))
+        .await?
+        .ok_or(UserError::UnknownBlock)?;
+    Ok(HttpResponse::Ok().json(block))
+}
+
+/// Transfers mana to another node.
+/// The transfer must be signed by the sender, so the secret is not needed.
+#[utoipa::path(
+    request_body = TransferRequest,
+    responses(
+        (status = 200, description = "Remaining mana of the sender", body = Mana),
+        (status = 400, description = "The transfer has been refused", body = ErrorReply),
+    )
+)]
+#[post("/v1/transfer")]
+async fn transfer(req: web::Json<TransferRequest>, state: web::Data<Main>) -> Result<HttpResponse> {
+    let req = req.into_inner();
+    let mana = state
+        .request(|tx| FromWeb::Transfer(tx, req))
+        .await?
+        .map_err(UserError::TransferFailed)?;
+    Ok(HttpResponse::Ok().json(mana))
+}
+
+/// Rewards a node for the bytes of a page it served, with a receipt signed
+/// by the viewer for a `ReceiptRequest` of the `RECEIPTS_HEADER`.
+#[utoipa::path(
+    request_body = Receipt,
+    responses(
+        (status = 200, description = "Mana of the server", body = Mana),
+        (status = 400, description = "The receipt has been refused", body = ErrorReply),
+    )
+)]
+#[post("/v1/receipt")]
+async fn submit_receipt(
+    receipt: web::Json<Receipt>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let receipt = receipt.into_inner();
+    let mana = state
+        .request(|tx| FromWeb::Receipt(tx, receipt))
+        .await?
+        .map_err(UserError::ReceiptFailed)?;
+    Ok(HttpResponse::Ok().json(mana))
+}
+
+/// Returns the transfers of the calling node, and the nonce for its next
+/// transfer.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Transfers of the node", body = TransferHistory),
+        (status = 400, description = "Malformed secret", body = ErrorReply),
+        (status = 401, description = "Missing signature or unknown node", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[get("/v1/transfers")]
+async fn transfers(req: HttpRequest, state: web::Data<Main>) -> Result<HttpResponse> {
+    let id = state.caller(&req, &[])?.id();
+    let history = state
+        .request(|tx| FromWeb::Transfers(tx, id))
+        .await?
+        .ok_or(UserError::UnknownNode)?;
+    Ok(HttpResponse::Ok().json(history))
+}
+
+/// Returns a page hosted by the nodes, fetched through the network.
+/// An empty path, or one ending in '/', returns the 'index.html'.
+/// Pages on the blocklist of the operator, by their domain, the hash of
+/// their content, or the categories their group is flagged for, are blocked.
+/// Else, if the domain is a name whose group is flagged by the reputation
+/// network, the content policy of the calling node, or the default policy if
+/// the caller is anonymous, decides whether the page is refused or served
+/// with the categories in the `CONTENT_WARNING_HEADER`.
+/// A signed caller gets the bytes served by the nodes in the
+/// `RECEIPTS_HEADER`, to sign and send to /v1/receipt.
+#[utoipa::path(
+    params(
+        ("domain" = String, Path, description = "Domain of the page"),
+        ("path" = String, Path, description = "Path of the page inside the domain"),
+    ),
+    responses(
+        (status = 200, description = "The page with its content type", content_type = "application/octet-stream"),
+        (status = 403, description = "The content policy refuses the page", body = ErrorReply),
+        (status = 404, description = "No node has this page", body = ErrorReply),
+        (status = 451, description = "The operator blocks the page", body = BlockedReply),
+    ),
+    security((), ("node_secret" = []), ("node_signature" = []))
+)]
+#[get("/v1/page/{domain}/{path:.*}")]
+async fn page(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let viewer = state.viewer(&req)?;
+    let (domain, path) = path.into_inner();
+    let check_domain = domain.clone();
+    let warnings = match state
+        .request(|tx| FromWeb::ContentCheck(tx, viewer, check_domain))
+        .await?
+    {
+        ContentCheck::Serve => vec![],
+        ContentCheck::Blocked(reason) => return Err(UserError::Blocked(reason).into()),
+        ContentCheck::Warn(categories) => categories,
+        ContentCheck::Refuse(categories) => {
+            return Err(UserError::Refused(category_names(&categories)).into())
+        }
+    };
+    let ServedPage { page, receipts } = state
+        .request(|tx| FromWeb::Page(tx, viewer, domain, path))
+        .await?
+        .ok_or(UserError::UnknownPage)?;
+    let hash = Hash256::digest(&page.data);
+    if let Some(reason) = state
+        .request(|tx| FromWeb::BlockedContent(tx, hash))
+        .await?
+    {
+        return Err(UserError::Blocked(reason).into());
+    }
+    let mut resp = HttpResponse::Ok();
+    if !warnings.is_empty() {
+        resp.insert_header((CONTENT_WARNING_HEADER, category_names(&warnings)));
+    }
+    if !receipts.is_empty() {
+        let receipts = serde_json::to_string(&receipts).map_err(|_| UserError::InternalError)?;
+        resp.insert_header((RECEIPTS_HEADER, receipts));
+    }
+    Ok(resp.content_type(page.content_type).body(page.data))
+}
+
+// Returns the names of the categories, separated by commas.
+fn category_names(categories: &[Category]) -> String {
+    let names: Vec<&str> = categories.iter().map(|c| c.name()).collect();
+    names.join(", ")
+}
+
+/// Stores a file in the storage of the calling node, overwriting an existing
+/// file with the same path. The content type is guessed from the extension.
+/// The first file of a domain reserves the domain for the calling node.
+#[utoipa::path(
+    params(
+        ("domain" = String, Path, description = "Domain of the file"),
+        ("path" = String, Path, description = "Path of the file inside the domain"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "The file is stored", body = StorageUsage),
+        (status = 401, description = "Missing signature or node not online", body = ErrorReply),
+        (status = 403, description = "The domain belongs to another node", body = ErrorReply),
+        (status = 507, description = "The storage quota is exceeded", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[put("/v1/storage/{domain}/{path:.*}")]
+async fn store_file(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    body: web::Bytes,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let owner = state.caller(&req, &body)?.id();
+    let (domain, path) = path.into_inner();
+    let file = PageFile {
+        page: Page::new(&path, body.to_vec()),
+        domain,
+        path,
+    };
+    let usage = state
+        .request(|tx| FromWeb::StoreFile(tx, owner, file))
+        .await?
+        .map_err(UserError::Storage)?;
+    Ok(HttpResponse::Ok().json(usage))
+}
+
+/// Deletes a file from the storage of the calling node.
+/// Deleting the last file of a domain releases the domain.
+#[utoipa::path(
+    params(
+        ("domain" = String, Path, description = "Domain of the file"),
+        ("path" = String, Path, description = "Path of the file inside the domain"),
+    ),
+    responses(
+        (status = 200, description = "The file is deleted", body = StorageUsage),
+        (status = 401, description = "Missing signature or node not online", body = ErrorReply),
+        (status = 403, description = "The domain belongs to another node", body = ErrorReply),
+        (status = 404, description = "There is no such file", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[delete("/v1/storage/{domain}/{path:.*}")]
+async fn delete_file(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let owner = state.caller(&req, &[])?.id();
+    let (domain, path) = path.into_inner();
+    let usage = state
+        .request(|tx| FromWeb::DeleteFile(tx, owner, domain, path))
+        .await?
+        .map_err(UserError::Storage)?;
+    Ok(HttpResponse::Ok().json(usage))
+}
+
+/// Lists the files of a domain in the storage of the calling node.
+#[utoipa::path(
+    params(("domain" = String, Path, description = "Domain of the files")),
+    responses(
+        (status = 200, description = "The files of the domain", body = DomainFiles),
+        (status = 401, description = "Missing signature or node not online", body = ErrorReply),
+        (status = 403, description = "The domain belongs to another node", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[get("/v1/storage/{domain}")]
+async fn list_files(
+    req: HttpRequest,
+    domain: web::Path<String>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let owner = state.caller(&req, &[])?.id();
+    let domain = domain.into_inner();
+    let files = state
+        .request(|tx| FromWeb::ListFiles(tx, owner, domain))
+        .await?
+        .map_err(UserError::Storage)?;
+    Ok(HttpResponse::Ok().json(files))
+}
+
+/// Returns the domains which are kept on fewer online nodes than the
+/// replication factor. Domains without holders are lost.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "The under-replicated domains", body = ReplicationReport),
+    )
+)]
+#[get("/v1/replication")]
+async fn replication(state: web::Data<Main>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(state.request(FromWeb::Replication).await?))
+}
+
+/// Returns the owners who cannot pay the rent for their content anymore.
+/// Their content is deleted from all nodes after `delete_after`, unless
+/// they get enough mana to pay the rent again.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "The content to be deleted", body = [DeletableContent]),
+    )
+)]
+#[get("/v1/deletable")]
+async fn deletable(state: web::Data<Main>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(state.request(FromWeb::Deletable).await?))
+}
+
+/// Returns the faults injected into the simulated network.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "The active faults", body = [ActiveFault]),
+        (status = 403, description = "Missing or wrong admin token", body = ErrorReply),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/v1/admin/faults")]
+async fn list_faults(req: HttpRequest, state: web::Data<Main>) -> Result<HttpResponse> {
+    state.admin(&req)?;
+    Ok(HttpResponse::Ok().json(state.request(FromWeb::Faults).await?))
+}
+
+/// Injects a fault into the simulated network: a partition, an isolated
+/// node, or dropped or duplicated messages.
+#[utoipa::path(
+    request_body = FaultRequest,
+    responses(
+        (status = 200, description = "The fault with its id", body = ActiveFault),
+        (status = 400, description = "Unknown type of message in the filter", body = ErrorReply),
+        (status = 403, description = "Missing or wrong admin token", body = ErrorReply),
+    ),
+    security(("admin_token" = []))
+)]
+#[post("/v1/admin/faults")]
+async fn add_fault(
+    req: HttpRequest,
+    fault: web::Json<FaultRequest>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    state.admin(&req)?;
+    let fault = fault.into_inner();
+    let fault = state
+        .request(|tx| FromWeb::AddFault(tx, fault))
+        .await?
+        .map_err(UserError::InvalidFault)?;
+    Ok(HttpResponse::Ok().json(fault))
+}
+
+/// Heals all faults of the simulated network.
+#[utoipa::path(
+    responses(
+        (status = 204, description = "All faults are healed"),
+        (status = 403, description = "Missing or wrong admin token", body = ErrorReply),
+    ),
+    security(("admin_token" = []))
+)]
+#[delete("/v1/admin/faults")]
+async fn heal_faults(req: HttpRequest, state: web::Data<Main>) -> Result<HttpResponse> {
+    state.admin(&req)?;
+    state.request(FromWeb::HealFaults).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Heals a fault of the simulated network.
+#[utoipa::path(
+    params(("id" = u64, Path, description = "Id of the fault")),
+    responses(
+        (status = 204, description = "The fault is healed"),
+        (status = 403, description = "Missing or wrong admin token", body = ErrorReply),
+        (status = 404, description = "There is no such fault", body = ErrorReply),
+    ),
+    security(("admin_token" = []))
+)]
+#[delete("/v1/admin/faults/{id}")]
+async fn heal_fault(
+    req: HttpRequest,
+    id: web::Path<u64>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    state.admin(&req)?;
+    let id = id.into_inner();
+    if !state.request(|tx| FromWeb::HealFault(tx, id)).await? {
+        return Err(UserError::UnknownFault.into());
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Returns the members of the group which are currently online.
+#[utoipa::path(
+    params(("group" = String, Path, description = "Hexadecimal ID of the group")),
+    responses(
+        (status = 200, description = "The online members", body = GroupMembers),
+        (status = 400, description = "Malformed group ID", body = ErrorReply),
+    )
+)]
+#[get("/v1/groups/{group}/members")]
+async fn group_members(group: web::Path<String>, state: web::Data<Main>) -> Result<HttpResponse> {
+    let group = Main::group(&group)?;
+    let members = state.request(|tx| FromWeb::GroupMembers(tx, group)).await?;
+    Ok(HttpResponse::Ok().json(GroupMembers { group, members }))
+}
+
+/// Adds the calling node to the group, and returns the online members.
+#[utoipa::path(
+    params(("group" = String, Path, description = "Hexadecimal ID of the group")),
+    responses(
+        (status = 200, description = "The online members", body = GroupMembers),
+        (status = 400, description = "Malformed secret or group ID", body = ErrorReply),
+        (status = 401, description = "Missing signature or unknown node", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[post("/v1/groups/{group}/members")]
+async fn group_join(
+    req: HttpRequest,
+    group: web::Path<String>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let id = state.caller(&req, &[])?.id();
+    let group = Main::group(&group)?;
+    state
+        .request(|tx| FromWeb::GroupJoin(tx, id, group))
+        .await?
+        .map_err(UserError::Group)?;
+    let members = state.request(|tx| FromWeb::GroupMembers(tx, group)).await?;
+    Ok(HttpResponse::Ok().json(GroupMembers { group, members }))
+}
+
+/// Removes the calling node from the group.
+#[utoipa::path(
+    params(("group" = String, Path, description = "Hexadecimal ID of the group")),
+    responses(
+        (status = 204, description = "The node left the group"),
+        (status = 400, description = "Malformed secret or group ID", body = ErrorReply),
+        (status = 401, description = "Missing signature", body = ErrorReply),
+        (status = 404, description = "The node is not a member of the group", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[delete("/v1/groups/{group}/members")]
+async fn group_leave(
+    req: HttpRequest,
+    group: web::Path<String>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let id = state.caller(&req, &[])?.id();
+    let group = Main::group(&group)?;
+    state
+        .request(|tx| FromWeb::GroupLeave(tx, id, group))
+        .await?
+        .map_err(UserError::Group)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Resolves a name to its group and the online members of the group, so
+/// that `cyno://name/` can be fetched from them.
+#[utoipa::path(
+    params(("name" = String, Path, description = "The registered name")),
+    responses(
+        (status = 200, description = "The name and the online members of its group", body = NameResolution),
+        (status = 400, description = "Malformed name", body = ErrorReply),
+        (status = 404, description = "The name is not registered or expired", body = ErrorReply),
+        (status = 451, description = "The operator blocks the name", body = BlockedReply),
+    )
+)]
+#[get("/v1/names/{name}")]
+async fn resolve(name: web::Path<String>, state: web::Data<Main>) -> Result<HttpResponse> {
+    let name = name.into_inner();
+    let resolution = state
+        .request(|tx| FromWeb::Resolve(tx, name))
+        .await?
+        .map_err(|e| match e {
+            NameError::Blocked(reason) => UserError::Blocked(reason),
+            e => UserError::Name(e),
+        })?;
+    Ok(HttpResponse::Ok().json(resolution))
+}
+
+/// Registers a name for a group, paid with the mana of the calling node.
+/// If the node already owns the name, it is renewed and points to the
+/// given group.
+#[utoipa::path(
+    params(("name" = String, Path, description = "The name to register")),
+    request_body = NameRequest,
+    responses(
+        (status = 200, description = "The registered name", body = NameRecord),
+        (status = 400, description = "Malformed secret or name", body = ErrorReply),
+        (status = 401, description = "Missing signature or unknown node", body = ErrorReply),
+        (status = 402, description = "Not enough mana", body = ErrorReply),
+        (status = 409, description = "The name belongs to another node", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[post("/v1/names/{name}")]
+async fn register_name(
+    req: HttpRequest,
+    name: web::Path<String>,
+    body: web::Bytes,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let owner = state.caller(&req, &body)?.id();
+    let group = Main::json::<NameRequest>(&body)?.group;
+    let name = name.into_inner();
+    let record = state
+        .request(|tx| FromWeb::RegisterName(tx, owner, name, group))
+        .await?
+        .map_err(UserError::Name)?;
+    Ok(HttpResponse::Ok().json(record))
+}
+
+/// Gives a name of the calling node to another node.
+#[utoipa::path(
+    params(("name" = String, Path, description = "The name to give away")),
+    request_body = NameTransferRequest,
+    responses(
+        (status = 200, description = "The name with its new owner", body = NameRecord),
+        (status = 400, description = "Malformed secret or name, or unknown new owner", body = ErrorReply),
+        (status = 401, description = "Missing signature", body = ErrorReply),
+        (status = 403, description = "The name belongs to another node", body = ErrorReply),
+        (status = 404, description = "The name is not registered or expired", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[put("/v1/names/{name}/owner")]
+async fn transfer_name(
+    req: HttpRequest,
+    name: web::Path<String>,
+    body: web::Bytes,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let from = state.caller(&req, &body)?.id();
+    let to = Main::json::<NameTransferRequest>(&body)?.to;
+    let name = name.into_inner();
+    let record = state
+        .request(|tx| FromWeb::TransferName(tx, from, name, to))
+        .await?
+        .map_err(UserError::Name)?;
+    Ok(HttpResponse::Ok().json(record))
+}
+
+/// Votes whether the content of a group belongs to a category.
+/// The vote must be signed by the voter, so the secret is not needed.
+#[utoipa::path(
+    request_body = ReputationVote,
+    responses(
+        (status = 200, description = "The reputation of the group including the vote", body = GroupReputation),
+        (status = 400, description = "The vote has been refused", body = ErrorReply),
+    )
+)]
+#[post("/v1/reputation/votes")]
+async fn submit_vote(
+    vote: web::Json<ReputationVote>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let vote = vote.into_inner();
+    let reputation = state
+        .request(|tx| FromWeb::Vote(tx, vote))
+        .await?
+        .map_err(UserError::VoteFailed)?;
+    Ok(HttpResponse::Ok().json(reputation))
+}
+
+/// Returns the votes on the content of a group, weighted by the mana of the
+/// voters, and the categories it is flagged for.
+#[utoipa::path(
+    params(("group" = String, Path, description = "Hexadecimal ID of the group")),
+    responses(
+        (status = 200, description = "The reputation of the group", body = GroupReputation),
+        (status = 400, description = "Malformed group ID", body = ErrorReply),
+    )
+)]
+#[get("/v1/reputation/{group}")]
+async fn group_reputation(
+    group: web::Path<String>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let group = Main::group(&group)?;
+    Ok(HttpResponse::Ok().json(state.request(|tx| FromWeb::Reputation(tx, group)).await?))
+}
+
+/// Returns what the calling node wants to happen with pages of flagged
+/// groups.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "The content policy of the node", body = ContentPolicy),
+        (status = 400, description = "Malformed secret", body = ErrorReply),
+        (status = 401, description = "Missing signature or unknown node", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[get("/v1/preferences")]
+async fn get_preferences(req: HttpRequest, state: web::Data<Main>) -> Result<HttpResponse> {
+    let id = state.caller(&req, &[])?.id();
+    let policy = state
+        .request(|tx| FromWeb::Preferences(tx, id))
+        .await?
+        .ok_or(UserError::UnknownNode)?;
+    Ok(HttpResponse::Ok().json(policy))
+}
+
+/// Sets what the calling node wants to happen with pages of flagged groups.
+#[utoipa::path(
+    request_body = ContentPolicy,
+    responses(
+        (status = 204, description = "The content policy is stored"),
+        (status = 400, description = "Malformed secret", body = ErrorReply),
+        (status = 401, description = "Missing signature or unknown node", body = ErrorReply),
+    ),
+    security(("node_secret" = []), ("node_signature" = []))
+)]
+#[put("/v1/preferences")]
+async fn set_preferences(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let id = state.caller(&req, &body)?.id();
+    let policy: ContentPolicy = Main::json(&body)?;
+    state
+        .request(|tx| FromWeb::SetPreferences(tx, id, policy))
+        .await?
+        .ok_or(UserError::UnknownNode)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Returns the blocklist of pages and names.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "The blocklist", body = Blocklist),
+        (status = 403, description = "Missing or wrong admin token", body = ErrorReply),
+    ),
+    security(("admin_token" = []))
+)]
+#[get("/v1/admin/blocklist")]
+async fn get_blocklist(req: HttpRequest, state: web::Data<Main>) -> Result<HttpResponse> {
+    state.admin(&req)?;
+    Ok(HttpResponse::Ok().json(state.request(FromWeb::Blocklist).await?))
+}
+
+/// Replaces the blocklist of pages and names.
+/// The blocklist file is not changed.
+#[utoipa::path(
+    request_body = Blocklist,
+    responses(
+        (status = 200, description = "The new blocklist", body = Blocklist),
+        (status = 403, description = "Missing or wrong admin token", body = ErrorReply),
+    ),
+    security(("admin_token" = []))
+)]
+#[put("/v1/admin/blocklist")]
+async fn set_blocklist(
+    req: HttpRequest,
+    blocklist: web::Json<Blocklist>,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    state.admin(&req)?;
+    let blocklist = blocklist.into_inner();
+    Ok(HttpResponse::Ok().json(
+        state
+            .request(|tx| FromWeb::SetBlocklist(tx, blocklist))
+            .await?,
+    ))
+}
+
+/// Reads the blocklist file again, and replaces the blocklist with it.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "The new blocklist", body = Blocklist),
+        (status = 400, description = "The file cannot be read or is invalid", body = ErrorReply),
+        (status = 403, description = "Missing or wrong admin token", body = ErrorReply),
+        (status = 404, description = "No blocklist file is configured", body = ErrorReply),
+    ),
+    security(("admin_token" = []))
+)]
+#[post("/v1/admin/blocklist/reload")]
+async fn reload_blocklist(req: HttpRequest, state: web::Data<Main>) -> Result<HttpResponse> {
+    state.admin(&req)?;
+    let file = state
+        .blocklist_file
+        .as_ref()
+        .ok_or(UserError::NoBlocklistFile)?;
+    let blocklist = Main::load_blocklist(file).map_err(UserError::InvalidBlocklist)?;
+    Ok(HttpResponse::Ok().json(
+        state
+            .request(|tx| FromWeb::SetBlocklist(tx, blocklist))
+            .await?,
+    ))
+}
+
+/// Opens a websocket to receive `NodeUpdate`s.
+/// The first text message must be the JSON of a `HttpSignature` for
+/// "GET /v1/updates" of a registered node, or its secret if the server
+/// allows it.
+/// Afterwards the node sends "alive" text messages, or pings, to stay
+/// active, instead of calling /v1/alive.
+#[utoipa::path(
+    responses(
+        (status = 101, description = "Switching to the websocket protocol", body = NodeUpdate),
+        (status = 400, description = "Not a websocket request"),
+    )
+)]
+#[get("/v1/updates")]
+async fn updates(
+    req: HttpRequest,
+    body: web::Payload,
+    state: web::Data<Main>,
+) -> Result<HttpResponse> {
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(updates_session(state, session, stream));
+    Ok(response)
+}
+
+async fn updates_session(state: web::Data<Main>, mut session: Session, mut stream: MessageStream) {
+    let id: NodeID = match stream.recv().await {
+        Some(Ok(Message::Text(text))) => match state.ws_caller(&text) {
+            Ok(id) => id,
+            Err(e) => return close_policy(session, e.to_string()).await,
+        },
+        _ => return close_policy(session, UserError::MissingSignature.to_string()).await,
+    };
+
+    let (updates_tx, mut updates_rx) = unbounded_channel::<NodeUpdate>();
+    let subscribe = updates_tx.clone();
+    match state
+        .request(|tx| FromWeb::Subscribe(tx, id, subscribe))
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return close_policy(session, UserError::UnknownNode.to_string()).await,
+        Err(e) => return close_policy(session, e.to_string()).await,
+    }
+
+    let mut forward = session.clone();
+    actix_web::rt::spawn(async move {
+        while let Some(update) = updates_rx.recv().await {
+            match serde_json::to_string(&update) {
+                Ok(text) => {
+                    if forward.text(text).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => error!("While serializing {update:?}: {e:?}"),
+            }
+        }
+    });
+
+    while let Some(Ok(msg)) = stream.recv().await {
+        match msg {
+            Message::Ping(bytes) => {
+                if session.pong(&bytes).await.is_err() {
+                    break;
+                }
+                let _ = state.tx.send(FromWeb::Heartbeat(id));
+            }
+            Message::Text(text) if text.trim() == "alive" => {
+                let _ = state.tx.send(FromWeb::Heartbeat(id));
+            }
+            Message::Close(_) => break,
+            msg => debug!("Ignoring websocket message {msg:?}"),
+        }
+    }
+
+    let _ = state.tx.send(FromWeb::Unsubscribe(updates_tx));
+    let _ = session.close(None).await;
+}
+
+async fn close_policy(session: Session, description: String) {
+    let _ = session
+        .close(Some(CloseReason {
+            code: CloseCode::Policy,
+            description: Some(description),
+        }))
+        .await;
+}
+
+/// Returns this OpenAPI document.
+#[utoipa::path(
+    responses((status = 200, description = "The OpenAPI document", content_type = "application/json"))
+)]
+#[get("/v1/openapi.json")]
+async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Declares every handler once, so that `Main::config` serves exactly the
+/// paths documented by `ApiDoc`.
+macro_rules! api {
+    ($($handler:ident),* $(,)?) => {
+        /// Adds all handlers of the API to the scope.
+        fn api_services(scope: actix_web::Scope) -> actix_web::Scope {
+            scope$(.service($handler))*
+        }
+
+        #[derive(OpenApi)]
+        #[openapi(
+            paths($($handler),*),
+            components(schemas(
+                NodeInfo,
+                NodeID,
+                Mana,
+                StatsReply,
+                NetworkStatus,
+                NodeSummary,
+                NodeUpdate,
+                LedgerStatus,
+                Block,
+                Transition,
+                Hash256,
+                HttpSignature,
+                NodePublicKey,
+                TransferRequest,
+                TransferRecord,
+                TransferHistory,
+                Receipt,
+                ReceiptRequest,
+                Signature,
+                StorageUsage,
+                DomainFiles,
+                FileInfo,
+                ReplicationReport,
+                DomainReplicas,
+                DeletableContent,
+                Fault,
+                MsgFilter,
+                FaultRequest,
+                ActiveFault,
+                GroupID,
+                GroupMembers,
+                NameRecord,
+                NameRequest,
+                NameTransferRequest,
+                NameResolution,
+                Category,
+                ReputationVote,
+                CategoryScore,
+                GroupReputation,
+                ContentPolicy,
+                Blocklist,
+                BlockedReply,
+                ErrorReply
+            )),
+            modifiers(&SecurityAddon)
+        )]
+        struct ApiDoc;
+    };
+}
+
+api!(
+    register,
+    alive,
+    stats,
+    network,
+    ledger,
+    block,
+    transfer,
+    transfers,
+    page,
+    submit_receipt,
+    store_file,
+    delete_file,
+    list_files,
+    replication,
+    deletable,
+    list_faults,
+    add_fault,
+    heal_faults,
+    heal_fault,
+    group_members,
+    group_join,
+    group_leave,
+    resolve,
+    register_name,
+    transfer_name,
+    submit_vote,
+    group_reputation,
+    get_preferences,
+    set_preferences,
+    get_blocklist,
+    set_blocklist,
+    reload_blocklist,
+    updates,
+    openapi_json,
+);
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "node_secret",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(SECRET_HEADER))),
+        );
+        components.add_security_scheme(
+            "node_signature",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                SIGNATURE_HEADER.to_string(),
+                format!(
+                    "Signature of the request and its body, sent together with the \
+                     '{KEY_HEADER}' and '{TIME_HEADER}' headers, see HttpSignature. \
+                     Every time can only be used once."
+                ),
+            ))),
+        );
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(ADMIN_HEADER))),
+        );
+    }
+}
+
+/// Where the static pages of the frontend are, when started in the backend
+/// directory.
+const DEFAULT_PAGES_DIR: &str = "../frontend/src/assets/staticPages";
+
+/// The origins of `ng serve` and of `serve.js` in the frontend.
+const DEFAULT_CORS_ORIGINS: [&str; 2] = ["http://localhost:4200", "http://localhost:8000"];
+
+/// HTTP header holding the hexadecimal secret of the calling node.
+/// Only accepted with `Config::allow_secret`, else the node signs its
+/// requests with the headers of `HttpSignature`.
+const SECRET_HEADER: &str = "X-Node-Secret";
+
+/// HTTP header holding the categories a page is flagged for, if the content
+/// policy of the viewer warns about them.
+const CONTENT_WARNING_HEADER: &str = "X-Content-Warning";
+
+/// HTTP header holding the JSON list of `ReceiptRequest`s for the bytes the
+/// nodes served to the signed caller of a page.
+const RECEIPTS_HEADER: &str = "X-Receipts";
+
+/// HTTP header holding the token of `Config::admin_token`.
+const ADMIN_HEADER: &str = "X-Admin-Token";
+
+/// How the calling node authenticated itself.
+#[derive(Debug, Clone)]
+enum Caller {
+    /// The node sent its secret, so the broker can simulate it.
+    Secret(NodeSecret),
+    /// The node signed the request, its secret stays with the node.
+    Signed(NodePublicKey),
+}
+
+impl Caller {
+    fn id(&self) -> NodeID {
+        match self {
+            Caller::Secret(secret) => (*secret).into(),
+            Caller::Signed(public_key) => (*public_key).into(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 enum FromWeb {
-    Register(Sender<NodeInfo>, NodeSecret),
-    Alive(Sender<Mana>, NodeSecret),
+    Register(Sender<NodeInfo>, Caller),
+    // Replies None if the node is not registered.
+    Alive(Sender<Option<Mana>>, NodeID),
+    NetworkStatus(Sender<NetworkStatus>),
+    LedgerStatus(Sender<LedgerStatus>),
+    // Replies None if there is no block at this height.
+    Block(Sender<Option<Block>>, u64),
+    // Replies the remaining mana of the sender, or why the transfer failed.
+    Transfer(Sender<Result<Mana, String>>, TransferRequest),
+    // Replies None if the node is not registered.
+    Transfers(Sender<Option<TransferHistory>>, NodeID),
+    // Replies None if no node has this page, for the domain and the path.
+    // The viewer, if any, pays the holder with a receipt.
+    Page(Sender<Option<ServedPage>>, Option<NodeID>, String, String),
+    // Replies the mana of the server, or why the receipt has been refused.
+    Receipt(Sender<Result<Mana, String>>, Receipt),
+    // Stores the file in the storage of the node.
+    StoreFile(Sender<Result<StorageUsage, StorageError>>, NodeID, PageFile),
+    // Deletes the file of the node, for the domain and the path.
+    DeleteFile(
+        Sender<Result<StorageUsage, StorageError>>,
+        NodeID,
+        String,
+        String,
+    ),
+    // Lists the files of the node in the domain.
+    ListFiles(Sender<Result<DomainFiles, StorageError>>, NodeID, String),
+    Replication(Sender<ReplicationReport>),
+    Deletable(Sender<Vec<DeletableContent>>),
+    Faults(Sender<Vec<ActiveFault>>),
+    AddFault(Sender<Result<ActiveFault, String>>, FaultRequest),
+    // Replies false if there is no fault with this id.
+    HealFault(Sender<bool>, u64),
+    HealFaults(Sender<()>),
+    // Replies GroupError::UnknownNode if the node is not registered.
+    GroupJoin(Sender<Result<(), GroupError>>, NodeID, GroupID),
+    // Replies GroupError::NotMember if the node is not a member of the group.
+    GroupLeave(Sender<Result<(), GroupError>>, NodeID, GroupID),
+    GroupMembers(Sender<Vec<NodeID>>, GroupID),
+    // Registers or renews the name of the node for the group.
+    RegisterName(
+        Sender<Result<NameRecord, NameError>>,
+        NodeID,
+        String,
+        GroupID,
+    ),
+    // Gives the name of the first node to the second node.
+    TransferName(
+        Sender<Result<NameRecord, NameError>>,
+        NodeID,
+        String,
+        NodeID,
+    ),
+    Resolve(Sender<Result<NameResolution, NameError>>, String),
+    // Replies the reputation of the group, or why the vote failed.
+    Vote(Sender<Result<GroupReputation, String>>, ReputationVote),
+    Reputation(Sender<GroupReputation>, GroupID),
+    // Replies None if the node is not registered.
+    SetPreferences(Sender<Option<ContentPolicy>>, NodeID, ContentPolicy),
+    // Replies None if the node is not registered.
+    Preferences(Sender<Option<ContentPolicy>>, NodeID),
+    // Checks the domain against the content policy of the viewer.
+    ContentCheck(Sender<ContentCheck>, Option<NodeID>, String),
+    // Replies why the content with this hash is blocked, or None.
+    BlockedContent(Sender<Option<String>>, Hash256),
+    Blocklist(Sender<Blocklist>),
+    // Replaces the blocklist, and replies the new one.
+    SetBlocklist(Sender<Blocklist>, Blocklist),
+    // Keeps a node alive without replying, the mana is sent to the subscribers.
+    Heartbeat(NodeID),
+    // Replies None if the node is not registered, else the updates are sent
+    // to the given channel.
+    Subscribe(
+        Sender<Option<NodeInfo>>,
+        NodeID,
+        UnboundedSender<NodeUpdate>,
+    ),
+    Unsubscribe(UnboundedSender<NodeUpdate>),
 }
 
 // enum ToWeb {}
@@ -122,30 +1550,800 @@ enum FromWeb {
 enum UserError {
     #[display(fmt = "An internal error occurred. Please try again later.")]
     InternalError,
+    #[display(
+        fmt = "Missing the '{}', '{}', and '{}' signature headers.",
+        KEY_HEADER,
+        TIME_HEADER,
+        SIGNATURE_HEADER
+    )]
+    MissingSignature,
+    #[display(fmt = "This server doesn't accept the node secret, sign the request instead.")]
+    SecretDisabled,
+    #[display(fmt = "The node secret must be 64 hexadecimal characters.")]
+    InvalidSecret,
+    #[display(fmt = "The signature headers are malformed, expired, or don't match the request.")]
+    InvalidSignature,
+    #[display(fmt = "This node is not registered.")]
+    UnknownNode,
+    #[display(fmt = "There is no block at this height.")]
+    UnknownBlock,
+    #[display(fmt = "No node has this page.")]
+    UnknownPage,
+    #[display(fmt = "The transfer failed: {}", _0)]
+    TransferFailed(#[error(not(source))] String),
+    #[display(fmt = "The receipt has been refused: {}", _0)]
+    ReceiptFailed(#[error(not(source))] String),
+    #[display(fmt = "{}", _0)]
+    Storage(#[error(not(source))] StorageError),
+    #[display(fmt = "Missing or wrong admin token in the '{}' header.", ADMIN_HEADER)]
+    NotAdmin,
+    #[display(fmt = "There is no such fault.")]
+    UnknownFault,
+    #[display(fmt = "Invalid fault: {}", _0)]
+    InvalidFault(#[error(not(source))] String),
+    #[display(fmt = "Invalid group: {}", _0)]
+    InvalidGroup(#[error(not(source))] String),
+    #[display(fmt = "{}", _0)]
+    Group(#[error(not(source))] GroupError),
+    #[display(fmt = "{}", _0)]
+    Name(#[error(not(source))] NameError),
+    #[display(fmt = "The vote failed: {}", _0)]
+    VoteFailed(#[error(not(source))] String),
+    #[display(
+        fmt = "The content is flagged as {}, which the content policy refuses.",
+        _0
+    )]
+    Refused(#[error(not(source))] String),
+    #[display(fmt = "Blocked by the operator of this server: {}", _0)]
+    Blocked(#[error(not(source))] String),
+    #[display(fmt = "Invalid request body: {}", _0)]
+    InvalidBody(#[error(not(source))] String),
+    #[display(fmt = "No blocklist file is configured.")]
+    NoBlocklistFile,
+    #[display(fmt = "Invalid blocklist file: {}", _0)]
+    InvalidBlocklist(#[error(not(source))] String),
 }
 
 impl error::ResponseError for UserError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::html())
-            .body(self.to_string())
+        let mut resp = HttpResponse::build(self.status_code());
+        resp.insert_header(ContentType::json());
+        match self {
+            UserError::Blocked(reason) => resp.json(BlockedReply {
+                error: self.to_string(),
+                reason: reason.clone(),
+            }),
+            _ => resp.json(ErrorReply {
+                error: self.to_string(),
+            }),
+        }
     }
 
     fn status_code(&self) -> StatusCode {
         match *self {
             UserError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            UserError::MissingSignature
+            | UserError::SecretDisabled
+            | UserError::InvalidSignature
+            | UserError::UnknownNode => StatusCode::UNAUTHORIZED,
+            UserError::InvalidSecret
+            | UserError::TransferFailed(_)
+            | UserError::ReceiptFailed(_)
+            | UserError::InvalidGroup(_)
+            | UserError::InvalidBody(_)
+            | UserError::InvalidFault(_)
+            | UserError::VoteFailed(_)
+            | UserError::InvalidBlocklist(_) => StatusCode::BAD_REQUEST,
+            UserError::UnknownBlock
+            | UserError::UnknownPage
+            | UserError::UnknownFault
+            | UserError::NoBlocklistFile => StatusCode::NOT_FOUND,
+            UserError::Blocked(_) => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+            UserError::NotAdmin | UserError::Refused(_) => StatusCode::FORBIDDEN,
+            UserError::Group(ref e) => match e {
+                GroupError::UnknownNode => StatusCode::UNAUTHORIZED,
+                GroupError::NotMember => StatusCode::NOT_FOUND,
+            },
+            UserError::Name(ref e) => match e {
+                NameError::InvalidName | NameError::UnknownReceiver => StatusCode::BAD_REQUEST,
+                NameError::Taken => StatusCode::CONFLICT,
+                NameError::NotOwner => StatusCode::FORBIDDEN,
+                NameError::NotEnoughMana { .. } => StatusCode::PAYMENT_REQUIRED,
+                NameError::UnknownNode => StatusCode::UNAUTHORIZED,
+                NameError::UnknownName => StatusCode::NOT_FOUND,
+                NameError::Blocked(_) => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+            },
+            UserError::Storage(ref e) => match e {
+                StorageError::QuotaExceeded { .. } => StatusCode::INSUFFICIENT_STORAGE,
+                StorageError::NotOwner => StatusCode::FORBIDDEN,
+                StorageError::UnknownFile => StatusCode::NOT_FOUND,
+                StorageError::UnknownNode => StatusCode::UNAUTHORIZED,
+                StorageError::MissingChunk => StatusCode::INTERNAL_SERVER_ERROR,
+            },
         }
     }
 }
 
 #[actix_web::main] // or #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| {
+    // Every worker of the HttpServer gets a copy of the same `Main`, so that
+    // there is only one `Broker` for the whole server.
+    let config = Config::from_env();
+    let origins = config.cors_origins.clone();
+    let main = web::Data::new(Main::new(config)?);
+    HttpServer::new(move || {
         App::new()
+            .wrap(Main::cors(&origins))
             .wrap(middleware::Logger::default())
+            .app_data(main.clone())
             .configure(Main::config)
     })
     .bind(("127.0.0.1", 8080))?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod test {
+    use actix_web::{
+        http::{header::HeaderMap, Method},
+        test,
+    };
+    use utoipa::openapi::PathItemType;
+
+    use super::*;
+
+    const SECRET: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    // Most tests authenticate with the secret, which is simpler than signing.
+    fn secret_config() -> Config {
+        Config {
+            allow_secret: true,
+            ..Config::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_secret() {
+        // By default the secret is refused, also on the websocket.
+        let state = web::Data::new(Main::new(Config::default()).unwrap());
+        let app =
+            test::init_service(App::new().app_data(state.clone()).configure(Main::config)).await;
+        let req = test::TestRequest::get()
+            .uri("/v1/register")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        assert!(state.ws_caller(SECRET).is_err());
+
+        let state = web::Data::new(Main::new(secret_config()).unwrap());
+        let app =
+            test::init_service(App::new().app_data(state.clone()).configure(Main::config)).await;
+        assert!(state.ws_caller(SECRET).is_ok());
+
+        let req = test::TestRequest::get().uri("/v1/register").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/v1/register")
+            .insert_header((SECRET_HEADER, "0x1234"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/v1/alive")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/v1/register")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/v1/alive")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_broker_error() {
+        // The data directory cannot be created where a file is.
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = Config {
+            data_dir: Some(file.path().to_path_buf()),
+            ..Config::default()
+        };
+        assert!(Main::new(config).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_signature() {
+        let state = web::Data::new(Main::new(Config::default()).unwrap());
+        let app =
+            test::init_service(App::new().app_data(state.clone()).configure(Main::config)).await;
+        let secret = NodeSecret::random();
+        let signed = |method: Method, path: &str, body: &[u8], time: u128| {
+            let mut req = test::TestRequest::default()
+                .method(method.clone())
+                .uri(path)
+                .set_payload(body.to_vec());
+            for header in HttpSignature::new(&secret, method.as_str(), path, body, time).headers() {
+                req = req.insert_header(header);
+            }
+            req
+        };
+        let get = |path: &str, time: u128| signed(Method::GET, path, b"", time).to_request();
+
+        let now = Main::now();
+        let resp = test::call_service(&app, get("/v1/register", now)).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let ni: NodeInfo = test::read_body_json(resp).await;
+        assert_eq!(NodeID::from(secret), ni.id);
+        let resp = test::call_service(&app, get("/v1/alive", now + 1)).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        // Signatures for another path or query, which are too old, or which
+        // have already been used, are refused.
+        let mut req = get("/v1/register", now + 2);
+        req.head_mut().uri = "/v1/alive".parse().unwrap();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        let mut req = get("/v1/alive", now + 3);
+        req.head_mut().uri = "/v1/alive?x=1".parse().unwrap();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        let resp = test::call_service(&app, get("/v1/alive", now - 3_600_000)).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        let resp = test::call_service(&app, get("/v1/alive", now + 1)).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        // The body is signed, so it cannot be swapped.
+        let policy = |refuse: Vec<Category>| {
+            serde_json::to_vec(&ContentPolicy {
+                refuse,
+                warn: vec![],
+            })
+            .unwrap()
+        };
+        let resp = test::call_service(
+            &app,
+            signed(Method::PUT, "/v1/preferences", &policy(vec![]), now + 4)
+                .set_payload(policy(Category::ALL.to_vec()))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        let req = signed(Method::PUT, "/v1/preferences", &policy(vec![]), now + 5);
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+
+        // The websocket accepts the signature as the first message, once.
+        let sig = HttpSignature::new(&secret, "GET", "/v1/updates", b"", Main::now());
+        let text = serde_json::to_string(&sig).unwrap();
+        assert_eq!(Some(NodeID::from(secret)), state.ws_caller(&text).ok());
+        assert!(state.ws_caller(&text).is_err());
+        assert!(state.ws_caller("not a secret").is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_storage() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Main::new(secret_config()).unwrap()))
+                .configure(Main::config),
+        )
+        .await;
+        let other = "ff".repeat(32);
+        for secret in [SECRET, &other] {
+            let req = test::TestRequest::get()
+                .uri("/v1/register")
+                .insert_header((SECRET_HEADER, secret))
+                .to_request();
+            assert_eq!(StatusCode::OK, test::call_service(&app, req).await.status());
+        }
+        let put = |secret: &str, path: &str, body: Vec<u8>| {
+            test::TestRequest::put()
+                .uri(&format!("/v1/storage/{path}"))
+                .insert_header((SECRET_HEADER, secret.to_string()))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let resp =
+            test::call_service(&app, put(SECRET, "mysite/index.html", b"<h1>".to_vec())).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let usage: StorageUsage = test::read_body_json(resp).await;
+        assert_eq!((4, STORAGE_QUOTA), (usage.used, usage.quota));
+        let resp =
+            test::call_service(&app, put(SECRET, "mysite/index.html", b"<h1>Hi".to_vec())).await;
+        let usage: StorageUsage = test::read_body_json(resp).await;
+        assert_eq!(6, usage.used);
+
+        // The uploaded page is served by the node.
+        let req = test::TestRequest::get()
+            .uri("/v1/page/mysite/")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(b"<h1>Hi".as_slice(), test::read_body(resp).await);
+
+        let req = test::TestRequest::get()
+            .uri("/v1/storage/mysite")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        let files: DomainFiles = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(1, files.files.len());
+        assert_eq!("text/html; charset=utf-8", files.files[0].content_type);
+
+        // Other nodes cannot change the domain, and the quota is enforced.
+        let resp = test::call_service(&app, put(&other, "mysite/x.txt", vec![])).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        // Chunks of the same data would only be stored once.
+        let big = (0..STORAGE_QUOTA).map(|i| (i % 251) as u8).collect();
+        let resp = test::call_service(&app, put(SECRET, "mysite/big.bin", big)).await;
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, resp.status());
+
+        let delete = |path: &str| {
+            test::TestRequest::delete()
+                .uri(&format!("/v1/storage/mysite/{path}"))
+                .insert_header((SECRET_HEADER, SECRET))
+                .to_request()
+        };
+        let resp = test::call_service(&app, delete("index.html")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let resp = test::call_service(&app, delete("index.html")).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let req = test::TestRequest::get()
+            .uri("/v1/page/mysite/")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_receipt() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Main::new(secret_config()).unwrap()))
+                .configure(Main::config),
+        )
+        .await;
+        let signed = |secret: &NodeSecret, path: &str| {
+            let sig = HttpSignature::new(secret, "GET", path, b"", Main::now());
+            let mut req = test::TestRequest::get().uri(path);
+            for header in sig.headers() {
+                req = req.insert_header(header);
+            }
+            req.to_request()
+        };
+        let req = test::TestRequest::get()
+            .uri("/v1/register")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        let holder: NodeInfo = test::call_and_read_body_json(&app, req).await;
+        let req = test::TestRequest::put()
+            .uri("/v1/storage/mysite/index.html")
+            .insert_header((SECRET_HEADER, SECRET))
+            .set_payload(b"<h1>Hi".to_vec())
+            .to_request();
+        assert_eq!(StatusCode::OK, test::call_service(&app, req).await.status());
+        let viewer = NodeSecret::random();
+        let resp = test::call_service(&app, signed(&viewer, "/v1/register")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        // The signed viewer gets the bytes to sign with the page, and the
+        // holder is paid for them once.
+        let resp = test::call_service(&app, signed(&viewer, "/v1/page/mysite/")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let receipts: Vec<ReceiptRequest> =
+            serde_json::from_slice(resp.headers().get(RECEIPTS_HEADER).unwrap().as_bytes())
+                .unwrap();
+        assert_eq!(1, receipts.len());
+        assert_eq!((holder.id, 6), (receipts[0].server, receipts[0].bytes));
+        let post = || {
+            test::TestRequest::post()
+                .uri("/v1/receipt")
+                .set_json(receipts[0].sign(&viewer))
+                .to_request()
+        };
+        assert_eq!(
+            StatusCode::OK,
+            test::call_service(&app, post()).await.status()
+        );
+        let resp = test::call_service(&app, post()).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        // Anonymous viewers have nothing to sign.
+        let req = test::TestRequest::get()
+            .uri("/v1/page/mysite/")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(!resp.headers().contains_key(RECEIPTS_HEADER));
+    }
+
+    #[actix_web::test]
+    async fn test_faults() {
+        let config = Config {
+            admin_token: Some("admin".into()),
+            ..secret_config()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Main::new(config).unwrap()))
+                .configure(Main::config),
+        )
+        .await;
+        let fault = FaultRequest {
+            fault: Fault::Isolate {
+                id: NodeID::random(),
+            },
+            heal_at: None,
+        };
+        let post = |token: &str| {
+            test::TestRequest::post()
+                .uri("/v1/admin/faults")
+                .insert_header((ADMIN_HEADER, token.to_string()))
+                .set_json(&fault)
+                .to_request()
+        };
+
+        for token in ["wrong", "admiN"] {
+            let resp = test::call_service(&app, post(token)).await;
+            assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        }
+        let active: ActiveFault = test::call_and_read_body_json(&app, post("admin")).await;
+        assert_eq!(fault.fault, active.fault);
+
+        // Filters need a known type of message.
+        let req = test::TestRequest::post()
+            .uri("/v1/admin/faults")
+            .insert_header((ADMIN_HEADER, "admin"))
+            .set_json(FaultRequest {
+                fault: Fault::Drop {
+                    filter: MsgFilter {
+                        msg: Some("Pnig".into()),
+                        ..Default::default()
+                    },
+                },
+                heal_at: None,
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/v1/admin/faults")
+            .insert_header((ADMIN_HEADER, "admin"))
+            .to_request();
+        let faults: Vec<ActiveFault> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(vec![active.clone()], faults);
+
+        let delete = || {
+            test::TestRequest::delete()
+                .uri(&format!("/v1/admin/faults/{}", active.id))
+                .insert_header((ADMIN_HEADER, "admin"))
+                .to_request()
+        };
+        let resp = test::call_service(&app, delete()).await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = test::call_service(&app, delete()).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_groups() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Main::new(secret_config()).unwrap()))
+                .configure(Main::config),
+        )
+        .await;
+        let members = |method: Method, group: &str| {
+            test::TestRequest::default()
+                .method(method)
+                .uri(&format!("/v1/groups/{group}/members"))
+                .insert_header((SECRET_HEADER, SECRET))
+                .to_request()
+        };
+
+        // Unregistered nodes cannot join.
+        let resp = test::call_service(&app, members(Method::POST, "0x2a")).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        let req = test::TestRequest::get()
+            .uri("/v1/register")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        let ni: NodeInfo = test::call_and_read_body_json(&app, req).await;
+        let joined: GroupMembers =
+            test::call_and_read_body_json(&app, members(Method::POST, "0x2a")).await;
+        assert_eq!(vec![ni.id], joined.members);
+        let online: GroupMembers =
+            test::call_and_read_body_json(&app, members(Method::GET, "2a")).await;
+        assert_eq!(joined, online);
+
+        let resp = test::call_service(&app, members(Method::DELETE, "0x2a")).await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = test::call_service(&app, members(Method::DELETE, "0x2a")).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let resp = test::call_service(&app, members(Method::GET, "0xnogroup")).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_names() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Main::new(secret_config()).unwrap()))
+                .configure(Main::config),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/v1/register")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        assert_eq!(StatusCode::OK, test::call_service(&app, req).await.status());
+        let post = |name: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/v1/names/{name}"))
+                .insert_header((SECRET_HEADER, SECRET))
+                .set_json(NameRequest {
+                    group: GroupID::random(),
+                })
+                .to_request()
+        };
+
+        // A new node has no mana to pay for the name.
+        let resp = test::call_service(&app, post("ineiti")).await;
+        assert_eq!(StatusCode::PAYMENT_REQUIRED, resp.status());
+        let resp = test::call_service(&app, post("no_name")).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        let req = test::TestRequest::get()
+            .uri("/v1/names/ineiti")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_reputation() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Main::new(secret_config()).unwrap()))
+                .configure(Main::config),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/v1/register")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        assert_eq!(StatusCode::OK, test::call_service(&app, req).await.status());
+
+        let policy = ContentPolicy {
+            refuse: vec![Category::Malware],
+            warn: vec![],
+        };
+        let req = test::TestRequest::put()
+            .uri("/v1/preferences")
+            .insert_header((SECRET_HEADER, SECRET))
+            .set_json(&policy)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let req = test::TestRequest::get()
+            .uri("/v1/preferences")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        let stored: ContentPolicy = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(policy, stored);
+
+        // A vote of a node without mana is stored, but doesn't flag the group.
+        let secret: NodeSecret = SECRET.parse().unwrap();
+        let group = GroupID::random();
+        let req = test::TestRequest::post()
+            .uri("/v1/reputation/votes")
+            .set_json(ReputationVote::new(
+                &secret,
+                group,
+                Category::Malware,
+                true,
+                0,
+            ))
+            .to_request();
+        let rep: GroupReputation = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(1, rep.categories.len());
+        assert!(rep.flagged().is_empty());
+        let req = test::TestRequest::post()
+            .uri("/v1/reputation/votes")
+            .set_json(ReputationVote::new(
+                &secret,
+                group,
+                Category::Malware,
+                true,
+                0,
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_blocklist() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "mysite phishing\n").unwrap();
+        let config = Config {
+            admin_token: Some("admin".into()),
+            blocklist_file: Some(file.path().to_path_buf()),
+            ..secret_config()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Main::new(config).unwrap()))
+                .configure(Main::config),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/v1/register")
+            .insert_header((SECRET_HEADER, SECRET))
+            .to_request();
+        assert_eq!(StatusCode::OK, test::call_service(&app, req).await.status());
+        let req = test::TestRequest::put()
+            .uri("/v1/storage/mysite/index.html")
+            .insert_header((SECRET_HEADER, SECRET))
+            .set_payload(b"<h1>".to_vec())
+            .to_request();
+        assert_eq!(StatusCode::OK, test::call_service(&app, req).await.status());
+        let get_page = || {
+            test::TestRequest::get()
+                .uri("/v1/page/mysite/")
+                .to_request()
+        };
+
+        // The blocklist file is loaded on startup.
+        let resp = test::call_service(&app, get_page()).await;
+        assert_eq!(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, resp.status());
+        let reply: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("phishing", reply["reason"]);
+
+        // The content is blocked by its hash.
+        let blocklist = Blocklist {
+            hashes: [(Hash256::digest(b"<h1>"), "".to_string())].into(),
+            ..Blocklist::default()
+        };
+        let req = test::TestRequest::put()
+            .uri("/v1/admin/blocklist")
+            .insert_header((ADMIN_HEADER, "admin"))
+            .set_json(&blocklist)
+            .to_request();
+        let stored: Blocklist = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(blocklist, stored);
+        let resp = test::call_service(&app, get_page()).await;
+        let reply: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("blocked content", reply["reason"]);
+
+        // Reloading the file replaces the list.
+        fs::write(file.path(), "# nothing blocked\n").unwrap();
+        let req = test::TestRequest::post()
+            .uri("/v1/admin/blocklist/reload")
+            .insert_header((ADMIN_HEADER, "admin"))
+            .to_request();
+        let reloaded: Blocklist = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(Blocklist::default(), reloaded);
+        let resp = test::call_service(&app, get_page()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_cors() {
+        let origins = vec!["http://frontend:4200".to_string()];
+        let app = test::init_service(
+            App::new()
+                .wrap(Main::cors(&origins))
+                .app_data(web::Data::new(Main::new(Config::default()).unwrap()))
+                .configure(Main::config),
+        )
+        .await;
+        let preflight = |origin: &str| {
+            test::TestRequest::default()
+                .method(Method::OPTIONS)
+                .uri("/v1/alive")
+                .insert_header(("Origin", origin))
+                .insert_header(("Access-Control-Request-Method", "GET"))
+                .insert_header(("Access-Control-Request-Headers", SECRET_HEADER))
+                .to_request()
+        };
+        let allowed_origin = |headers: &HeaderMap| {
+            headers
+                .get("Access-Control-Allow-Origin")
+                .map(|o| o.to_str().unwrap().to_string())
+        };
+
+        // The frontend may send the authentication headers.
+        let resp = test::call_service(&app, preflight("http://frontend:4200")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            Some("http://frontend:4200".into()),
+            allowed_origin(resp.headers())
+        );
+
+        // Other origins are refused.
+        let resp = test::call_service(&app, preflight("http://evil:4200")).await;
+        assert_eq!(None, allowed_origin(resp.headers()));
+
+        let req = test::TestRequest::get()
+            .uri("/v1/stats")
+            .insert_header(("Origin", "http://frontend:4200"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            Some("http://frontend:4200".into()),
+            allowed_origin(resp.headers())
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_openapi() {
+        // Requests not matching any route get a distinct status, so that
+        // they can be told apart from handlers returning 404.
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Main::new(Config::default()).unwrap()))
+                .configure(Main::config)
+                .default_service(web::to(HttpResponse::ImATeapot)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/v1/unknown").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(StatusCode::IM_A_TEAPOT, resp.status());
+
+        // The `api!` list gives the same handlers to Main::config and ApiDoc,
+        // so every documented path must be served.
+        let spec = ApiDoc::openapi();
+        assert!(!spec.paths.paths.is_empty());
+        for (path, item) in &spec.paths.paths {
+            let uri: Vec<&str> = path
+                .split('/')
+                .map(|s| if s.starts_with('{') { "0" } else { s })
+                .collect();
+            let uri = uri.join("/");
+            for method in item.operations.keys() {
+                let method = match method {
+                    PathItemType::Get => Method::GET,
+                    PathItemType::Post => Method::POST,
+                    PathItemType::Put => Method::PUT,
+                    PathItemType::Delete => Method::DELETE,
+                    _ => panic!("Unexpected method for {path}"),
+                };
+                let req = test::TestRequest::default()
+                    .method(method.clone())
+                    .uri(&uri)
+                    .insert_header((SECRET_HEADER, SECRET))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_ne!(
+                    StatusCode::IM_A_TEAPOT,
+                    resp.status(),
+                    "{method} {path} is not served"
+                );
+            }
+        }
+
+        // And the served document is the generated one.
+        let req = test::TestRequest::get()
+            .uri("/v1/openapi.json")
+            .to_request();
+        let served: utoipa::openapi::OpenApi = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(spec.paths.paths.len(), served.paths.paths.len());
+    }
+}
@@ -2,26 +2,100 @@
 // the other hand it communicates with the network, simulation, and web
 // module.
 
-use std::{error::Error, sync::mpsc::Sender};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::mpsc::Sender,
+};
 
+use serde::{Deserialize, Serialize};
 use tracing::{error, info, warn};
 
-use crate::simul::trusted::TrustedReply;
+use crate::{
+    api::{
+        blocklist::Blocklist,
+        faults::{ActiveFault, FaultRequest},
+        groups::GroupError,
+        names::{NameError, NameRecord, NameResolution},
+        reputation::{ContentCheck, ContentPolicy, GroupReputation, ReputationVote},
+        stats::NetworkStatus,
+        storage::{DeletableContent, DomainFiles, ReplicationReport, StorageUsage},
+        wallet::{TransferHistory, TransferRequest},
+    },
+    simul::trusted::TrustedReply,
+};
 
 use super::{
+    kademlia::DhtGet,
+    ledger::{Block, LedgerStatus},
     msgs::NodeAction,
     network::Network,
-    node::{Node, NodeInfo},
+    node::{Node, NodeInfo, NodeMsg, Receipt, StorageError},
+    node_types::{GroupID, Hash256, Mana, NodeID, NodePublicKey, NodeSecret},
+    page::{normalize_path, PageFile},
+    replication::Replicator,
     simulator::{self, Simulator},
     trusted::{self, TReqMsg, Trusted, TrustedRequest},
-    web::Web, node_types::{NodeSecret, NodeID, Mana},
+    web::{ServedPage, Viewer, Web},
 };
 
 pub struct Broker {
     simulator: Simulator,
     network: Network,
     web: Web,
+    replicator: Replicator,
     trusted: Sender<TrustedRequest>,
+    events: Vec<BrokerEvent>,
+    // The bytes stored by every owner, as last sent to Trusted
+    stored: BTreeMap<NodeID, u64>,
+    // The pages and names which are not resolved
+    blocklist: Blocklist,
+}
+
+/// File in the data directory of Trusted with the `SimulatedSecrets`.
+const SECRETS: &str = "simulator.json";
+
+/// The secrets of the nodes simulated by the broker.
+/// They are stored next to the state of Trusted, so that after a restart the
+/// same nodes are simulated, instead of registering new ones every time.
+#[derive(Serialize, Deserialize)]
+struct SimulatedSecrets {
+    gateway: NodeSecret,
+    nodes: Vec<NodeSecret>,
+}
+
+impl SimulatedSecrets {
+    // Reads the secrets from the directory, if there is one, and adds or
+    // removes nodes to get the given number of nodes.
+    // Changed secrets are written back.
+    fn load(dir: Option<&Path>, count: usize) -> Result<Self, Box<dyn Error>> {
+        let stored = match dir.map(|dir| fs::read(dir.join(SECRETS))) {
+            Some(Ok(data)) => Some(serde_json::from_slice::<Self>(&data)?),
+            Some(Err(e)) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => None,
+        };
+        let mut secrets = match stored {
+            Some(secrets) if secrets.nodes.len() == count => return Ok(secrets),
+            Some(secrets) => secrets,
+            None => Self {
+                gateway: NodeSecret::random(),
+                nodes: vec![],
+            },
+        };
+        secrets.nodes.resize_with(count, NodeSecret::random);
+        if let Some(dir) = dir {
+            fs::create_dir_all(dir)?;
+            let tmp = dir.join(format!("{SECRETS}.tmp"));
+            let mut file = File::create(&tmp)?;
+            file.write_all(&serde_json::to_vec(&secrets)?)?;
+            file.sync_all()?;
+            fs::rename(tmp, dir.join(SECRETS))?;
+        }
+        Ok(secrets)
+    }
 }
 
 #[derive(Debug)]
@@ -30,17 +104,52 @@ pub enum BrokerMsg {
     Network(BMNet),
     Simulator(BMSimul),
     Node(BMNode),
+    Event(BrokerEvent),
+}
+
+/// Events which happened inside the broker and which are of interest
+/// outside of the simulation, e.g., for the web-frontend.
+#[derive(Debug, Clone)]
+pub enum BrokerEvent {
+    NodeAdd(NodeID),
+    NodeDel(NodeID),
+    NodeMsg(NodeMsg),
+    /// A message which could not be delivered, because the receiver is
+    /// not in the network.
+    Undelivered(NodeMsg),
 }
 
 #[derive(Debug)]
 pub enum BMNet {
-    NodeAction(NodeAction),
+    /// Makes a node in the network store, fetch, or send something.
+    NodeAction {
+        id: NodeID,
+        action: NodeAction,
+    },
     NodeAdd(Node),
+    /// Removes the node from the network. Messages to it are reported as
+    /// `BrokerEvent::Undelivered`.
     NodeDel(NodeID),
+    /// Sends a message from a node in the network.
+    NodeMsg(NodeMsg),
 }
 #[derive(Debug)]
 pub enum BMWeb {
-    WebRegister(NodeSecret),
+    /// The secret is only known if the node is simulated by the broker.
+    WebRegister(NodePublicKey, Option<NodeSecret>),
+    /// Fetches a page from one of the holders, and sends it to the channel
+    /// once it arrives. The viewer signs the receipts for the holder.
+    PageRequest {
+        domain: String,
+        path: String,
+        holders: Vec<NodeID>,
+        viewer: Option<Viewer>,
+        reply: Sender<Option<ServedPage>>,
+    },
+    /// A message which has been delivered to the gateway node of `Web`.
+    GatewayMsg(NodeMsg),
+    /// A message of the gateway node which could not be delivered.
+    GatewayUndelivered(NodeMsg),
 }
 
 #[derive(Debug)]
@@ -73,7 +182,11 @@ impl From<BMNode> for BrokerMsg {
     }
 }
 
-pub struct NetworkStatus {}
+impl From<BrokerEvent> for BrokerMsg {
+    fn from(value: BrokerEvent) -> Self {
+        BrokerMsg::Event(value)
+    }
+}
 
 impl Broker {
     pub fn new(
@@ -81,16 +194,28 @@ impl Broker {
         sim: simulator::Config,
         now: u128,
     ) -> Result<Self, Box<dyn Error>> {
-        let trusted = Trusted::new(trust, now);
-        let nodes: Vec<Node> = (0..sim.nodes_root + sim.nodes_flex)
-            .map(|_| Node::new(&trusted))
-            .collect();
-        let node_ids = nodes.iter().map(|n| n.id()).collect();
+        let secrets =
+            SimulatedSecrets::load(trust.data_dir.as_deref(), sim.nodes_root + sim.nodes_flex)?;
+        let trusted = Trusted::restore(trust, now)?;
+        // Only the new nodes are registered, so the restored ones keep
+        // their names.
+        for secret in &secrets.nodes {
+            if let TrustedReply::NodeInfo(None) = TReqMsg::Info((*secret).into()).send(&trusted)? {
+                Node::from_secret(*secret, &trusted);
+            }
+        }
+        let web = Web::new(trusted.clone(), secrets.gateway, now);
+        let mut network = Network::new(sim.network.clone());
+        network.action(BMNet::NodeAdd(web.gateway()));
         Ok(Self {
-            simulator: Simulator::new(sim, node_ids, trusted.clone())?,
-            network: Network::new(),
-            web: Web::new(trusted.clone()),
+            replicator: Replicator::new(sim.replication_factor),
+            simulator: Simulator::new(sim, secrets.nodes, trusted.clone())?,
+            network,
+            web,
             trusted,
+            events: vec![],
+            stored: BTreeMap::new(),
+            blocklist: Blocklist::default(),
         })
     }
 
@@ -107,6 +232,13 @@ impl Broker {
         actions.append(&mut self.web.tick(time));
         actions.append(&mut self.network.tick(time));
         self.handle_msgs(actions);
+        // Only now the nodes which went on- or offline are in the network.
+        let copies = self.replicator.tick(time, &self.network);
+        self.handle_msgs(copies);
+        let republish = self.simulator.republish();
+        self.handle_msgs(republish);
+        self.update_stored();
+        self.evict(time);
         if let Err(e) = TReqMsg::Tick(time).send(&self.trusted) {
             error!("While sending tick to Trusted: {e:?}");
         }
@@ -116,19 +248,31 @@ impl Broker {
     /// It returns the corresponding node-id.
     pub fn register(&mut self, secret: NodeSecret) -> NodeID {
         info!("register");
-        let msgs = self.web.action(BMWeb::WebRegister(secret));
+        let msgs = self
+            .web
+            .action(BMWeb::WebRegister(secret.public_key(), Some(secret)));
         self.handle_msgs(msgs);
         secret.into()
     }
 
+    /// Registers the node identified by the public key, without knowing
+    /// its secret. Such a node cannot send messages in the simulation.
+    /// It returns the corresponding node-id.
+    pub fn register_public_key(&mut self, public_key: NodePublicKey) -> NodeID {
+        info!("register public key");
+        let msgs = self.web.action(BMWeb::WebRegister(public_key, None));
+        self.handle_msgs(msgs);
+        public_key.into()
+    }
+
     /// Updates the mana of the node, and marks it as still connected.
     /// It returns how much mana the node currenlty has.
     /// TODO: perhaps it should return the NodeInfo?
     pub fn alive(&mut self, id: NodeID) -> Result<Mana, Box<dyn Error>> {
         info!("alive {id}");
         match TReqMsg::Alive(id).send(&self.trusted)? {
-            TrustedReply::Mana(m) => return Ok(m),
-            msg => return Err(format!("Got wrong type of message: {msg:?}").into()),
+            TrustedReply::Mana(m) => Ok(m),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
         }
     }
 
@@ -141,13 +285,477 @@ impl Broker {
         Err("No NodeInfo for this node available.".into())
     }
 
+    /// Returns the statistics of all nodes known to Trusted.
+    pub fn network_status(&mut self) -> Result<NetworkStatus, Box<dyn Error>> {
+        match TReqMsg::NetworkStatus.send(&self.trusted)? {
+            TrustedReply::NetworkStatus(ns) => Ok(ns),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Returns the block of the ledger at this height, if it exists.
+    pub fn get_block(&mut self, height: u64) -> Result<Option<Block>, Box<dyn Error>> {
+        match TReqMsg::Block(height).send(&self.trusted)? {
+            TrustedReply::Block(block) => Ok(block),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Returns the height of the ledger, and whether its chain is valid.
+    pub fn ledger_status(&mut self) -> Result<LedgerStatus, Box<dyn Error>> {
+        match TReqMsg::LedgerStatus.send(&self.trusted)? {
+            TrustedReply::LedgerStatus(ls) => Ok(ls),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Transfers mana between two nodes.
+    /// Returns the remaining mana of the sender.
+    pub fn transfer(&mut self, req: TransferRequest) -> Result<Mana, Box<dyn Error>> {
+        let msg = TReqMsg::Transfer {
+            from: req.from,
+            to: req.to,
+            amount: req.amount,
+            nonce: req.nonce,
+            signature: req.signature,
+        };
+        match msg.send(&self.trusted)? {
+            TrustedReply::Mana(m) => Ok(m),
+            TrustedReply::ErrorMsg(e) => Err(e.into()),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Submits a receipt signed by a viewer outside of the simulation.
+    /// Returns the mana of the server.
+    pub fn submit_receipt(&mut self, receipt: Receipt) -> Result<Mana, Box<dyn Error>> {
+        match TReqMsg::Receipt(receipt).send(&self.trusted)? {
+            TrustedReply::Mana(m) => Ok(m),
+            TrustedReply::ErrorMsg(e) => Err(e.into()),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Returns the nonce and all transfers of the node, if it exists.
+    pub fn transfers(&mut self, id: NodeID) -> Result<Option<TransferHistory>, Box<dyn Error>> {
+        match TReqMsg::Transfers(id).send(&self.trusted)? {
+            TrustedReply::Transfers(th) => Ok(th),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Adds the node to the group.
+    pub fn group_join(
+        &mut self,
+        id: NodeID,
+        group: GroupID,
+    ) -> Result<Result<(), GroupError>, Box<dyn Error>> {
+        match TReqMsg::GroupJoin(id, group).send(&self.trusted)? {
+            TrustedReply::Group(result) => Ok(result),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Removes the node from the group.
+    pub fn group_leave(
+        &mut self,
+        id: NodeID,
+        group: GroupID,
+    ) -> Result<Result<(), GroupError>, Box<dyn Error>> {
+        match TReqMsg::GroupLeave(id, group).send(&self.trusted)? {
+            TrustedReply::Group(result) => Ok(result),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Returns the members of the group which are currently online.
+    pub fn group_members(&mut self, group: GroupID) -> Result<Vec<NodeID>, Box<dyn Error>> {
+        match TReqMsg::GroupMembers(group).send(&self.trusted)? {
+            TrustedReply::Members(members) => Ok(members),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Registers the name for the group, or renews it if the node owns it.
+    pub fn register_name(
+        &mut self,
+        owner: NodeID,
+        name: &str,
+        group: GroupID,
+    ) -> Result<Result<NameRecord, NameError>, Box<dyn Error>> {
+        self.name_request(TReqMsg::NameRegister {
+            name: name.to_string(),
+            owner,
+            group,
+        })
+    }
+
+    /// Gives the name of a node to another node.
+    pub fn transfer_name(
+        &mut self,
+        from: NodeID,
+        name: &str,
+        to: NodeID,
+    ) -> Result<Result<NameRecord, NameError>, Box<dyn Error>> {
+        self.name_request(TReqMsg::NameTransfer {
+            name: name.to_string(),
+            from,
+            to,
+        })
+    }
+
+    /// Returns the record of the name, and the online members of its group,
+    /// unless the blocklist blocks the name or its group.
+    pub fn resolve(
+        &mut self,
+        name: &str,
+    ) -> Result<Result<NameResolution, NameError>, Box<dyn Error>> {
+        if let Some(reason) = self.blocklist.domain(name) {
+            return Ok(Err(NameError::Blocked(reason)));
+        }
+        let record = match self.name_request(TReqMsg::Resolve(name.to_string()))? {
+            Ok(record) => record,
+            Err(e) => return Ok(Err(e)),
+        };
+        let flagged = self.reputation(record.group)?.flagged();
+        if let Some(reason) = self.blocklist.flagged(&flagged) {
+            return Ok(Err(NameError::Blocked(reason)));
+        }
+        Ok(Ok(NameResolution {
+            members: self.group_members(record.group)?,
+            record,
+        }))
+    }
+
+    fn name_request(
+        &mut self,
+        msg: TReqMsg,
+    ) -> Result<Result<NameRecord, NameError>, Box<dyn Error>> {
+        match msg.send(&self.trusted)? {
+            TrustedReply::Name(name) => Ok(name),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Stores the signed vote on the content of a group.
+    /// Returns the reputation of the group including the vote.
+    pub fn vote(&mut self, vote: ReputationVote) -> Result<GroupReputation, Box<dyn Error>> {
+        match TReqMsg::Vote(vote).send(&self.trusted)? {
+            TrustedReply::Reputation(rep) => Ok(rep),
+            TrustedReply::ErrorMsg(e) => Err(e.into()),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Returns the votes on the group, weighted by the mana of the voters.
+    pub fn reputation(&mut self, group: GroupID) -> Result<GroupReputation, Box<dyn Error>> {
+        match TReqMsg::Reputation(group).send(&self.trusted)? {
+            TrustedReply::Reputation(rep) => Ok(rep),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Sets what the node wants to happen with pages of flagged groups.
+    pub fn set_preferences(
+        &mut self,
+        id: NodeID,
+        policy: ContentPolicy,
+    ) -> Result<Option<ContentPolicy>, Box<dyn Error>> {
+        match TReqMsg::Preferences(id, policy).send(&self.trusted)? {
+            TrustedReply::Preferences(policy) => Ok(policy),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Returns the content policy of the node, if it is registered.
+    pub fn preferences(&mut self, id: NodeID) -> Result<Option<ContentPolicy>, Box<dyn Error>> {
+        match TReqMsg::GetPreferences(id).send(&self.trusted)? {
+            TrustedReply::Preferences(policy) => Ok(policy),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    /// Checks whether the pages of the domain can be shown to the viewer.
+    /// Domains on the blocklist are blocked. Else the domain is resolved as
+    /// a name, and the categories its group is flagged for are checked
+    /// against the blocklist, and the content policy of the viewer, or the
+    /// default policy for unknown viewers.
+    pub fn content_check(
+        &mut self,
+        viewer: Option<NodeID>,
+        domain: &str,
+    ) -> Result<ContentCheck, Box<dyn Error>> {
+        if let Some(reason) = self.blocklist.domain(domain) {
+            return Ok(ContentCheck::Blocked(reason));
+        }
+        let Ok(record) = self.name_request(TReqMsg::Resolve(domain.to_string()))? else {
+            return Ok(ContentCheck::Serve);
+        };
+        let flagged = self.reputation(record.group)?.flagged();
+        if let Some(reason) = self.blocklist.flagged(&flagged) {
+            return Ok(ContentCheck::Blocked(reason));
+        }
+        let policy = match viewer {
+            Some(id) => self.preferences(id)?.unwrap_or_default(),
+            None => ContentPolicy::default(),
+        };
+        Ok(policy.check(&flagged))
+    }
+
+    /// Returns why the content with this hash is blocked, if it is.
+    pub fn blocked_content(&self, hash: &Hash256) -> Option<String> {
+        self.blocklist.content(hash)
+    }
+
+    pub fn blocklist(&self) -> &Blocklist {
+        &self.blocklist
+    }
+
+    /// Replaces the blocklist.
+    pub fn set_blocklist(&mut self, blocklist: Blocklist) {
+        info!(
+            "Blocking {} domains, {} hashes, and {} categories",
+            blocklist.domains.len(),
+            blocklist.hashes.len(),
+            blocklist.categories.len()
+        );
+        self.blocklist = blocklist;
+    }
+
+    /// Fetches the page at the path of the domain from a node holding it.
+    /// The page, or None if it cannot be found, is sent to the reply
+    /// channel once the answer of the node arrives.
+    /// The viewer pays the node for the page with a receipt. If its secret
+    /// is in the simulation, the receipt is signed for it, else the page
+    /// comes with the `ReceiptRequest`s for the viewer to sign and submit.
+    /// Anonymous views are not paid.
+    pub fn request_page(
+        &mut self,
+        viewer: Option<NodeID>,
+        domain: &str,
+        path: &str,
+        reply: Sender<Option<ServedPage>>,
+    ) {
+        let viewer = viewer.map(|id| {
+            let secret = self.network.node(&id).and_then(|node| node.secret());
+            secret.map_or(Viewer::Signed(id), Viewer::Secret)
+        });
+        let msgs = self.web.action(BMWeb::PageRequest {
+            domain: domain.to_string(),
+            path: normalize_path(path),
+            holders: self.network.holders(domain),
+            viewer,
+            reply,
+        });
+        self.handle_msgs(msgs);
+    }
+
+    /// Stores the file in the storage of the node of the owner, which must
+    /// be online. Files with the same path are overwritten.
+    /// The domain must not belong to another node.
+    pub fn store_file(
+        &mut self,
+        owner: NodeID,
+        mut file: PageFile,
+    ) -> Result<StorageUsage, StorageError> {
+        self.check_domain(owner, &file.domain)?;
+        file.path = normalize_path(&file.path);
+        self.owner_node(owner)?.store_page(file)
+    }
+
+    /// Deletes the file from the storage of the node of the owner.
+    /// Once the last file is deleted, the copies of the domain on the other
+    /// nodes are removed, too.
+    pub fn delete_file(
+        &mut self,
+        owner: NodeID,
+        domain: &str,
+        path: &str,
+    ) -> Result<StorageUsage, StorageError> {
+        self.check_domain(owner, domain)?;
+        let node = self.owner_node(owner)?;
+        let usage = node
+            .storage_mut()
+            .delete(owner, domain, &normalize_path(path))?;
+        if !node.has_domain(domain) {
+            self.network.remove_domain(domain);
+            self.replicator.remove_domain(domain);
+        }
+        Ok(usage)
+    }
+
+    /// Returns the sorted IDs of the online nodes holding the domain.
+    pub fn holders(&self, domain: &str) -> Vec<NodeID> {
+        self.network.holders(domain)
+    }
+
+    /// Returns the domains which are stored on too few nodes.
+    pub fn replication(&self) -> ReplicationReport {
+        self.replicator.report(&self.network)
+    }
+
+    /// Stores the value under the key in the DHT, at the nodes closest to
+    /// the key. The value expires after `ttl` milliseconds, unless it is
+    /// stored again.
+    pub fn dht_put(&mut self, key: Hash256, value: Vec<u8>, ttl: u128) {
+        self.gateway_action(NodeAction::DhtPut { key, value, ttl });
+    }
+
+    /// Starts looking up the value of the key in the DHT. The result is
+    /// available from `dht_result` once the replies arrived, which is
+    /// right away in a network without latency.
+    pub fn dht_get(&mut self, key: Hash256) {
+        self.gateway_action(NodeAction::DhtGet { key });
+    }
+
+    /// Returns the state of the last lookup of the key in the DHT.
+    pub fn dht_result(&self, key: &Hash256) -> DhtGet {
+        self.network
+            .node(&self.web.gateway_id())
+            .map_or(DhtGet::NotFound, |gateway| gateway.dht_result(key))
+    }
+
+    // The gateway of Web is always online, so it does the work of the
+    // broker in the DHT.
+    fn gateway_action(&mut self, action: NodeAction) {
+        let id = self.web.gateway_id();
+        self.handle_msgs(vec![BMNet::NodeAction { id, action }.into()]);
+    }
+
+    /// Injects a fault into the network.
+    pub fn add_fault(&mut self, request: FaultRequest) -> Result<ActiveFault, String> {
+        self.network.faults_mut().add(request)
+    }
+
+    /// Removes the fault, and returns false if there is no such fault.
+    pub fn heal_fault(&mut self, id: u64) -> bool {
+        self.network.faults_mut().heal(id)
+    }
+
+    pub fn heal_faults(&mut self) {
+        self.network.faults_mut().heal_all()
+    }
+
+    pub fn faults(&self) -> Vec<ActiveFault> {
+        self.network.faults().list()
+    }
+
+    /// Lists the files of the domain in the storage of the node of the owner.
+    pub fn list_files(&mut self, owner: NodeID, domain: &str) -> Result<DomainFiles, StorageError> {
+        self.check_domain(owner, domain)?;
+        let storage = self.owner_node(owner)?.storage();
+        Ok(DomainFiles {
+            usage: storage.usage(),
+            files: storage.list(domain),
+        })
+    }
+
+    fn check_domain(&self, owner: NodeID, domain: &str) -> Result<(), StorageError> {
+        match self.network.domain_owner(domain) {
+            Some(o) if o != owner => Err(StorageError::NotOwner),
+            _ => Ok(()),
+        }
+    }
+
+    fn owner_node(&mut self, owner: NodeID) -> Result<&mut Node, StorageError> {
+        self.network
+            .node_mut(&owner)
+            .ok_or(StorageError::UnknownNode)
+    }
+
+    /// Returns the owners whose content will be deleted, because they
+    /// cannot pay the rent for it.
+    pub fn deletable(&self) -> Result<Vec<DeletableContent>, Box<dyn Error>> {
+        match TReqMsg::Deletable.send(&self.trusted)? {
+            TrustedReply::Deletable(list) => Ok(list),
+            msg => Err(format!("Got wrong type of message: {msg:?}").into()),
+        }
+    }
+
+    // Sends the bytes stored by the owners to Trusted, if they changed.
+    fn update_stored(&mut self) {
+        let mut stored: BTreeMap<NodeID, u64> = BTreeMap::new();
+        for (owner, size) in self.network.domains().into_values() {
+            *stored.entry(owner).or_default() += size;
+        }
+        for owner in self.stored.keys() {
+            stored.entry(*owner).or_default();
+        }
+        for (owner, bytes) in &stored {
+            if self.stored.get(owner) != Some(bytes) {
+                if let Err(e) = TReqMsg::Stored(*owner, *bytes).send(&self.trusted) {
+                    error!("While sending stored bytes to Trusted: {e:?}");
+                }
+            }
+        }
+        stored.retain(|_, bytes| *bytes > 0);
+        self.stored = stored;
+    }
+
+    // Deletes the content of the owners whose grace period is over from all
+    // nodes.
+    fn evict(&mut self, now: u128) {
+        let deletable = match self.deletable() {
+            Ok(list) => list,
+            Err(e) => return error!("While asking Trusted for deletable content: {e:?}"),
+        };
+        for content in deletable.iter().filter(|c| c.delete_after <= now) {
+            for (domain, (owner, _)) in self.network.domains() {
+                if owner == content.owner {
+                    info!("Evicting {domain} of {owner}");
+                    self.network.remove_domain(&domain);
+                    self.replicator.remove_domain(&domain);
+                }
+            }
+        }
+    }
+
+    /// Returns all events since the last call to this method.
+    pub fn events(&mut self) -> Vec<BrokerEvent> {
+        std::mem::take(&mut self.events)
+    }
+
     fn handle_msgs(&mut self, mut msgs: Vec<BrokerMsg>) {
         while let Some(msg) = msgs.pop() {
             match msg {
                 BrokerMsg::Web(msg) => msgs.append(&mut self.web.action(msg)),
-                BrokerMsg::Network(msg) => msgs.append(&mut self.network.action(msg)),
+                BrokerMsg::Network(msg) => {
+                    match &msg {
+                        BMNet::NodeAdd(n) => {
+                            self.replicator.node_add(n);
+                            self.events.push(BrokerEvent::NodeAdd(n.id()));
+                            // New nodes join the routing through the gateway,
+                            // once they are in the network.
+                            let gateway = self.web.gateway_id();
+                            if n.has_secret() && n.id() != gateway {
+                                msgs.push(BrokerMsg::Network(BMNet::NodeAction {
+                                    id: n.id(),
+                                    action: NodeAction::Join { bootstrap: gateway },
+                                }));
+                            }
+                        }
+                        BMNet::NodeDel(id) => {
+                            self.replicator.node_del(id);
+                            self.events.push(BrokerEvent::NodeDel(*id))
+                        }
+                        BMNet::NodeAction { .. } | BMNet::NodeMsg(_) => {}
+                    }
+                    msgs.append(&mut self.network.action(msg))
+                }
                 BrokerMsg::Simulator(msg) => msgs.append(&mut self.simulator.action(msg)),
                 BrokerMsg::Node(_) => warn!("Got {msg:?} for node"),
+                BrokerMsg::Event(event) => {
+                    let gateway = self.web.gateway_id();
+                    match &event {
+                        BrokerEvent::NodeMsg(msg) if msg.to == gateway => {
+                            msgs.append(&mut self.web.action(BMWeb::GatewayMsg(msg.clone())))
+                        }
+                        BrokerEvent::Undelivered(msg) if msg.from == gateway => msgs
+                            .append(&mut self.web.action(BMWeb::GatewayUndelivered(msg.clone()))),
+                        _ => {}
+                    }
+                    self.events.push(event)
+                }
             }
         }
     }
@@ -0,0 +1,214 @@
+// Content-addressed storage of the data of the nodes.
+// Files are split into chunks, which are identified by the SHA-256 hash of
+// their data. Identical data is stored only once, and a chunk fetched from
+// another node can be verified against the hash it was requested with.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::node_types::Hash256;
+
+/// The maximum size of a chunk in bytes.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A piece of a file, identified by its hash.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chunk {
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    /// Returns the SHA-256 hash of the data, which identifies the chunk.
+    pub fn id(&self) -> Hash256 {
+        Hash256::digest(&self.data)
+    }
+
+    /// Splits the data into chunks of `CHUNK_SIZE` bytes.
+    /// Empty data gives no chunks.
+    pub fn split(data: &[u8]) -> Vec<Chunk> {
+        data.chunks(CHUNK_SIZE)
+            .map(|data| Chunk {
+                data: data.to_vec(),
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for Chunk {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}: {} bytes", self.id(), self.data.len())
+    }
+}
+
+/// Describes how to put a file together from its chunks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub content_type: String,
+    pub size: u64,
+    pub chunks: Vec<Hash256>,
+}
+
+impl FileEntry {
+    /// Puts the file together from the given chunks.
+    /// Returns None if a chunk is missing, or if the chunks don't match
+    /// the hashes or the size of the entry.
+    pub fn assemble<'a>(
+        &self,
+        mut get: impl FnMut(&Hash256) -> Option<&'a Chunk>,
+    ) -> Option<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.size as usize);
+        for id in &self.chunks {
+            let chunk = get(id)?;
+            if &chunk.id() != id {
+                return None;
+            }
+            data.extend_from_slice(&chunk.data);
+        }
+        (data.len() as u64 == self.size).then_some(data)
+    }
+}
+
+/// The files of a domain, by path.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+impl Manifest {
+    /// Returns the hash of the manifest, which changes with every change of
+    /// a file.
+    pub fn id(&self) -> Hash256 {
+        Hash256::digest(&serde_json::to_vec(self).expect("Manifest serializes to JSON"))
+    }
+
+    /// Returns the chunks of all files, including duplicates.
+    pub fn chunk_ids(&self) -> Vec<Hash256> {
+        self.files
+            .values()
+            .flat_map(|f| f.chunks.iter().copied())
+            .collect()
+    }
+
+    /// Returns the size of all files, without deduplication.
+    pub fn size(&self) -> u64 {
+        self.files.values().map(|f| f.size).sum()
+    }
+}
+
+/// Holds chunks and counts how many files refer to them, so that a chunk
+/// is only removed when the last file using it is gone.
+#[derive(Debug, Default)]
+pub struct ChunkStore {
+    chunks: HashMap<Hash256, StoredChunk>,
+    size: u64,
+}
+
+#[derive(Debug)]
+struct StoredChunk {
+    chunk: Chunk,
+    refs: usize,
+}
+
+impl ChunkStore {
+    /// Returns the number of bytes of all chunks, each counted once.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get(&self, id: &Hash256) -> Option<&Chunk> {
+        self.chunks.get(id).map(|s| &s.chunk)
+    }
+
+    /// Adds a reference to the chunk, and stores it if it is new.
+    pub fn insert(&mut self, chunk: Chunk) -> Hash256 {
+        let id = chunk.id();
+        let size = chunk.data.len() as u64;
+        let stored = self
+            .chunks
+            .entry(id)
+            .or_insert_with(|| StoredChunk { chunk, refs: 0 });
+        if stored.refs == 0 {
+            self.size += size;
+        }
+        stored.refs += 1;
+        id
+    }
+
+    /// Removes a reference to each chunk, and the chunks which are not
+    /// referenced anymore.
+    pub fn release(&mut self, ids: &[Hash256]) {
+        for id in ids {
+            let Some(stored) = self.chunks.get_mut(id) else {
+                continue;
+            };
+            stored.refs -= 1;
+            if stored.refs == 0 {
+                self.size -= stored.chunk.data.len() as u64;
+                self.chunks.remove(id);
+            }
+        }
+    }
+
+    /// Returns how many bytes `release` would free for these chunks.
+    pub fn freed_by(&self, ids: &[Hash256]) -> u64 {
+        let mut count: HashMap<&Hash256, usize> = HashMap::new();
+        for id in ids {
+            *count.entry(id).or_default() += 1;
+        }
+        count
+            .into_iter()
+            .filter_map(|(id, count)| self.chunks.get(id).filter(|s| s.refs <= count))
+            .map(|s| s.chunk.data.len() as u64)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_assemble() {
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        let chunks = Chunk::split(&data);
+        assert_eq!(3, chunks.len());
+        assert_eq!(10, chunks[2].data.len());
+        assert!(Chunk::split(&[]).is_empty());
+
+        let chunks: HashMap<Hash256, Chunk> = chunks.into_iter().map(|c| (c.id(), c)).collect();
+        let entry = FileEntry {
+            content_type: "application/octet-stream".into(),
+            size: data.len() as u64,
+            chunks: Chunk::split(&data).iter().map(Chunk::id).collect(),
+        };
+        assert_eq!(Some(data), entry.assemble(|id| chunks.get(id)));
+
+        // A chunk which doesn't match its hash is refused.
+        let forged = Chunk { data: vec![1] };
+        assert_eq!(None, entry.assemble(|_| Some(&forged)));
+        assert_eq!(None, entry.assemble(|_| None));
+    }
+
+    #[test]
+    fn test_store() {
+        let mut store = ChunkStore::default();
+        let a = Chunk { data: vec![1; 10] };
+        let b = Chunk { data: vec![2; 5] };
+
+        // The same chunk is stored only once.
+        let id_a = store.insert(a.clone());
+        store.insert(a.clone());
+        let id_b = store.insert(b);
+        assert_eq!(15, store.size());
+        assert_eq!(Some(&a), store.get(&id_a));
+
+        assert_eq!(5, store.freed_by(&[id_a, id_b]));
+        assert_eq!(15, store.freed_by(&[id_a, id_a, id_b]));
+        store.release(&[id_a]);
+        assert_eq!(15, store.size());
+        store.release(&[id_a, id_b]);
+        assert_eq!(0, store.size());
+        assert_eq!(None, store.get(&id_a));
+    }
+}
@@ -0,0 +1,212 @@
+// Faults injected into the network, to test how the nodes cope with
+// outages: partitions, isolated nodes, and lost or duplicated messages.
+// Every fault stays until it is removed, or until the tick at its
+// `heal_at` time.
+
+use std::collections::BTreeMap;
+
+use tracing::debug;
+
+use crate::api::faults::{ActiveFault, Fault, FaultRequest, MsgFilter};
+
+use super::{
+    node::{Msg, NodeMsg},
+    node_types::NodeID,
+};
+
+#[derive(Debug, Default)]
+pub struct Faults {
+    faults: BTreeMap<u64, FaultRequest>,
+    // The id of the next fault
+    next_id: u64,
+}
+
+impl Faults {
+    /// Adds the fault and returns it with its id.
+    /// Filters with an unknown type of message are refused.
+    pub fn add(&mut self, request: FaultRequest) -> Result<ActiveFault, String> {
+        if let Fault::Drop { filter } | Fault::Duplicate { filter } = &request.fault {
+            if let Some(kind) = filter
+                .msg
+                .as_ref()
+                .filter(|k| !Msg::KINDS.contains(&k.as_str()))
+            {
+                return Err(format!(
+                    "unknown message type '{kind}', expected one of {}",
+                    Msg::KINDS.join(", ")
+                ));
+            }
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        debug!("Adding fault {id}: {request:?}");
+        self.faults.insert(id, request.clone());
+        Ok(ActiveFault {
+            id,
+            fault: request.fault,
+            heal_at: request.heal_at,
+        })
+    }
+
+    /// Removes the fault, and returns false if there is no such fault.
+    pub fn heal(&mut self, id: u64) -> bool {
+        self.faults.remove(&id).is_some()
+    }
+
+    pub fn heal_all(&mut self) {
+        self.faults.clear();
+    }
+
+    pub fn list(&self) -> Vec<ActiveFault> {
+        self.faults
+            .iter()
+            .map(|(id, request)| ActiveFault {
+                id: *id,
+                fault: request.fault.clone(),
+                heal_at: request.heal_at,
+            })
+            .collect()
+    }
+
+    /// Removes the faults which heal until now.
+    pub fn tick(&mut self, now: u128) {
+        self.faults.retain(|id, request| {
+            let heal = request.heal_at.is_some_and(|heal_at| heal_at <= now);
+            if heal {
+                debug!("Healing fault {id}");
+            }
+            !heal
+        });
+    }
+
+    /// Returns how many times the message is to be delivered: 0 if one of
+    /// the faults drops it, 2 if it is duplicated, else 1.
+    pub fn copies(&self, msg: &NodeMsg) -> usize {
+        if self.cut(msg) {
+            return 0;
+        }
+        let mut copies = 1;
+        for request in self.faults.values() {
+            match &request.fault {
+                Fault::Partition { .. } | Fault::Isolate { .. } => {}
+                Fault::Drop { filter } => {
+                    if matches(filter, msg) {
+                        return 0;
+                    }
+                }
+                Fault::Duplicate { filter } => {
+                    if matches(filter, msg) {
+                        copies = 2;
+                    }
+                }
+            }
+        }
+        copies
+    }
+
+    /// Returns true if a partition or an isolated node separates the sender
+    /// from the receiver. This is checked again when the message arrives,
+    /// so that messages on their way are lost, too.
+    pub fn cut(&self, msg: &NodeMsg) -> bool {
+        self.faults.values().any(|request| match &request.fault {
+            Fault::Partition { partitions } => {
+                partition(partitions, &msg.from) != partition(partitions, &msg.to)
+            }
+            Fault::Isolate { id } => &msg.from == id || &msg.to == id,
+            Fault::Drop { .. } | Fault::Duplicate { .. } => false,
+        })
+    }
+}
+
+// Returns the index of the partition of the node, or None for the nodes
+// in no partition.
+fn partition(partitions: &[Vec<NodeID>], id: &NodeID) -> Option<usize> {
+    partitions.iter().position(|p| p.contains(id))
+}
+
+fn matches(filter: &MsgFilter, msg: &NodeMsg) -> bool {
+    filter.from.is_none_or(|from| from == msg.from)
+        && filter.to.is_none_or(|to| to == msg.to)
+        && filter
+            .msg
+            .as_ref()
+            .is_none_or(|kind| kind == msg.msg.kind())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::simul::node_types::NodeSecret;
+
+    #[test]
+    fn test_copies() {
+        let (secret1, secret2) = (NodeSecret::random(), NodeSecret::random());
+        let (id1, id2, id3) = (secret1.into(), secret2.into(), NodeID::random());
+        let ping = NodeMsg::new(&secret1, id2, Msg::Ping);
+        let pong = NodeMsg::new(&secret2, id1, Msg::Pong);
+        let mut faults = Faults::default();
+        assert_eq!(1, faults.copies(&ping));
+
+        // Nodes in no partition form their own partition.
+        let partition = faults
+            .add(FaultRequest {
+                fault: Fault::Partition {
+                    partitions: vec![vec![id1, id3]],
+                },
+                heal_at: Some(100),
+            })
+            .unwrap();
+        assert_eq!(0, faults.copies(&ping));
+        assert_eq!(1, faults.copies(&NodeMsg::new(&secret1, id3, Msg::Ping)));
+        faults.tick(99);
+        assert_eq!(1, faults.list().len());
+        faults.tick(100);
+        assert_eq!(1, faults.copies(&ping));
+        assert!(!faults.heal(partition.id));
+
+        // Only the matching messages are duplicated.
+        let duplicate = faults
+            .add(FaultRequest {
+                fault: Fault::Duplicate {
+                    filter: MsgFilter {
+                        msg: Some("Pong".into()),
+                        ..Default::default()
+                    },
+                },
+                heal_at: None,
+            })
+            .unwrap();
+        assert_eq!(1, faults.copies(&ping));
+        assert_eq!(2, faults.copies(&pong));
+        faults
+            .add(FaultRequest {
+                fault: Fault::Isolate { id: id1 },
+                heal_at: None,
+            })
+            .unwrap();
+        assert_eq!(0, faults.copies(&pong));
+        assert!(faults.cut(&pong));
+        assert!(faults.heal(duplicate.id));
+        assert_eq!(1, faults.list().len());
+        faults.heal_all();
+        assert_eq!(1, faults.copies(&pong));
+
+        // Filters only accept known types of messages.
+        let drop = |msg: &str| Fault::Drop {
+            filter: MsgFilter {
+                msg: Some(msg.into()),
+                ..Default::default()
+            },
+        };
+        for kind in [ping.msg.kind(), pong.msg.kind()] {
+            assert!(Msg::KINDS.contains(&kind));
+        }
+        let request = |fault| FaultRequest {
+            fault,
+            heal_at: None,
+        };
+        assert!(faults.add(request(drop("Receipt"))).is_ok());
+        assert!(faults.add(request(drop("receipt"))).is_err());
+        assert_eq!(1, faults.list().len());
+    }
+}
@@ -0,0 +1,185 @@
+// A very simple persistence layer: a snapshot of the state plus an
+// append-only journal of all updates since that snapshot.
+// Both are stored as JSON in a directory, the journal with one entry per line.
+
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{debug, warn};
+
+const SNAPSHOT: &str = "snapshot.json";
+const JOURNAL: &str = "journal.jsonl";
+
+/// Journal stores a snapshot of type `S` and the entries of type `E` which
+/// have been applied since that snapshot.
+/// Every entry has a sequence number, and the snapshot stores the sequence
+/// number of the first entry it doesn't contain.
+/// So if the journal cannot be truncated after a snapshot, the old entries
+/// are skipped on the next `open`.
+pub struct Journal<S, E> {
+    dir: PathBuf,
+    journal: File,
+    // Sequence number of the next entry
+    seq: u64,
+    // Number of entries since the last snapshot
+    entries: usize,
+    _types: PhantomData<(S, E)>,
+}
+
+/// The journal, the latest snapshot, and the entries since that snapshot.
+pub type Opened<S, E> = (Journal<S, E>, Option<S>, Vec<E>);
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<S> {
+    seq: u64,
+    state: S,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry<E> {
+    seq: u64,
+    entry: E,
+}
+
+impl<S: Serialize + DeserializeOwned, E: Serialize + DeserializeOwned> Journal<S, E> {
+    /// Opens the journal in the given directory, creating it if necessary.
+    /// Returns the journal, the latest snapshot, if any, and all entries
+    /// which need to be replayed on top of the snapshot.
+    /// An incomplete last line in the journal, e.g., due to a crash, is ignored.
+    pub fn open(dir: &Path) -> Result<Opened<S, E>, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+
+        let snapshot: Option<Snapshot<S>> = match fs::read(dir.join(SNAPSHOT)) {
+            Ok(data) => Some(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let mut seq = snapshot.as_ref().map(|s| s.seq).unwrap_or(0);
+
+        let mut entries = vec![];
+        let journal_path = dir.join(JOURNAL);
+        let data = match fs::read_to_string(&journal_path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        // Every entry is terminated by a newline, so anything after the
+        // last newline has been interrupted while writing.
+        let valid_len = data.rfind('\n').map(|pos| pos + 1).unwrap_or(0);
+        if valid_len < data.len() {
+            warn!("Ignoring incomplete last journal entry");
+        }
+        for line in data[..valid_len].lines() {
+            match serde_json::from_str::<Entry<E>>(line) {
+                Ok(entry) if entry.seq < seq => {
+                    debug!("Skipping entry {} already in snapshot", entry.seq)
+                }
+                Ok(entry) if entry.seq == seq => {
+                    entries.push(entry.entry);
+                    seq += 1;
+                }
+                Ok(entry) => return Err(format!("Expected entry {seq}, got {}", entry.seq).into()),
+                Err(e) => return Err(format!("Corrupt journal entry {seq}: {e}").into()),
+            }
+        }
+
+        let journal = Self {
+            dir: dir.to_path_buf(),
+            journal: OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&journal_path)?,
+            seq,
+            entries: entries.len(),
+            _types: PhantomData,
+        };
+        // Make sure an incomplete last line doesn't get glued to the next entry.
+        journal.journal.set_len(valid_len as u64)?;
+        Ok((journal, snapshot.map(|s| s.state), entries))
+    }
+
+    /// Appends an entry to the journal.
+    pub fn append(&mut self, entry: &E) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_vec(&Entry {
+            seq: self.seq,
+            entry,
+        })?;
+        line.push(b'\n');
+        self.journal.write_all(&line)?;
+        self.journal.sync_data()?;
+        self.seq += 1;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Stores the state as the new snapshot and truncates the journal.
+    /// The state must contain all entries appended so far.
+    pub fn snapshot(&mut self, state: &S) -> Result<(), Box<dyn Error>> {
+        let tmp = self.dir.join(format!("{SNAPSHOT}.tmp"));
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(&Snapshot {
+            seq: self.seq,
+            state,
+        })?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(SNAPSHOT))?;
+        self.journal.set_len(0)?;
+        self.journal.sync_data()?;
+        self.entries = 0;
+        Ok(())
+    }
+
+    /// Returns the number of entries since the last snapshot.
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type ResErr = Result<(), Box<dyn Error>>;
+
+    #[test]
+    fn test_replay() -> ResErr {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let (mut journal, state, entries) = Journal::<Vec<u8>, u8>::open(dir)?;
+        assert_eq!(None, state);
+        assert!(entries.is_empty());
+        journal.append(&1)?;
+        journal.append(&2)?;
+        journal.snapshot(&vec![1, 2])?;
+        journal.append(&3)?;
+
+        let (mut journal, state, entries) = Journal::<Vec<u8>, u8>::open(dir)?;
+        assert_eq!(Some(vec![1, 2]), state);
+        assert_eq!(vec![3], entries);
+
+        // Entries already in the snapshot are skipped.
+        journal.append(&4)?;
+        let journal_file = dir.join(JOURNAL);
+        let lines = fs::read_to_string(&journal_file)?;
+        fs::write(
+            &journal_file,
+            format!("{{\"seq\":0,\"entry\":1}}\n{lines}{{\"seq\":5,\"en"),
+        )?;
+        let (mut journal, state, entries) = Journal::<Vec<u8>, u8>::open(dir)?;
+        assert_eq!(Some(vec![1, 2]), state);
+        assert_eq!(vec![3, 4], entries);
+
+        // The incomplete entry has been removed.
+        journal.append(&5)?;
+        let (_, _, entries) = Journal::<Vec<u8>, u8>::open(dir)?;
+        assert_eq!(vec![3, 4, 5], entries);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,478 @@
+// Kademlia routing: every node keeps the IDs of other nodes in k-buckets,
+// by the highest bit in which their ID differs from its own. A node is found
+// by asking the closest known nodes for closer ones, which halves the
+// distance with every hop, so a lookup needs O(log n) hops.
+// See https://pdos.csail.mit.edu/~petar/papers/maymounkov-kademlia-lncs.pdf
+
+use std::collections::BTreeMap;
+
+use primitive_types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::node_types::NodeID;
+
+/// The number of nodes in a k-bucket, and the number of nodes returned by
+/// a lookup.
+pub const K: usize = 20;
+/// The number of requests of a lookup which are sent in parallel.
+pub const ALPHA: usize = 3;
+/// How long a node waits for a reply, in milliseconds.
+pub const TIMEOUT: u128 = 5_000;
+/// How long a value stays in the DHT if it is not stored again, in
+/// milliseconds.
+pub const TTL: u128 = 24 * 3_600_000;
+
+/// The nodes known to a node, in one k-bucket per bit of the ID.
+#[derive(Debug)]
+pub struct RoutingTable {
+    id: NodeID,
+    // The nodes of every bucket, the least recently seen first
+    buckets: Vec<Vec<NodeID>>,
+}
+
+impl RoutingTable {
+    pub fn new(id: NodeID) -> Self {
+        Self {
+            id,
+            buckets: vec![vec![]; 256],
+        }
+    }
+
+    /// Marks the node as the most recently seen of its bucket.
+    /// If the bucket is full, the node is not added, and the least recently
+    /// seen node of the bucket is returned: it should be pinged, and
+    /// replaced if it doesn't answer.
+    pub fn seen(&mut self, id: NodeID) -> Option<NodeID> {
+        let bucket = &mut self.buckets[self.id.bucket_index(&id)?];
+        if let Some(pos) = bucket.iter().position(|n| n == &id) {
+            bucket.remove(pos);
+        } else if bucket.len() >= K {
+            return bucket.first().copied();
+        }
+        bucket.push(id);
+        None
+    }
+
+    /// Removes the node, and returns false if it wasn't known.
+    pub fn remove(&mut self, id: &NodeID) -> bool {
+        let Some(index) = self.id.bucket_index(id) else {
+            return false;
+        };
+        let bucket = &mut self.buckets[index];
+        let len = bucket.len();
+        bucket.retain(|n| n != id);
+        bucket.len() != len
+    }
+
+    pub fn contains(&self, id: &NodeID) -> bool {
+        self.id
+            .bucket_index(id)
+            .is_some_and(|index| self.buckets[index].contains(id))
+    }
+
+    /// Returns up to `count` known nodes, the closest to `target` first.
+    pub fn closest(&self, target: &NodeID, count: usize) -> Vec<NodeID> {
+        let mut nodes: Vec<NodeID> = self.buckets.iter().flatten().copied().collect();
+        nodes.sort_by_key(|n| n.distance(target));
+        nodes.truncate(count);
+        nodes
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.iter().all(Vec::is_empty)
+    }
+}
+
+/// A value of the DHT, as stored by the nodes closest to its key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DhtRecord {
+    pub value: Vec<u8>,
+    /// The time after which the nodes drop the value.
+    pub expires: u128,
+}
+
+/// The state of the lookup of a value in the DHT.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DhtGet {
+    Pending,
+    Found(DhtRecord),
+    NotFound,
+}
+
+/// An iterative search for the nodes closest to a target.
+#[derive(Debug)]
+pub struct Lookup {
+    target: NodeID,
+    // The node doing the lookup, which is never asked
+    own: NodeID,
+    // All nodes learned during the lookup, by their distance to the target
+    candidates: BTreeMap<U256, Candidate>,
+}
+
+#[derive(Debug)]
+struct Candidate {
+    id: NodeID,
+    // How many requests in a row were needed to learn about this node
+    hops: usize,
+    state: CandidateState,
+}
+
+#[derive(Debug, PartialEq)]
+enum CandidateState {
+    New,
+    Asked { since: u128 },
+    Answered,
+    Failed,
+}
+
+/// The outcome of a lookup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupResult {
+    /// The closest nodes which answered, the closest first.
+    pub closest: Vec<NodeID>,
+    /// How many requests in a row were needed to reach the closest node.
+    pub hops: usize,
+}
+
+impl Lookup {
+    /// Starts a lookup with the closest nodes of the routing table.
+    pub fn new(own: NodeID, target: NodeID, known: Vec<NodeID>) -> Self {
+        let mut lookup = Self {
+            target,
+            own,
+            candidates: BTreeMap::new(),
+        };
+        lookup.add(known, 1);
+        lookup
+    }
+
+    pub fn target(&self) -> NodeID {
+        self.target
+    }
+
+    fn add(&mut self, nodes: Vec<NodeID>, hops: usize) {
+        for id in nodes.into_iter().filter(|id| id != &self.own) {
+            self.candidates
+                .entry(id.distance(&self.target))
+                .or_insert(Candidate {
+                    id,
+                    hops,
+                    state: CandidateState::New,
+                });
+        }
+    }
+
+    // The K closest candidates which didn't fail.
+    fn closest(&self) -> impl Iterator<Item = &Candidate> {
+        self.candidates
+            .values()
+            .filter(|c| c.state != CandidateState::Failed)
+            .take(K)
+    }
+
+    /// Returns the nodes to ask next, keeping at most ALPHA requests in
+    /// flight, and marks them as asked.
+    pub fn next(&mut self, now: u128) -> Vec<NodeID> {
+        let in_flight = self
+            .closest()
+            .filter(|c| matches!(c.state, CandidateState::Asked { .. }))
+            .count();
+        let mut ask = vec![];
+        for candidate in self
+            .candidates
+            .values_mut()
+            .filter(|c| c.state != CandidateState::Failed)
+            .take(K)
+            .filter(|c| c.state == CandidateState::New)
+            .take(ALPHA.saturating_sub(in_flight))
+        {
+            candidate.state = CandidateState::Asked { since: now };
+            ask.push(candidate.id);
+        }
+        ask
+    }
+
+    /// Adds the nodes returned by `from`. Answers of nodes which were not
+    /// asked are ignored, and return false.
+    pub fn answer(&mut self, from: &NodeID, nodes: Vec<NodeID>) -> bool {
+        let Some(candidate) = self.candidates.get_mut(&from.distance(&self.target)) else {
+            return false;
+        };
+        if !matches!(candidate.state, CandidateState::Asked { .. }) {
+            return false;
+        }
+        candidate.state = CandidateState::Answered;
+        let hops = candidate.hops + 1;
+        self.add(nodes, hops);
+        true
+    }
+
+    /// Marks the nodes which didn't answer in time as failed, and returns
+    /// them.
+    pub fn timeout(&mut self, now: u128) -> Vec<NodeID> {
+        self.candidates
+            .values_mut()
+            .filter(
+                |c| matches!(c.state, CandidateState::Asked { since } if since + TIMEOUT <= now),
+            )
+            .map(|c| {
+                c.state = CandidateState::Failed;
+                c.id
+            })
+            .collect()
+    }
+
+    /// Marks the node as failed if it was asked, and returns false
+    /// otherwise.
+    pub fn fail(&mut self, id: &NodeID) -> bool {
+        match self.candidates.get_mut(&id.distance(&self.target)) {
+            Some(c) if matches!(c.state, CandidateState::Asked { .. }) => {
+                c.state = CandidateState::Failed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true once the K closest nodes which didn't fail all answered.
+    pub fn is_done(&self) -> bool {
+        self.closest().all(|c| c.state == CandidateState::Answered)
+    }
+
+    pub fn result(&self) -> LookupResult {
+        let closest: Vec<&Candidate> = self
+            .closest()
+            .filter(|c| c.state == CandidateState::Answered)
+            .collect();
+        LookupResult {
+            hops: closest.first().map_or(0, |c| c.hops),
+            closest: closest.iter().map(|c| c.id).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::faults::{Fault, FaultRequest};
+    use crate::simul::{
+        broker::BMNet,
+        msgs::NodeAction,
+        network::Network,
+        node::Node,
+        node_types::{Hash256, NodeSecret},
+        trusted::{self, Trusted},
+    };
+
+    fn id(n: u64) -> NodeID {
+        U256::from(n).into()
+    }
+
+    // Adds the node to the network, and joins it through the bootstrap node.
+    fn join(network: &mut Network, node: Node, bootstrap: Option<NodeID>) -> NodeID {
+        let id = node.id();
+        network.action(BMNet::NodeAdd(node));
+        if let Some(bootstrap) = bootstrap {
+            network.action(BMNet::NodeAction {
+                id,
+                action: NodeAction::Join { bootstrap },
+            });
+        }
+        id
+    }
+
+    // Returns a network where every node joined through the first one,
+    // and the secrets of the nodes.
+    fn network(nodes: usize) -> (Network, Vec<NodeSecret>) {
+        let trusted = Trusted::new(trusted::Config::default(), 0);
+        let mut network = Network::default();
+        let secrets: Vec<NodeSecret> = (0..nodes).map(|_| NodeSecret::random()).collect();
+        for (i, secret) in secrets.iter().enumerate() {
+            let bootstrap = (i > 0).then(|| secrets[0].into());
+            join(
+                &mut network,
+                Node::from_secret(*secret, &trusted),
+                bootstrap,
+            );
+        }
+        (network, secrets)
+    }
+
+    #[test]
+    fn test_routing_table() {
+        let mut table = RoutingTable::new(id(0));
+        assert_eq!(None, table.seen(id(0)));
+        assert!(table.is_empty());
+
+        // The bucket of the highest bit fills up, and then returns the
+        // least recently seen node.
+        let nodes: Vec<NodeID> = (0..K as u64).map(|n| id(0x100 + n)).collect();
+        for node in &nodes {
+            assert_eq!(None, table.seen(*node));
+        }
+        assert_eq!(Some(nodes[0]), table.seen(id(0x1ff)));
+        assert!(!table.contains(&id(0x1ff)));
+        assert_eq!(None, table.seen(nodes[0]));
+        assert_eq!(Some(nodes[1]), table.seen(id(0x1ff)));
+        assert!(table.remove(&nodes[1]));
+        assert_eq!(None, table.seen(id(0x1ff)));
+
+        assert_eq!(None, table.seen(id(1)));
+        assert_eq!(K + 1, table.len());
+        assert_eq!(vec![id(1), nodes[3]], table.closest(&id(3), 2));
+        assert_eq!(vec![id(0x1ff), nodes[K - 1]], table.closest(&id(0x1ff), 2));
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut lookup = Lookup::new(id(0), id(0xf0), vec![id(0), id(1), id(2), id(3), id(4)]);
+        assert_eq!(vec![id(1), id(2), id(3)], lookup.next(0));
+        assert!(lookup.next(0).is_empty());
+
+        // The answer of a node which wasn't asked is ignored.
+        assert!(!lookup.answer(&id(4), vec![id(0xf0)]));
+        assert!(lookup.answer(&id(3), vec![id(0xf1), id(0)]));
+        assert_eq!(vec![id(0xf1)], lookup.next(10));
+        assert!(lookup.fail(&id(1)));
+        assert!(!lookup.fail(&id(1)));
+        assert_eq!(vec![id(2)], lookup.timeout(TIMEOUT));
+        assert_eq!(vec![id(4)], lookup.next(TIMEOUT));
+        lookup.answer(&id(0xf1), vec![id(0xf0)]);
+        lookup.answer(&id(4), vec![]);
+        assert!(!lookup.is_done());
+        assert_eq!(vec![id(0xf0)], lookup.next(TIMEOUT));
+        lookup.answer(&id(0xf0), vec![]);
+        assert!(lookup.is_done());
+        assert_eq!(
+            LookupResult {
+                closest: vec![id(0xf0), id(0xf1), id(3), id(4)],
+                hops: 3,
+            },
+            lookup.result()
+        );
+    }
+
+    #[test]
+    fn test_network_lookup() {
+        let (mut network, secrets) = network(64);
+        let ids: Vec<NodeID> = secrets.iter().map(|s| (*s).into()).collect();
+        let lookup = |network: &mut Network, from: NodeID, target: NodeID| {
+            network.action(BMNet::NodeAction {
+                id: from,
+                action: NodeAction::FindNode { target },
+            });
+            network.node(&from).unwrap().lookup_result(&target).cloned()
+        };
+
+        // Every node is found in about log2(64) = 6 hops.
+        let mut hops = 0;
+        for (i, from) in ids.iter().enumerate() {
+            let target = ids[(i * 37 + 11) % ids.len()];
+            let result = lookup(&mut network, *from, target).unwrap();
+            if &target != from {
+                assert_eq!(target, result.closest[0]);
+            }
+            assert!(result.hops <= 6, "{} hops", result.hops);
+            hops += result.hops;
+        }
+        assert!(hops <= 4 * ids.len(), "{hops} hops");
+
+        // A node which doesn't answer is dropped after the timeout.
+        let (from, gone) = (ids[1], ids[2]);
+        network
+            .faults_mut()
+            .add(FaultRequest {
+                fault: Fault::Isolate { id: gone },
+                heal_at: None,
+            })
+            .unwrap();
+        assert_eq!(None, lookup(&mut network, from, gone));
+        network.tick(TIMEOUT);
+        let result = network.node(&from).unwrap().lookup_result(&gone).unwrap();
+        assert!(!result.closest.contains(&gone));
+        assert!(!network.node(&from).unwrap().routing().contains(&gone));
+
+        // A node which left the network is dropped right away.
+        network.faults_mut().heal_all();
+        let left = ids[3];
+        network.action(BMNet::NodeDel(left));
+        let result = lookup(&mut network, from, left).unwrap();
+        assert!(!result.closest.contains(&left));
+    }
+
+    #[test]
+    fn test_dht() {
+        let (mut network, secrets) = network(32);
+        let ids: Vec<NodeID> = secrets.iter().map(|s| (*s).into()).collect();
+        let key = Hash256::digest(b"key");
+        let action = |network: &mut Network, id: NodeID, action: NodeAction| {
+            network.action(BMNet::NodeAction { id, action });
+        };
+        let get = |network: &mut Network, id: NodeID| {
+            action(network, id, NodeAction::DhtGet { key });
+            network.node(&id).unwrap().dht_result(&key)
+        };
+        assert_eq!(DhtGet::NotFound, get(&mut network, ids[1]));
+
+        // The value is stored at the K closest nodes.
+        let value = b"value".to_vec();
+        action(
+            &mut network,
+            ids[0],
+            NodeAction::DhtPut {
+                key,
+                value: value.clone(),
+                ttl: 1_000,
+            },
+        );
+        let holders = network
+            .nodes()
+            .filter(|n| n.stored_value(&key).is_some())
+            .count();
+        assert_eq!(K, holders);
+        let record = DhtRecord {
+            value,
+            expires: 1_000,
+        };
+        for id in &ids[1..4] {
+            assert_eq!(DhtGet::Found(record.clone()), get(&mut network, *id));
+        }
+
+        // The closest nodes come back without the value, and get it again
+        // once the others republish it.
+        let mut closest = ids[1..].to_vec();
+        closest.sort_by_key(|id| id.distance(&key.into()));
+        let trusted = Trusted::new(trusted::Config::default(), 0);
+        for id in &closest[..K / 2] {
+            let secret = secrets[ids.iter().position(|i| i == id).unwrap()];
+            network.action(BMNet::NodeDel(*id));
+            join(
+                &mut network,
+                Node::from_secret(secret, &trusted),
+                Some(ids[0]),
+            );
+        }
+        let stored =
+            |network: &Network, id: &NodeID| network.node(id).unwrap().stored_value(&key).cloned();
+        assert!(closest[..K / 2]
+            .iter()
+            .all(|id| stored(&network, id).is_none()));
+        let asker = *closest.last().unwrap();
+        assert_eq!(DhtGet::Found(record.clone()), get(&mut network, asker));
+        for id in &closest[K / 2..K] {
+            action(&mut network, *id, NodeAction::Republish);
+        }
+        for id in &closest[..K] {
+            assert_eq!(Some(record.clone()), stored(&network, id));
+        }
+
+        // Values expire after their TTL.
+        network.tick(1_000);
+        assert!(network.nodes().all(|n| n.stored_value(&key).is_none()));
+        assert_eq!(DhtGet::NotFound, get(&mut network, asker));
+    }
+}
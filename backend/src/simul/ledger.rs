@@ -0,0 +1,426 @@
+// The ledger of Trusted: every tick, all state transitions since the
+// previous tick are sealed in a block, which is linked to its parent
+// through its hash.
+// With a data directory, the sealed blocks are appended to their own file,
+// so that neither the memory nor the snapshots of Trusted grow with them.
+
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+use crate::api::reputation::Category;
+
+use super::node_types::{GroupID, Hash256, Mana, NodeID};
+
+const BLOCKS: &str = "blocks.jsonl";
+
+/// A single change to the state of Trusted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(tag = "type")]
+pub enum Transition {
+    Register { id: NodeID, name: String },
+    Alive { id: NodeID },
+    ManaCredit { id: NodeID, amount: Mana },
+    ManaDebit { id: NodeID, amount: Mana },
+    Remove { id: NodeID },
+    Bankrupt { id: NodeID },
+    Solvent { id: NodeID },
+    Transfer {
+        from: NodeID,
+        to: NodeID,
+        amount: Mana,
+        nonce: u64,
+    },
+    Receipt {
+        viewer: NodeID,
+        server: NodeID,
+        bytes: u64,
+        nonce: u64,
+    },
+    GroupJoin { id: NodeID, group: GroupID },
+    GroupLeave { id: NodeID, group: GroupID },
+    NameRegister {
+        name: String,
+        owner: NodeID,
+        group: GroupID,
+        expires: u128,
+    },
+    NameTransfer {
+        name: String,
+        from: NodeID,
+        to: NodeID,
+    },
+    NameExpire { name: String },
+    Vote {
+        voter: NodeID,
+        group: GroupID,
+        category: Category,
+        flag: bool,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Block {
+    pub height: u64,
+    /// Time of the tick which sealed this block.
+    pub time: u128,
+    /// Hash of the previous block, or zero for the first block.
+    pub parent: Hash256,
+    /// Hash over the state after applying the transitions: the mana and
+    /// activity of all nodes, the deletable content, the groups, the names,
+    /// and the votes.
+    pub state_root: Hash256,
+    pub transitions: Vec<Transition>,
+    /// Hash over all the fields above.
+    pub hash: Hash256,
+}
+
+impl Block {
+    fn new(
+        height: u64,
+        time: u128,
+        parent: Hash256,
+        state_root: Hash256,
+        transitions: Vec<Transition>,
+    ) -> Self {
+        let mut block = Self {
+            height,
+            time,
+            parent,
+            state_root,
+            transitions,
+            hash: Hash256::zero(),
+        };
+        block.hash = block.calc_hash();
+        block
+    }
+
+    /// Calculates the hash of the block, without using the `hash` field.
+    pub fn calc_hash(&self) -> Hash256 {
+        let mut data = vec![];
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.extend_from_slice(&self.time.to_be_bytes());
+        data.extend_from_slice(&self.parent.to_bytes());
+        data.extend_from_slice(&self.state_root.to_bytes());
+        data.extend(serde_json::to_vec(&self.transitions).expect("Transitions serialize"));
+        Hash256::digest(&data)
+    }
+}
+
+/// Overview of the ledger, and whether the chain of blocks is valid.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LedgerStatus {
+    /// Number of sealed blocks.
+    pub height: u64,
+    /// Hash of the latest block.
+    pub head: Option<Hash256>,
+    /// Why the chain is invalid, or None if it is valid.
+    pub error: Option<String>,
+}
+
+/// What needs to be stored in the snapshot of Trusted to continue the
+/// ledger. The sealed blocks are not part of it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LedgerState {
+    height: u64,
+    head: Option<Hash256>,
+    // Transitions for the next block
+    pending: Vec<Transition>,
+}
+
+/// The chain of blocks, which is verified once when it is opened, and then
+/// for every new block, so that the status is always at hand.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    state: LedgerState,
+    blocks: Blocks,
+    // Why the chain is invalid, if it is
+    error: Option<String>,
+}
+
+#[derive(Debug)]
+enum Blocks {
+    /// Without a data directory, the blocks are only kept in memory.
+    Memory(Vec<Block>),
+    /// The blocks are appended to a file, one per line, and only read again
+    /// when they are asked for.
+    File {
+        path: PathBuf,
+        file: File,
+        // The position of every stored block in the file
+        offsets: Vec<u64>,
+        // Where the next block is written
+        end: u64,
+    },
+}
+
+impl Default for Blocks {
+    fn default() -> Self {
+        Blocks::Memory(vec![])
+    }
+}
+
+impl Ledger {
+    /// Opens the blocks stored in the directory, and continues after the
+    /// state of the snapshot of Trusted.
+    /// Stored blocks after the state are kept: replaying the journal of
+    /// Trusted seals them again, and they are checked against each other.
+    pub fn open(dir: &Path, state: LedgerState) -> Result<Self, Box<dyn Error>> {
+        let path = dir.join(BLOCKS);
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        // As in the journal, anything after the last newline has been
+        // interrupted while writing.
+        let end = data.rfind('\n').map(|pos| pos + 1).unwrap_or(0);
+        if end < data.len() {
+            warn!("Ignoring incomplete last block");
+        }
+        let mut error = None;
+        let mut offsets = vec![];
+        let mut parent = Hash256::zero();
+        let mut offset = 0;
+        for line in data[..end].split_inclusive('\n') {
+            let height = offsets.len() as u64;
+            let block: Block = serde_json::from_str(line)
+                .map_err(|e| format!("Corrupt block {height}: {e}"))?;
+            if error.is_none() {
+                error = verify(&block, height, parent).err();
+            }
+            if height + 1 == state.height && state.head != Some(block.hash) {
+                error.get_or_insert(format!("Block {height} is not the head of the snapshot"));
+            }
+            parent = block.hash;
+            offsets.push(offset);
+            offset += line.len() as u64;
+        }
+        if (offsets.len() as u64) < state.height {
+            return Err(format!(
+                "Only {} of {} blocks are stored",
+                offsets.len(),
+                state.height
+            )
+            .into());
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        // Make sure an incomplete last line doesn't get glued to the next block.
+        file.set_len(end as u64)?;
+        Ok(Self {
+            state,
+            blocks: Blocks::File {
+                path,
+                file,
+                offsets,
+                end: end as u64,
+            },
+            error,
+        })
+    }
+
+    /// Adds a transition to the next block.
+    pub fn record(&mut self, transition: Transition) {
+        self.state.pending.push(transition);
+    }
+
+    /// Seals all pending transitions in a new block and returns it.
+    pub fn seal(&mut self, time: u128, state_root: Hash256) -> Block {
+        let block = Block::new(
+            self.height(),
+            time,
+            self.state.head.unwrap_or_default(),
+            state_root,
+            std::mem::take(&mut self.state.pending),
+        );
+        let stored = verify(&block, self.height(), self.state.head.unwrap_or_default())
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|_| self.store(&block));
+        if let Err(e) = stored {
+            error!("While storing block {}: {e}", block.height);
+            self.error.get_or_insert(e.to_string());
+        }
+        self.state.height += 1;
+        self.state.head = Some(block.hash);
+        block
+    }
+
+    // Appends the block to the file. If the block is already stored, because
+    // it is sealed again while replaying the journal, it must be the same.
+    fn store(&mut self, block: &Block) -> Result<(), Box<dyn Error>> {
+        if block.height < self.stored() {
+            return match self.read(block.height) {
+                Ok(stored) if stored.hash == block.hash => Ok(()),
+                Ok(_) => Err(format!("Block {} differs from the stored one", block.height).into()),
+                Err(e) => Err(e),
+            };
+        }
+        let (file, offsets, end) = match &mut self.blocks {
+            Blocks::Memory(blocks) => {
+                blocks.push(block.clone());
+                return Ok(());
+            }
+            Blocks::File {
+                file, offsets, end, ..
+            } => (file, offsets, end),
+        };
+        let mut line = serde_json::to_vec(block)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        file.sync_data()?;
+        offsets.push(*end);
+        *end += line.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the number of sealed blocks.
+    pub fn height(&self) -> u64 {
+        self.state.height
+    }
+
+    pub fn block(&self, height: u64) -> Option<Block> {
+        if height >= self.height() {
+            return None;
+        }
+        self.read(height)
+            .map_err(|e| error!("While reading block {height}: {e}"))
+            .ok()
+    }
+
+    // Returns the number of blocks in memory or in the file, which can be
+    // more than the height while the journal is replayed.
+    fn stored(&self) -> u64 {
+        match &self.blocks {
+            Blocks::Memory(blocks) => blocks.len() as u64,
+            Blocks::File { offsets, .. } => offsets.len() as u64,
+        }
+    }
+
+    // Returns the stored block at the given height.
+    fn read(&self, height: u64) -> Result<Block, Box<dyn Error>> {
+        let index = usize::try_from(height)?;
+        match &self.blocks {
+            Blocks::Memory(blocks) => blocks.get(index).cloned().ok_or("No such block".into()),
+            Blocks::File { path, offsets, .. } => {
+                let offset = *offsets.get(index).ok_or("No such block")?;
+                let mut reader = BufReader::new(File::open(path)?);
+                reader.seek(SeekFrom::Start(offset))?;
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                Ok(serde_json::from_str(&line)?)
+            }
+        }
+    }
+
+    /// Returns the state to store in the snapshot of Trusted.
+    pub fn state(&self) -> LedgerState {
+        self.state.clone()
+    }
+
+    pub fn status(&self) -> LedgerStatus {
+        LedgerStatus {
+            height: self.height(),
+            head: self.state.head,
+            error: self.error.clone(),
+        }
+    }
+}
+
+// Verifies the height, the hash, and the parent of the block.
+fn verify(block: &Block, height: u64, parent: Hash256) -> Result<(), String> {
+    if block.height != height {
+        return Err(format!("Block {height} has height {}", block.height));
+    }
+    if block.parent != parent {
+        return Err(format!("Block {height} has wrong parent"));
+    }
+    if block.hash != block.calc_hash() {
+        return Err(format!("Block {height} has wrong hash"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type ResErr = Result<(), Box<dyn Error>>;
+
+    // Seals a block with an 'Alive' transition of the node.
+    fn seal_alive(ledger: &mut Ledger, id: NodeID, time: u128) -> Block {
+        ledger.record(Transition::Alive { id });
+        ledger.seal(time, Hash256::digest(&time.to_be_bytes()))
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut ledger = Ledger::default();
+        let id = NodeID::random();
+        ledger.record(Transition::Register {
+            id,
+            name: "node".into(),
+        });
+        let first = ledger.seal(1, Hash256::digest(b"state1"));
+        assert_eq!(Hash256::zero(), first.parent);
+        assert_eq!(1, first.transitions.len());
+
+        let second = seal_alive(&mut ledger, id, 2);
+        assert_eq!(first.hash, second.parent);
+        assert_eq!(2, ledger.height());
+        assert_eq!(Some(second.clone()), ledger.block(1));
+        assert_eq!(None, ledger.block(2));
+        assert_eq!(None, ledger.status().error);
+        assert_eq!(Some(second.hash), ledger.status().head);
+    }
+
+    #[test]
+    fn test_file() -> ResErr {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        let id = NodeID::random();
+        let mut ledger = Ledger::open(dir, LedgerState::default())?;
+        seal_alive(&mut ledger, id, 1);
+        let snapshot = ledger.state();
+        let second = seal_alive(&mut ledger, id, 2);
+        drop(ledger);
+
+        // Replaying the second block after the snapshot doesn't store it
+        // twice.
+        let mut ledger = Ledger::open(dir, snapshot.clone())?;
+        assert_eq!(1, ledger.height());
+        assert_eq!(None, ledger.block(1));
+        assert_eq!(second, seal_alive(&mut ledger, id, 2));
+        let third = seal_alive(&mut ledger, id, 3);
+        assert_eq!(Some(third.clone()), ledger.block(2));
+        assert_eq!(None, ledger.status().error);
+        let state = ledger.state();
+        drop(ledger);
+        let ledger = Ledger::open(dir, state.clone())?;
+        assert_eq!(Some(second), ledger.block(1));
+        assert_eq!(Some(third.hash), ledger.status().head);
+        assert_eq!(None, ledger.status().error);
+
+        // A replayed block which differs from the stored one breaks the chain.
+        let mut ledger = Ledger::open(dir, snapshot)?;
+        seal_alive(&mut ledger, NodeID::random(), 2);
+        assert!(ledger.status().error.is_some());
+
+        // Changing a stored transition breaks the chain.
+        let blocks = fs::read_to_string(dir.join(BLOCKS))?;
+        fs::write(dir.join(BLOCKS), blocks.replacen("Alive", "Remove", 1))?;
+        assert!(Ledger::open(dir, state.clone())?.status().error.is_some());
+
+        // Missing blocks cannot be recovered.
+        fs::write(dir.join(BLOCKS), "")?;
+        assert!(Ledger::open(dir, state).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,456 @@
+// The rules how the mana of the nodes changes over time.
+// Trusted asks its policy on every tick what to credit and debit for every node,
+// and then applies the changes.
+
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use super::{node_types::Mana, trusted::Config};
+
+/// What a node offers to the network, and what it uses from it, as
+/// reported by the node.
+/// The simulated nodes report their storage quota when they register, but
+/// neither bandwidth nor CPU, so `Contribution` only rewards storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Resources {
+    /// Bytes of storage offered to other nodes.
+    pub storage: u64,
+    /// Bytes per second offered to other nodes.
+    pub bandwidth: u64,
+    /// Milliseconds of CPU time per second offered to other nodes.
+    pub cpu: u64,
+    /// Bytes of content of this node stored by other nodes. Trusted sets it
+    /// from `TReqMsg::Stored`, not from what the node reports.
+    pub stored: u64,
+}
+
+/// The view of a node a policy bases its decision on.
+#[derive(Debug, Clone, Copy)]
+pub struct NodeState {
+    pub mana: Mana,
+    pub active: bool,
+    pub resources: Resources,
+}
+
+/// How much mana to add to and remove from a node.
+/// If the debit is bigger than the mana of an inactive node, the node is
+/// removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ManaChange {
+    pub credit: Mana,
+    pub debit: Mana,
+}
+
+/// A ManaPolicy decides how the mana of a node changes between two ticks.
+/// Policies only depend on their arguments, so that replaying the journal
+/// of Trusted gives the same result.
+pub trait ManaPolicy: Debug + Send {
+    /// Returns the change of mana of the node between the tick at time `last`
+    /// and the tick at time `now`.
+    fn change(&self, node: &NodeState, last: u128, now: u128) -> ManaChange;
+}
+
+/// Selects the `ManaPolicy` of Trusted.
+#[derive(Debug, Clone, Default)]
+pub enum ManaPolicyConfig {
+    /// The original rules: +1 for every `Config::time_mana_increase` while
+    /// active, -1 for every `Config::time_mana_decrease` while inactive.
+    #[default]
+    Time,
+    /// Every `period`, active nodes get 1 mana per unit of contributed storage,
+    /// bandwidth, and CPU. Units of 0 are not rewarded, and a period of 0
+    /// disables the policy.
+    Contribution {
+        period: u128,
+        storage_unit: u64,
+        bandwidth_unit: u64,
+        cpu_unit: u64,
+    },
+    /// Every `period`, all nodes pay 1 mana per started `storage_unit` of
+    /// their content stored in the network. A period or unit of 0 disables
+    /// the rent.
+    StorageRent { period: u128, storage_unit: u64 },
+    /// Every `period`, inactive nodes lose `permille` of their mana, but at
+    /// least 1. A period of 0 disables the decay.
+    Decay { period: u128, permille: u32 },
+    /// Limits the mana the inner policy credits to `max`.
+    Capped {
+        policy: Box<ManaPolicyConfig>,
+        max: Mana,
+    },
+    /// Adds up the changes of all policies.
+    Combined(Vec<ManaPolicyConfig>),
+}
+
+impl ManaPolicyConfig {
+    /// Creates the policy, taking the times of `Time` from the config.
+    pub fn build(&self, config: &Config) -> Box<dyn ManaPolicy> {
+        match self {
+            ManaPolicyConfig::Time => Box::new(TimePolicy {
+                increase: config.time_mana_increase,
+                decrease: config.time_mana_decrease,
+            }),
+            ManaPolicyConfig::Contribution {
+                period,
+                storage_unit,
+                bandwidth_unit,
+                cpu_unit,
+            } => Box::new(ContributionPolicy {
+                period: *period,
+                storage_unit: *storage_unit,
+                bandwidth_unit: *bandwidth_unit,
+                cpu_unit: *cpu_unit,
+            }),
+            ManaPolicyConfig::StorageRent {
+                period,
+                storage_unit,
+            } => Box::new(StorageRentPolicy {
+                period: *period,
+                storage_unit: *storage_unit,
+            }),
+            ManaPolicyConfig::Decay { period, permille } => Box::new(DecayPolicy {
+                period: *period,
+                permille: *permille,
+            }),
+            ManaPolicyConfig::Capped { policy, max } => Box::new(CappedPolicy {
+                policy: policy.build(config),
+                max: *max,
+            }),
+            ManaPolicyConfig::Combined(policies) => Box::new(CombinedPolicy {
+                policies: policies.iter().map(|p| p.build(config)).collect(),
+            }),
+        }
+    }
+}
+
+// Returns how many multiples of `period` lie in (last, now].
+// Counting the multiples instead of the elapsed time makes sure that no
+// fractions of a period get lost between ticks.
+// A period of 0 disables the policy, so there are no multiples.
+fn periods(period: u128, last: u128, now: u128) -> u128 {
+    if period == 0 {
+        return 0;
+    }
+    (now / period).saturating_sub(last / period)
+}
+
+#[derive(Debug)]
+struct TimePolicy {
+    increase: u128,
+    decrease: u128,
+}
+
+impl ManaPolicy for TimePolicy {
+    fn change(&self, node: &NodeState, last: u128, now: u128) -> ManaChange {
+        if node.active {
+            ManaChange {
+                credit: periods(self.increase, last, now).into(),
+                ..Default::default()
+            }
+        } else {
+            ManaChange {
+                debit: periods(self.decrease, last, now).into(),
+                ..Default::default()
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ContributionPolicy {
+    period: u128,
+    storage_unit: u64,
+    bandwidth_unit: u64,
+    cpu_unit: u64,
+}
+
+impl ManaPolicy for ContributionPolicy {
+    fn change(&self, node: &NodeState, last: u128, now: u128) -> ManaChange {
+        if !node.active {
+            return ManaChange::default();
+        }
+        let units = |amount: u64, unit: u64| amount.checked_div(unit).unwrap_or(0) as u128;
+        let res = &node.resources;
+        let per_period = units(res.storage, self.storage_unit)
+            + units(res.bandwidth, self.bandwidth_unit)
+            + units(res.cpu, self.cpu_unit);
+        ManaChange {
+            credit: (per_period * periods(self.period, last, now)).into(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+struct StorageRentPolicy {
+    period: u128,
+    storage_unit: u64,
+}
+
+impl ManaPolicy for StorageRentPolicy {
+    fn change(&self, node: &NodeState, last: u128, now: u128) -> ManaChange {
+        if self.storage_unit == 0 {
+            return ManaChange::default();
+        }
+        let per_period = node.resources.stored.div_ceil(self.storage_unit) as u128;
+        ManaChange {
+            debit: (per_period * periods(self.period, last, now)).into(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DecayPolicy {
+    period: u128,
+    permille: u32,
+}
+
+impl ManaPolicy for DecayPolicy {
+    fn change(&self, node: &NodeState, last: u128, now: u128) -> ManaChange {
+        if node.active {
+            return ManaChange::default();
+        }
+        let mut mana = node.mana;
+        let mut debit = Mana::zero();
+        for _ in 0..periods(self.period, last, now) {
+            let mut loss = mana.scale(self.permille.into(), 1_000);
+            if loss == Mana::zero() {
+                loss = 1.into();
+            }
+            debit += loss;
+            if mana < loss {
+                // The node will be removed, no need to go on.
+                break;
+            }
+            mana -= loss;
+        }
+        ManaChange {
+            debit,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CappedPolicy {
+    policy: Box<dyn ManaPolicy>,
+    max: Mana,
+}
+
+impl ManaPolicy for CappedPolicy {
+    fn change(&self, node: &NodeState, last: u128, now: u128) -> ManaChange {
+        let mut change = self.policy.change(node, last, now);
+        let mut room = Mana::zero();
+        if node.mana < self.max {
+            room = self.max;
+            room -= node.mana;
+        }
+        if change.credit > room {
+            change.credit = room;
+        }
+        change
+    }
+}
+
+#[derive(Debug)]
+struct CombinedPolicy {
+    policies: Vec<Box<dyn ManaPolicy>>,
+}
+
+impl ManaPolicy for CombinedPolicy {
+    fn change(&self, node: &NodeState, last: u128, now: u128) -> ManaChange {
+        let mut total = ManaChange::default();
+        for policy in &self.policies {
+            let change = policy.change(node, last, now);
+            total.credit += change.credit;
+            total.debit += change.debit;
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(mana: u128, active: bool, resources: Resources) -> NodeState {
+        NodeState {
+            mana: mana.into(),
+            active,
+            resources,
+        }
+    }
+
+    fn credit(amount: u128) -> ManaChange {
+        ManaChange {
+            credit: amount.into(),
+            ..Default::default()
+        }
+    }
+
+    fn debit(amount: u128) -> ManaChange {
+        ManaChange {
+            debit: amount.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_periods() {
+        assert_eq!(0, periods(10, 0, 9));
+        assert_eq!(1, periods(10, 9, 10));
+        assert_eq!(0, periods(10, 10, 19));
+        assert_eq!(3, periods(10, 5, 35));
+        assert_eq!(0, periods(0, 5, 35));
+    }
+
+    #[test]
+    fn test_zero_period() {
+        let resources = Resources {
+            storage: 1_000,
+            stored: 1_000,
+            ..Default::default()
+        };
+        let policies = [
+            ManaPolicyConfig::Contribution {
+                period: 0,
+                storage_unit: 1,
+                bandwidth_unit: 1,
+                cpu_unit: 1,
+            },
+            ManaPolicyConfig::StorageRent {
+                period: 0,
+                storage_unit: 1,
+            },
+            ManaPolicyConfig::Decay {
+                period: 0,
+                permille: 100,
+            },
+        ];
+        for policy in policies {
+            let policy = policy.build(&Config::default());
+            for active in [true, false] {
+                let change = policy.change(&node(1_000, active, resources), 0, 1_000);
+                assert_eq!(ManaChange::default(), change);
+            }
+        }
+    }
+
+    #[test]
+    fn test_time() {
+        let config = Config::default();
+        let policy = ManaPolicyConfig::Time.build(&config);
+        let active = node(10, true, Resources::default());
+        let inactive = node(10, false, Resources::default());
+
+        let now = 5 * config.time_mana_increase;
+        assert_eq!(credit(5), policy.change(&active, 0, now));
+        assert_eq!(ManaChange::default(), policy.change(&inactive, 0, now));
+        let now = 2 * config.time_mana_decrease;
+        assert_eq!(debit(2), policy.change(&inactive, 0, now));
+    }
+
+    #[test]
+    fn test_contribution() {
+        let policy = ManaPolicyConfig::Contribution {
+            period: 10,
+            storage_unit: 1_000,
+            bandwidth_unit: 100,
+            cpu_unit: 0,
+        }
+        .build(&Config::default());
+        let resources = Resources {
+            storage: 2_500,
+            bandwidth: 300,
+            cpu: 1_000,
+            stored: 0,
+        };
+
+        // 2 for the storage, 3 for the bandwidth, nothing for the CPU.
+        assert_eq!(credit(5), policy.change(&node(0, true, resources), 0, 10));
+        assert_eq!(credit(10), policy.change(&node(0, true, resources), 0, 20));
+        let change = policy.change(&node(0, false, resources), 0, 20);
+        assert_eq!(ManaChange::default(), change);
+        let change = policy.change(&node(0, true, Resources::default()), 0, 20);
+        assert_eq!(ManaChange::default(), change);
+    }
+
+    #[test]
+    fn test_storage_rent() {
+        let policy = ManaPolicyConfig::StorageRent {
+            period: 10,
+            storage_unit: 1_000,
+        }
+        .build(&Config::default());
+        let resources = Resources {
+            stored: 1_001,
+            ..Default::default()
+        };
+
+        // Started units are charged, whether the node is active or not.
+        assert_eq!(debit(2), policy.change(&node(10, true, resources), 0, 10));
+        assert_eq!(debit(6), policy.change(&node(10, false, resources), 5, 35));
+        let change = policy.change(&node(10, true, Resources::default()), 0, 10);
+        assert_eq!(ManaChange::default(), change);
+    }
+
+    #[test]
+    fn test_decay() {
+        let policy = ManaPolicyConfig::Decay {
+            period: 10,
+            permille: 100,
+        }
+        .build(&Config::default());
+
+        // 10% of 1000, then 10% of 900.
+        assert_eq!(
+            debit(190),
+            policy.change(&node(1_000, false, Resources::default()), 0, 20)
+        );
+        assert_eq!(
+            ManaChange::default(),
+            policy.change(&node(1_000, true, Resources::default()), 0, 20)
+        );
+
+        // At least 1 is lost, so that the node is removed eventually.
+        assert_eq!(
+            debit(2),
+            policy.change(&node(1, false, Resources::default()), 0, 100)
+        );
+    }
+
+    #[test]
+    fn test_capped_combined() {
+        let config = Config::default();
+        let policy = ManaPolicyConfig::Capped {
+            policy: Box::new(ManaPolicyConfig::Combined(vec![
+                ManaPolicyConfig::Time,
+                ManaPolicyConfig::StorageRent {
+                    period: config.time_mana_increase,
+                    storage_unit: 1,
+                },
+            ])),
+            max: 10.into(),
+        }
+        .build(&config);
+        let resources = Resources {
+            stored: 1,
+            ..Default::default()
+        };
+        let now = 5 * config.time_mana_increase;
+
+        let expected = ManaChange {
+            credit: 5.into(),
+            debit: 5.into(),
+        };
+        assert_eq!(expected, policy.change(&node(0, true, resources), 0, now));
+        let expected = ManaChange {
+            credit: 2.into(),
+            debit: 5.into(),
+        };
+        assert_eq!(expected, policy.change(&node(8, true, resources), 0, now));
+        assert_eq!(debit(5), policy.change(&node(12, true, resources), 0, now));
+    }
+}
@@ -1,7 +1,16 @@
 pub mod broker;
+pub mod chunk;
+pub mod faults;
+pub mod journal;
+pub mod kademlia;
+pub mod ledger;
+pub mod mana_policy;
 pub mod network;
 pub mod node;
 pub mod node_types;
+pub mod page;
+pub mod replication;
+pub mod reputation;
 pub mod msgs;
 pub mod simulator;
 pub mod trusted;
@@ -1,8 +1,39 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum NodeAction {}
+use super::{
+    node::Msg,
+    node_types::{Hash256, NodeID},
+    page::PageFile,
+};
+
+/// What the broker can ask a node in the network to do.
+#[derive(Debug)]
+pub enum NodeAction {
+    /// Stores a file in a domain of the node.
+    Store(PageFile),
+    /// Copies the domain from the node `from`, replacing the domain if
+    /// the node already has it.
+    Fetch { domain: String, from: NodeID },
+    /// Sends a message from the node.
+    Send { to: NodeID, msg: Msg },
+    /// Joins the network through the `bootstrap` node, by looking up the
+    /// nodes closest to its own ID.
+    Join { bootstrap: NodeID },
+    /// Looks up the nodes closest to the target.
+    FindNode { target: NodeID },
+    /// Stores the value at the nodes closest to the key, until `ttl`
+    /// milliseconds from now.
+    DhtPut {
+        key: Hash256,
+        value: Vec<u8>,
+        ttl: u128,
+    },
+    /// Looks up the value of the key in the DHT.
+    DhtGet { key: Hash256 },
+    /// Stores the values held by the node again at the nodes closest to
+    /// their keys, which might have changed with nodes coming and going.
+    Republish,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum NodeRequest {}
-
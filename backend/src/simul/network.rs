@@ -1,66 +1,515 @@
-use std::collections::HashMap;
+// The network delivers the messages between the nodes.
+// Every message is put in a queue ordered by the time it arrives at the
+// receiver, which depends on the latency of the links of both nodes, and on
+// the bandwidth of the slower node. Messages can also get lost on the way.
+// By default, links are instant and reliable.
+// On top of this, `Faults` can be injected to split the network or to drop
+// and duplicate messages.
 
-use tracing::{debug, trace};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+
+use rand::{random, Rng};
+use tracing::{debug, trace, warn};
 
 use super::{
-    broker::{BMNet, BrokerMsg},
-    node::{Node, NodeMsg}, node_types::NodeID,
+    broker::{BMNet, BrokerEvent, BrokerMsg},
+    faults::Faults,
+    node::{Node, NodeClass, NodeMsg},
+    node_types::NodeID,
 };
 
+/// The 1 Mbps every node shares, in bytes per second.
+pub const BANDWIDTH_1MBPS: u64 = 125_000;
+
+/// How the links of the nodes of every class behave.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub root: LinkConfig,
+    pub flex: LinkConfig,
+    pub browser: LinkConfig,
+}
+
+impl Config {
+    pub fn link(&self, class: NodeClass) -> &LinkConfig {
+        match class {
+            NodeClass::Root => &self.root,
+            NodeClass::Flex => &self.flex,
+            NodeClass::Browser => &self.browser,
+        }
+    }
+}
+
+/// The link of a node to the network.
+#[derive(Debug, Clone, Default)]
+pub struct LinkConfig {
+    // The time a message needs from the node to the network, or back.
+    pub latency: Latency,
+    // Bytes per second the node can send and receive. 0 means no limit.
+    pub bandwidth: u64,
+    // The probability (0..2**16-1) for a message to get lost on this link.
+    pub loss: u16,
+}
+
+/// A distribution of latencies, in milliseconds.
+#[derive(Debug, Clone)]
+pub enum Latency {
+    Fixed(u128),
+    Uniform {
+        min: u128,
+        max: u128,
+    },
+    /// `min` plus an exponentially distributed delay with the given mean.
+    Exponential {
+        min: u128,
+        mean: u128,
+    },
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Fixed(0)
+    }
+}
+
+impl Latency {
+    pub fn sample(&self, rng: &mut impl Rng) -> u128 {
+        match self {
+            Latency::Fixed(latency) => *latency,
+            Latency::Uniform { min, max } => rng.gen_range(*min..=*max),
+            Latency::Exponential { min, mean } => {
+                let delay = -(1.0 - rng.gen::<f64>()).ln() * *mean as f64;
+                min + delay as u128
+            }
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct Network {
+    config: Config,
     nodes: HashMap<NodeID, Node>,
+    // Messages on their way, by arrival time and the order they were sent
+    queue: BTreeMap<(u128, u64), NodeMsg>,
+    // How many messages have been sent
+    sent: u64,
+    // Until when every node is busy sending earlier messages
+    busy: HashMap<NodeID, u128>,
+    // Latest tick time
+    now: u128,
+    faults: Faults,
 }
 
 impl Network {
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
         Self {
-            nodes: HashMap::new(),
+            config,
+            ..Default::default()
         }
     }
 
     pub fn action(&mut self, action: BMNet) -> Vec<BrokerMsg> {
         match action {
             BMNet::NodeAdd(n) => {
-                if !self.nodes.contains_key(&n.id()) {
+                if let Entry::Vacant(e) = self.nodes.entry(n.id()) {
                     debug!("Adding node {}", n.info());
-                    self.nodes.insert(n.id(), n);
+                    e.insert(n);
                 } else {
                     debug!("Node already present: {}", n.info());
                 }
             }
-            _ => {}
+            BMNet::NodeDel(id) => {
+                if self.nodes.remove(&id).is_some() {
+                    debug!("Removing node {id}");
+                }
+                self.busy.remove(&id);
+                return self.fail_msgs(&id);
+            }
+            BMNet::NodeMsg(msg) => return self.process_msgs(vec![msg]),
+            BMNet::NodeAction { id, action } => match self.nodes.get_mut(&id) {
+                Some(node) => {
+                    let msgs = node.action(action);
+                    return self.process_msgs(msgs);
+                }
+                None => warn!("Dropping {action:?} for unknown node {id}"),
+            },
         }
         vec![]
     }
 
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    pub fn node(&self, id: &NodeID) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
+    pub fn node_mut(&mut self, id: &NodeID) -> Option<&mut Node> {
+        self.nodes.get_mut(id)
+    }
+
+    /// Returns the owner of the domain, if a node hosts it.
+    pub fn domain_owner(&self, domain: &str) -> Option<NodeID> {
+        self.nodes.values().find_map(|n| n.storage().owner(domain))
+    }
+
+    /// Removes the domain from all nodes.
+    pub fn remove_domain(&mut self, domain: &str) {
+        for node in self.nodes.values_mut() {
+            node.storage_mut().remove_domain(domain);
+        }
+    }
+
+    /// Returns the owner and the size of every domain in the network.
+    /// If the copies differ, the biggest one is counted.
+    pub fn domains(&self) -> BTreeMap<String, (NodeID, u64)> {
+        let mut domains: BTreeMap<String, (NodeID, u64)> = BTreeMap::new();
+        for storage in self.nodes.values().map(|n| n.storage()) {
+            for domain in storage.domains() {
+                let owner = storage.owner(&domain).expect("domain is stored");
+                let size = storage.manifest(&domain).map(|m| m.size()).unwrap_or(0);
+                let entry = domains.entry(domain).or_insert((owner, size));
+                entry.1 = entry.1.max(size);
+            }
+        }
+        domains
+    }
+
+    /// Returns the sorted IDs of all nodes hosting pages of the domain.
+    pub fn holders(&self, domain: &str) -> Vec<NodeID> {
+        let mut ids: Vec<NodeID> = self
+            .nodes
+            .values()
+            .filter(|n| n.has_domain(domain))
+            .map(|n| n.id())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    pub fn faults_mut(&mut self) -> &mut Faults {
+        &mut self.faults
+    }
+
+    /// Returns the number of messages on their way.
+    pub fn in_flight(&self) -> usize {
+        self.queue.len()
+    }
+
     pub fn tick(&mut self, now: u128) -> Vec<BrokerMsg> {
+        self.now = now;
+        self.faults.tick(now);
         let mut msgs = vec![];
         for node in self.nodes.values_mut() {
             msgs.append(&mut node.tick(now));
         }
-        self.process_msgs(msgs);
-
-        vec![]
+        self.process_msgs(msgs)
     }
 
-    fn process_msgs(&mut self, mut msgs: Vec<NodeMsg>) {
+    // Sends the messages, and delivers all messages which arrive until now,
+    // including the replies of the nodes.
+    // Every message is reported as a BrokerEvent, either as delivered or as
+    // undelivered if the receiver is not in the network.
+    fn process_msgs(&mut self, msgs: Vec<NodeMsg>) -> Vec<BrokerMsg> {
         debug!("Processing {} messages.", msgs.len());
-        while let Some(msg) = msgs.pop() {
-            msgs.append(&mut self.send_msg(msg));
+        for msg in msgs {
+            self.send_msg(msg, self.now);
         }
+        let mut events = vec![];
+        while let Some(entry) = self.queue.first_entry() {
+            let time = entry.key().0;
+            if time > self.now {
+                break;
+            }
+            let msg = entry.remove();
+            if self.faults.cut(&msg) {
+                debug!("Fault dropped message on its way {msg:?}");
+                continue;
+            }
+            let Some(node) = self.nodes.get_mut(&msg.to) else {
+                debug!("Node {} is not in the network", msg.to);
+                // The sender notices that it cannot connect to the receiver.
+                if let Some(sender) = self.nodes.get_mut(&msg.from) {
+                    for reply in sender.undelivered(&msg) {
+                        self.send_msg(reply, time);
+                    }
+                }
+                events.push(BrokerEvent::Undelivered(msg).into());
+                continue;
+            };
+            trace!("Delivering {msg:?} at {time}");
+            events.push(BrokerEvent::NodeMsg(msg.clone()).into());
+            for reply in node.receive(msg) {
+                self.send_msg(reply, time);
+            }
+        }
+        events
     }
 
-    // Sends a message to the corresponding node.
-    // If the node is in 'nodes_flex' and it's offline, the message will silently
-    // be dropped.
-    fn send_msg(&mut self, msg: NodeMsg) -> Vec<NodeMsg> {
-        if let Some(node) = self.nodes.get_mut(&msg.to) {
-            trace!("Sending {msg:?}");
-            return node.receive(msg);
+    // Puts the message in the queue, to arrive after it has been sent over
+    // the links of both nodes.
+    // Messages which are not signed by their sender are dropped, as well
+    // as the ones lost on one of the links or dropped by a fault.
+    fn send_msg(&mut self, msg: NodeMsg, time: u128) {
+        let Some(from) = self.nodes.get(&msg.from) else {
+            warn!("Dropping message of unknown sender: {msg:?}");
+            return;
+        };
+        if !msg.verify(&from.public_key()) {
+            warn!("Dropping message with invalid signature: {msg:?}");
+            return;
+        }
+        let mut links = vec![self.config.link(from.class())];
+        if let Some(to) = self.nodes.get(&msg.to) {
+            links.push(self.config.link(to.class()));
+        }
+        if links.iter().any(|link| link.loss > random::<u16>()) {
+            debug!("Lost message {msg:?}");
+            return;
+        }
+        let copies = self.faults.copies(&msg);
+        if copies == 0 {
+            debug!("Fault dropped message {msg:?}");
+            return;
+        }
+
+        // The slower node decides how long the message takes to be sent,
+        // and the sender sends one message after the other.
+        let mut rng = rand::thread_rng();
+        let latency: u128 = links.iter().map(|l| l.latency.sample(&mut rng)).sum();
+        let mut sent = self.busy.get(&msg.from).map_or(time, |b| time.max(*b));
+        if let Some(bandwidth) = links.iter().map(|l| l.bandwidth).filter(|b| *b > 0).min() {
+            let size = serde_json::to_vec(&msg)
+                .expect("NodeMsg serializes to JSON")
+                .len();
+            sent += (size as u128 * 1_000).div_ceil(bandwidth as u128);
+            self.busy.insert(msg.from, sent);
+        }
+        for _ in 0..copies {
+            self.queue.insert((sent + latency, self.sent), msg.clone());
+            self.sent += 1;
         }
-        vec![]
+    }
+
+    // Removes the messages on their way to the node, and reports them as
+    // undelivered, both to the broker and to their senders.
+    fn fail_msgs(&mut self, id: &NodeID) -> Vec<BrokerMsg> {
+        let (failed, queue): (BTreeMap<_, _>, _) = std::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|(_, msg)| &msg.to == id);
+        self.queue = queue;
+        let mut events = vec![];
+        let mut replies = vec![];
+        for msg in failed.into_values() {
+            if let Some(sender) = self.nodes.get_mut(&msg.from) {
+                replies.append(&mut sender.undelivered(&msg));
+            }
+            events.push(BrokerEvent::Undelivered(msg).into());
+        }
+        events.append(&mut self.process_msgs(replies));
+        events
     }
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+    use crate::api::faults::{Fault, FaultRequest};
+    use crate::simul::{
+        msgs::NodeAction,
+        node::Msg,
+        node_types::NodeSecret,
+        page::{Page, PageFile},
+        trusted::{self, Trusted},
+    };
+
+    #[test]
+    fn test_signed_msgs() {
+        let trusted = Trusted::new(trusted::Config::default(), 0);
+        let mut network = Network::default();
+        let secret1 = NodeSecret::random();
+        let node1 = Node::from_secret(secret1, &trusted);
+        let node2 = Node::new(&trusted);
+        let (id1, id2) = (node1.id(), node2.id());
+        network.action(BMNet::NodeAdd(node1));
+        network.action(BMNet::NodeAdd(node2));
+
+        // The ping and the pong are delivered.
+        let events = network.process_msgs(vec![NodeMsg::new(&secret1, id2, Msg::Ping)]);
+        assert_eq!(2, events.len());
+
+        // Messages with a forged sender are dropped.
+        let mut forged = NodeMsg::new(&NodeSecret::random(), id2, Msg::Ping);
+        forged.from = id1;
+        assert!(network.process_msgs(vec![forged]).is_empty());
+    }
+
+    #[test]
+    fn test_node_del_action() {
+        let trusted = Trusted::new(trusted::Config::default(), 0);
+        let mut network = Network::default();
+        let node1 = Node::new(&trusted);
+        let node2 = Node::new(&trusted);
+        let (id1, id2) = (node1.id(), node2.id());
+        network.action(BMNet::NodeAdd(node1));
+        network.action(BMNet::NodeAdd(node2));
+        let ping = |id| BMNet::NodeAction {
+            id,
+            action: NodeAction::Send {
+                to: id2,
+                msg: Msg::Ping,
+            },
+        };
+
+        let events = network.action(ping(id1));
+        assert_eq!(2, events.len());
+        network.action(BMNet::NodeAction {
+            id: id1,
+            action: NodeAction::Store(PageFile {
+                domain: "ineiti".into(),
+                path: "index.html".into(),
+                page: Page::new("index.html", b"<h1>Hi</h1>".to_vec()),
+            }),
+        });
+        assert_eq!(vec![id1], network.holders("ineiti"));
+
+        // Messages to a removed node fail, and removed nodes cannot act.
+        network.action(BMNet::NodeDel(id2));
+        let events = network.action(ping(id1));
+        assert_matches!(&events[..], [BrokerMsg::Event(BrokerEvent::Undelivered(msg))]
+            if msg.to == id2);
+        assert!(network.action(ping(id2)).is_empty());
+    }
+
+    #[test]
+    fn test_node_del_undelivered() {
+        let trusted = Trusted::new(trusted::Config::default(), 0);
+        let mut network = Network::new(Config {
+            flex: LinkConfig {
+                latency: Latency::Fixed(50),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let secret1 = NodeSecret::random();
+        let node1 = Node::from_secret(secret1, &trusted).with_class(NodeClass::Flex);
+        let node2 = Node::new(&trusted).with_class(NodeClass::Flex);
+        let (id1, id2) = (node1.id(), node2.id());
+        network.action(BMNet::NodeAdd(node1));
+        network.action(BMNet::NodeAdd(node2));
+        network.process_msgs(vec![NodeMsg::new(&secret1, id2, Msg::Ping)]);
+        network.tick(200);
+        assert!(network.node(&id1).unwrap().routing().contains(&id2));
+
+        // The sender of a message on its way to a removed node forgets it.
+        network.process_msgs(vec![NodeMsg::new(&secret1, id2, Msg::Ping)]);
+        let events = network.action(BMNet::NodeDel(id2));
+        assert_matches!(&events[..], [BrokerMsg::Event(BrokerEvent::Undelivered(msg))]
+            if msg.to == id2);
+        assert!(!network.node(&id1).unwrap().routing().contains(&id2));
+    }
+
+    #[test]
+    fn test_partition_in_flight() {
+        let trusted = Trusted::new(trusted::Config::default(), 0);
+        let mut network = Network::new(Config {
+            flex: LinkConfig {
+                latency: Latency::Fixed(50),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let secret1 = NodeSecret::random();
+        let node1 = Node::from_secret(secret1, &trusted).with_class(NodeClass::Flex);
+        let node2 = Node::new(&trusted).with_class(NodeClass::Flex);
+        let (id1, id2) = (node1.id(), node2.id());
+        network.action(BMNet::NodeAdd(node1));
+        network.action(BMNet::NodeAdd(node2));
+
+        // A message sent before the partition doesn't get through it.
+        network.process_msgs(vec![NodeMsg::new(&secret1, id2, Msg::Ping)]);
+        network
+            .faults_mut()
+            .add(FaultRequest {
+                fault: Fault::Partition {
+                    partitions: vec![vec![id1]],
+                },
+                heal_at: None,
+            })
+            .unwrap();
+        assert!(network.tick(200).is_empty());
+        assert_eq!(0, network.in_flight());
+    }
+
+    fn pings(events: &[BrokerMsg]) -> usize {
+        events
+            .iter()
+            .filter(
+                |e| matches!(e, BrokerMsg::Event(BrokerEvent::NodeMsg(m)) if m.msg == Msg::Ping),
+            )
+            .count()
+    }
+
+    #[test]
+    fn test_links() {
+        let trusted = Trusted::new(trusted::Config::default(), 0);
+        let mut network = Network::new(Config {
+            root: LinkConfig {
+                latency: Latency::Fixed(50),
+                ..Default::default()
+            },
+            browser: LinkConfig {
+                latency: Latency::Fixed(100),
+                bandwidth: BANDWIDTH_1MBPS,
+                ..Default::default()
+            },
+            flex: LinkConfig {
+                loss: 0x8000,
+                ..Default::default()
+            },
+        });
+        let secret1 = NodeSecret::random();
+        let node1 = Node::from_secret(secret1, &trusted).with_class(NodeClass::Root);
+        let node2 = Node::new(&trusted);
+        let node3 = Node::new(&trusted).with_class(NodeClass::Flex);
+        let (id2, id3) = (node2.id(), node3.id());
+        network.action(BMNet::NodeAdd(node1));
+        network.action(BMNet::NodeAdd(node2));
+        network.action(BMNet::NodeAdd(node3));
+
+        // Messages arrive after the latency of both links, plus the few
+        // milliseconds they need to be sent with 1 Mbps.
+        let ping = NodeMsg::new(&secret1, id2, Msg::Ping);
+        assert!(network.process_msgs(vec![ping.clone()]).is_empty());
+        assert!(network.tick(150).is_empty());
+        assert_eq!(1, network.tick(160).len());
+        assert!(network.tick(300).is_empty());
+        assert_eq!(1, network.in_flight());
+        assert_eq!(1, network.tick(320).len());
+
+        // Every message waits until the earlier ones are sent.
+        network.process_msgs(vec![ping.clone(); 100]);
+        let arrived = pings(&network.tick(620));
+        assert!(0 < arrived && arrived < 100, "{arrived} pings arrived");
+        let arrived = arrived + pings(&network.tick(1_000));
+        assert_eq!(100, arrived);
+        network.tick(2_000);
+
+        // Messages on their way to a removed node fail.
+        network.process_msgs(vec![ping]);
+        let events = network.action(BMNet::NodeDel(id2));
+        assert_matches!(&events[..], [BrokerMsg::Event(BrokerEvent::Undelivered(msg))]
+            if msg.to == id2);
+        assert_eq!(0, network.in_flight());
+
+        // About half of the messages to the flex node get lost.
+        let ping = NodeMsg::new(&secret1, id3, Msg::Ping);
+        let arrived = pings(&network.process_msgs(vec![ping; 400]));
+        let arrived = arrived + pings(&network.tick(3_000));
+        assert!((120..280).contains(&arrived), "{arrived} pings arrived");
+    }
+}
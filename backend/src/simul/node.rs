@@ -1,38 +1,345 @@
-use serde::Serialize;
-use std::{fmt::Display, sync::mpsc::Sender};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Display, sync::mpsc::Sender};
+use utoipa::ToSchema;
 
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+use crate::api::storage::{FileInfo, StorageUsage};
 
 use super::{
-    broker::{BMNode, BrokerMsg},
-    node_types::{Mana, NodeID},
-    trusted::{TReqMsg, TrustedRequest},
+    chunk::{Chunk, ChunkStore, FileEntry, Manifest},
+    kademlia::{self, DhtGet, DhtRecord, Lookup, LookupResult, RoutingTable},
+    mana_policy::Resources,
+    msgs::NodeAction,
+    node_types::{Hash256, Mana, NodeID, NodePublicKey, NodeSecret, Signature},
+    page::{Page, PageFile},
+    trusted::{TReqMsg, TrustedReply, TrustedRequest},
 };
 
 /// Node is a simulation which can answer to certain messages.
 /// If it receives regular 'tick's, it will send out some messages on its own.
+/// Nodes without a secret are controlled from outside of the simulation,
+/// e.g., by a browser which only gave its public key.
+/// They can receive messages, but not send any.
 #[derive(Debug)]
 pub struct Node {
     info: NodeInfo,
+    secret: Option<NodeSecret>,
     trusted: Sender<TrustedRequest>,
+    // The pages hosted by this node
+    storage: Storage,
+    // Domains being copied from other nodes, by domain
+    replicas: HashMap<String, PendingReplica>,
+    // Decides how the link of the node to the network behaves
+    class: NodeClass,
+    // Boxed, as nodes are moved around in the messages of the broker
+    routing: Box<Routing>,
+    // The time of the last tick
+    now: u128,
+}
+
+// The Kademlia state of a node, with the values it keeps for the DHT.
+#[derive(Debug)]
+struct Routing {
+    // The other nodes known to this node
+    table: RoutingTable,
+    // The running lookups, by target
+    lookups: HashMap<NodeID, Lookup>,
+    // The finished lookups, by target
+    results: HashMap<NodeID, LookupResult>,
+    // The least recently seen nodes of full buckets which were pinged, with
+    // the node replacing them if they don't answer, and when they were pinged
+    pings: HashMap<NodeID, (NodeID, u128)>,
+    // The values stored at this node, by key
+    values: HashMap<Hash256, DhtRecord>,
+    // The values to store once the lookup of their key is done, by target
+    puts: HashMap<NodeID, (Hash256, DhtRecord)>,
+    // The keys of the lookups searching for a value, by target
+    gets: HashMap<NodeID, Hash256>,
+    // The state of the lookups of values, by key
+    found: HashMap<Hash256, DhtGet>,
+}
+
+/// The kind of a node, each with its own link to the network.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NodeClass {
+    /// Always online, like the root nodes of the simulator and the gateway.
+    Root,
+    /// Simulated nodes going on- and offline.
+    Flex,
+    /// Nodes registered through the web-frontend.
+    #[default]
+    Browser,
+}
+
+// A domain which is copied from another node. First the manifest is
+// fetched, then the chunks which are not stored yet.
+#[derive(Debug)]
+struct PendingReplica {
+    from: NodeID,
+    manifest: Option<(NodeID, Manifest)>,
+    chunks: HashMap<Hash256, Chunk>,
 }
 
+/// How many bytes every node shares with the network.
+pub const STORAGE_QUOTA: u64 = 10 * 1024 * 1024;
+
+/// The files stored on a node, by domain and path.
+/// The data of the files is kept in a `ChunkStore`, so identical data in
+/// different files only counts once against the quota.
+/// Every domain belongs to the node which stored its first file, and only
+/// this node can change the files of the domain.
 #[derive(Debug)]
+pub struct Storage {
+    quota: u64,
+    domains: HashMap<String, Domain>,
+    chunks: ChunkStore,
+}
+
+#[derive(Debug)]
+struct Domain {
+    owner: NodeID,
+    manifest: Manifest,
+}
+
+#[derive(Debug, derive_more::Display, derive_more::Error, Clone, PartialEq)]
+pub enum StorageError {
+    #[display(
+        fmt = "Storage quota exceeded: {} bytes needed, {} bytes available.",
+        needed,
+        available
+    )]
+    QuotaExceeded { needed: u64, available: u64 },
+    #[display(fmt = "The domain belongs to another node.")]
+    NotOwner,
+    #[display(fmt = "There is no such file.")]
+    UnknownFile,
+    #[display(fmt = "The node is not online.")]
+    UnknownNode,
+    #[display(fmt = "A chunk of the file is missing.")]
+    MissingChunk,
+}
+
+/// A message between two nodes, signed by the sender.
+#[derive(Debug, Clone, Serialize)]
 pub struct NodeMsg {
     pub from: NodeID,
     pub to: NodeID,
     pub msg: Msg,
+    /// Signature over `NodeMsg::message` with the key of the sender.
+    pub signature: Signature,
 }
 
-#[derive(Debug)]
+impl NodeMsg {
+    /// Creates a new message from the node with the given secret.
+    pub fn new(secret: &NodeSecret, to: NodeID, msg: Msg) -> Self {
+        let from = (*secret).into();
+        Self {
+            from,
+            to,
+            signature: secret.sign(&Self::message(&from, &to, &msg)),
+            msg,
+        }
+    }
+
+    /// Returns the bytes to be signed for a message.
+    pub fn message(from: &NodeID, to: &NodeID, msg: &Msg) -> Vec<u8> {
+        let mut bytes = b"cybernode-nodemsg".to_vec();
+        bytes.extend_from_slice(&from.to_bytes());
+        bytes.extend_from_slice(&to.to_bytes());
+        bytes.extend(serde_json::to_vec(msg).expect("Msg serializes to JSON"));
+        bytes
+    }
+
+    /// Returns true if the key belongs to the sender and the message has
+    /// been signed with it.
+    pub fn verify(&self, key: &NodePublicKey) -> bool {
+        NodeID::from(*key) == self.from
+            && key.verify(
+                &Self::message(&self.from, &self.to, &self.msg),
+                &self.signature,
+            )
+    }
+}
+
+/// Confirms that `server` sent `bytes` of pages to `viewer`.
+/// The viewer signs the receipt and sends it to the server, which submits it
+/// to Trusted to get rewarded for serving the pages.
+/// Viewers outside of the simulation submit it on /v1/receipt.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Receipt {
+    pub viewer: NodeID,
+    pub server: NodeID,
+    pub bytes: u64,
+    /// Must not be lower than the nonce of the next receipt of the viewer
+    /// in Trusted, so that receipts cannot be submitted twice.
+    pub nonce: u64,
+    /// Signature over `Receipt::message` with the key of the viewer.
+    pub signature: Signature,
+}
+
+impl Receipt {
+    /// Creates a receipt signed by the viewer with the given secret.
+    pub fn new(secret: &NodeSecret, server: NodeID, bytes: u64, nonce: u64) -> Self {
+        let viewer = (*secret).into();
+        Self {
+            viewer,
+            server,
+            bytes,
+            nonce,
+            signature: secret.sign(&Self::message(&viewer, &server, bytes, nonce)),
+        }
+    }
+
+    /// Returns the bytes to be signed for a receipt.
+    pub fn message(viewer: &NodeID, server: &NodeID, bytes: u64, nonce: u64) -> Vec<u8> {
+        let mut msg = b"cybernode-receipt".to_vec();
+        msg.extend_from_slice(&viewer.to_bytes());
+        msg.extend_from_slice(&server.to_bytes());
+        msg.extend_from_slice(&bytes.to_be_bytes());
+        msg.extend_from_slice(&nonce.to_be_bytes());
+        msg
+    }
+
+    /// Returns true if the key belongs to the viewer and the receipt has
+    /// been signed with it.
+    pub fn verify(&self, key: &NodePublicKey) -> bool {
+        NodeID::from(*key) == self.viewer
+            && key.verify(
+                &Self::message(&self.viewer, &self.server, self.bytes, self.nonce),
+                &self.signature,
+            )
+    }
+}
+
+/// The bytes a server sent to a viewer outside of the simulation, for which
+/// the viewer signs a `Receipt` itself.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ReceiptRequest {
+    pub server: NodeID,
+    pub bytes: u64,
+    pub nonce: u64,
+}
+
+impl ReceiptRequest {
+    /// Returns the receipt signed by the viewer with the given secret.
+    pub fn sign(&self, secret: &NodeSecret) -> Receipt {
+        Receipt::new(secret, self.server, self.bytes, self.nonce)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub enum Msg {
     Ping,
     Pong,
+    /// Asks for a page hosted by the receiver.
+    PageRequest {
+        domain: String,
+        path: String,
+    },
+    /// Where to find the chunks of the requested page, or None if the node
+    /// doesn't have it.
+    PageReply {
+        domain: String,
+        path: String,
+        file: Option<FileEntry>,
+    },
+    /// Asks for the manifest of a domain, to replicate it.
+    ManifestRequest {
+        domain: String,
+    },
+    /// The owner and the manifest of the domain, or None if the sender
+    /// doesn't have it.
+    ManifestReply {
+        domain: String,
+        manifest: Option<(NodeID, Manifest)>,
+    },
+    /// Asks for chunks by their hashes.
+    ChunkRequest {
+        ids: Vec<Hash256>,
+    },
+    /// The requested chunks the sender has. The receiver checks them
+    /// against the hashes it asked for.
+    ChunkReply {
+        chunks: Vec<Chunk>,
+    },
+    /// Confirms the bytes the receiver served to the sender.
+    Receipt(Box<Receipt>),
+    /// Stores a value of the DHT at the receiver, which should be one of
+    /// the nodes closest to the key.
+    Store {
+        key: Hash256,
+        record: DhtRecord,
+    },
+    /// Asks for the value of the key, or else the closest nodes to it.
+    FindValue {
+        key: Hash256,
+    },
+    /// The value of the key, or the nodes closest to it if the sender
+    /// doesn't have it.
+    Value {
+        key: Hash256,
+        record: Option<DhtRecord>,
+        nodes: Vec<NodeID>,
+    },
+    /// Asks for the nodes closest to the target the receiver knows.
+    FindNode {
+        target: NodeID,
+    },
+    /// The nodes closest to the target known to the sender.
+    Nodes {
+        target: NodeID,
+        nodes: Vec<NodeID>,
+    },
+}
+
+impl Msg {
+    /// The names of all types of messages, as returned by `kind`.
+    pub const KINDS: [&'static str; 14] = [
+        "Ping",
+        "Pong",
+        "PageRequest",
+        "PageReply",
+        "ManifestRequest",
+        "ManifestReply",
+        "ChunkRequest",
+        "ChunkReply",
+        "Receipt",
+        "FindNode",
+        "Nodes",
+        "Store",
+        "FindValue",
+        "Value",
+    ];
+
+    /// Returns the name of the type of the message, like "PageRequest".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Msg::Ping => "Ping",
+            Msg::Pong => "Pong",
+            Msg::PageRequest { .. } => "PageRequest",
+            Msg::PageReply { .. } => "PageReply",
+            Msg::ManifestRequest { .. } => "ManifestRequest",
+            Msg::ManifestReply { .. } => "ManifestReply",
+            Msg::ChunkRequest { .. } => "ChunkRequest",
+            Msg::ChunkReply { .. } => "ChunkReply",
+            Msg::Receipt(_) => "Receipt",
+            Msg::FindNode { .. } => "FindNode",
+            Msg::Nodes { .. } => "Nodes",
+            Msg::Store { .. } => "Store",
+            Msg::FindValue { .. } => "FindValue",
+            Msg::Value { .. } => "Value",
+        }
+    }
 }
 
 impl Node {
     pub fn new(trusted: &Sender<TrustedRequest>) -> Self {
-        Self::from_info(NodeInfo::random(), trusted)
+        Self::from_secret(NodeSecret::random(), trusted)
+    }
+
+    pub fn from_secret(secret: NodeSecret, trusted: &Sender<TrustedRequest>) -> Self {
+        Self::from_info(NodeInfo::from_secret(&secret), Some(secret), trusted)
     }
 
     /// Make a dummy node with a dummy channel to Trusted.
@@ -52,61 +359,764 @@ impl Node {
         self.info.clone()
     }
 
+    pub fn public_key(&self) -> NodePublicKey {
+        self.info.public_key
+    }
+
+    pub fn class(&self) -> NodeClass {
+        self.class
+    }
+
+    pub fn with_class(mut self, class: NodeClass) -> Self {
+        self.class = class;
+        self
+    }
+
+    // Returns a signed message, or None if this node has no secret.
+    fn send(&self, to: NodeID, msg: Msg) -> Option<NodeMsg> {
+        match &self.secret {
+            Some(secret) => Some(NodeMsg::new(secret, to, msg)),
+            None => {
+                debug!("Node {} has no secret to send {msg:?}", self.id());
+                None
+            }
+        }
+    }
+
+    // Registers the node, and reports the storage it offers to the network,
+    // which the `ManaPolicy` of Trusted can reward.
+    // Bandwidth and CPU are not simulated, so they are not offered.
     fn update_trusted(&self) {
         if let Err(e) = TReqMsg::Register(self.info.clone()).send(&self.trusted) {
             error!("While registering node: {e:?}");
         }
+        let resources = Resources {
+            storage: self.storage.quota,
+            ..Resources::default()
+        };
+        if let Err(e) = TReqMsg::Resources(self.info.id, resources).send(&self.trusted) {
+            error!("While reporting the resources of the node: {e:?}");
+        }
     }
 
     pub fn receive(&mut self, input: NodeMsg) -> Vec<NodeMsg> {
-        let mut out = vec![];
         debug!("Processing message {input:?}");
+        let mut out = self.seen(input.from);
         match &input.msg {
-            Msg::Ping => out.push(NodeMsg {
-                from: self.id(),
-                to: input.from,
-                msg: Msg::Pong,
-            }),
-            Msg::Pong => info!("Got pong {input:?}"),
+            Msg::Ping => out.extend(self.send(input.from, Msg::Pong)),
+            Msg::Pong => {
+                info!("Got pong {input:?}");
+                self.routing.pings.remove(&input.from);
+            }
+            Msg::PageRequest { domain, path } => out.extend(self.send(
+                input.from,
+                Msg::PageReply {
+                    domain: domain.clone(),
+                    path: path.clone(),
+                    file: self.storage.file(domain, path).cloned(),
+                },
+            )),
+            Msg::PageReply { .. } => debug!("Got page reply {input:?}"),
+            Msg::ChunkRequest { ids } => {
+                let chunks = ids
+                    .iter()
+                    .filter_map(|id| self.storage.chunk(id).cloned())
+                    .collect();
+                out.extend(self.send(input.from, Msg::ChunkReply { chunks }))
+            }
+            Msg::ManifestRequest { domain } => {
+                let manifest = self
+                    .storage
+                    .owner(domain)
+                    .zip(self.storage.manifest(domain).cloned());
+                out.extend(self.send(
+                    input.from,
+                    Msg::ManifestReply {
+                        domain: domain.clone(),
+                        manifest,
+                    },
+                ))
+            }
+            Msg::ManifestReply { domain, manifest } => {
+                out.extend(self.manifest_reply(input.from, domain, manifest.clone()))
+            }
+            Msg::ChunkReply { chunks } => self.chunk_reply(chunks),
+            Msg::Receipt(receipt) => self.submit_receipt(input.from, receipt),
+            Msg::FindNode { target } => out.extend(self.send(
+                input.from,
+                Msg::Nodes {
+                    target: *target,
+                    nodes: self.routing.table.closest(target, kademlia::K),
+                },
+            )),
+            Msg::Nodes { target, nodes } => {
+                out.extend(self.lookup_answer(input.from, *target, nodes.clone()))
+            }
+            Msg::Store { key, record } => {
+                if record.expires > self.now {
+                    self.routing.values.insert(*key, record.clone());
+                }
+            }
+            Msg::FindValue { key } => {
+                let record = self.stored_value(key).cloned();
+                let nodes = match record {
+                    Some(_) => vec![],
+                    None => self.routing.table.closest(&(*key).into(), kademlia::K),
+                };
+                out.extend(self.send(
+                    input.from,
+                    Msg::Value {
+                        key: *key,
+                        record,
+                        nodes,
+                    },
+                ))
+            }
+            Msg::Value {
+                key,
+                record: None,
+                nodes,
+            } => out.extend(self.lookup_answer(input.from, (*key).into(), nodes.clone())),
+            Msg::Value {
+                key,
+                record: Some(record),
+                ..
+            } => out.extend(self.value_found(input.from, *key, record.clone())),
         }
         out
     }
 
-    pub fn from_info(info: NodeInfo, trusted: &Sender<TrustedRequest>) -> Self {
+    // Continues the lookup of the target with the nodes returned by `from`.
+    fn lookup_answer(&mut self, from: NodeID, target: NodeID, nodes: Vec<NodeID>) -> Vec<NodeMsg> {
+        let answered = self
+            .routing
+            .lookups
+            .get_mut(&target)
+            .is_some_and(|lookup| lookup.answer(&from, nodes));
+        match answered {
+            true => self.continue_lookup(target),
+            false => vec![],
+        }
+    }
+
+    // Ends the lookup of the key with the value returned by `from`.
+    fn value_found(&mut self, from: NodeID, key: Hash256, record: DhtRecord) -> Vec<NodeMsg> {
+        let target = key.into();
+        let asked = self
+            .routing
+            .lookups
+            .get_mut(&target)
+            .is_some_and(|lookup| lookup.answer(&from, vec![]));
+        if !asked || self.routing.gets.remove(&target).is_none() {
+            return vec![];
+        }
+        self.routing.lookups.remove(&target);
+        self.routing.found.insert(key, DhtGet::Found(record));
+        // A value waiting for the lookup still needs the closest nodes.
+        if self.routing.puts.contains_key(&target) {
+            return self.find_node(target);
+        }
+        vec![]
+    }
+
+    /// Stores the value at the nodes closest to the key, until `ttl`
+    /// milliseconds from now.
+    pub fn dht_put(&mut self, key: Hash256, value: Vec<u8>, ttl: u128) -> Vec<NodeMsg> {
+        let expires = self.now + ttl;
+        self.publish(key, DhtRecord { value, expires })
+    }
+
+    /// Starts looking up the value of the key. The result is available from
+    /// `dht_result` once the lookup is done.
+    pub fn dht_get(&mut self, key: Hash256) -> Vec<NodeMsg> {
+        if let Some(record) = self.stored_value(&key).cloned() {
+            self.routing.found.insert(key, DhtGet::Found(record));
+            return vec![];
+        }
+        let target = key.into();
+        self.routing.gets.insert(target, key);
+        self.routing.found.insert(key, DhtGet::Pending);
+        self.start_lookup(target)
+    }
+
+    /// Returns the state of the last lookup of the value of the key.
+    pub fn dht_result(&self, key: &Hash256) -> DhtGet {
+        self.routing
+            .found
+            .get(key)
+            .cloned()
+            .unwrap_or(DhtGet::NotFound)
+    }
+
+    /// Returns the value of the key if it is stored at this node and not
+    /// expired.
+    pub fn stored_value(&self, key: &Hash256) -> Option<&DhtRecord> {
+        self.routing
+            .values
+            .get(key)
+            .filter(|record| record.expires > self.now)
+    }
+
+    /// Stores the values of this node again at the nodes closest to their
+    /// keys, without changing when they expire.
+    pub fn republish(&mut self) -> Vec<NodeMsg> {
+        let values: Vec<(Hash256, DhtRecord)> = self
+            .routing
+            .values
+            .iter()
+            .map(|(key, record)| (*key, record.clone()))
+            .collect();
+        values
+            .into_iter()
+            .flat_map(|(key, record)| self.publish(key, record))
+            .collect()
+    }
+
+    // Looks up the nodes closest to the key, and then stores the value
+    // at them.
+    fn publish(&mut self, key: Hash256, record: DhtRecord) -> Vec<NodeMsg> {
+        let target = key.into();
+        self.routing.puts.insert(target, (key, record));
+        self.start_lookup(target)
+    }
+
+    // Starts a lookup of the target, unless one is already running.
+    fn start_lookup(&mut self, target: NodeID) -> Vec<NodeMsg> {
+        if self.routing.lookups.contains_key(&target) {
+            return vec![];
+        }
+        self.find_node(target)
+    }
+
+    // Adds the sender of a message to the routing table. If its bucket is
+    // full, the least recently seen node is pinged, and replaced by the
+    // sender if it doesn't answer in time.
+    fn seen(&mut self, from: NodeID) -> Vec<NodeMsg> {
+        let Some(oldest) = self.routing.table.seen(from) else {
+            return vec![];
+        };
+        if self.routing.pings.contains_key(&oldest) {
+            return vec![];
+        }
+        self.routing.pings.insert(oldest, (from, self.now));
+        self.send(oldest, Msg::Ping).into_iter().collect()
+    }
+
+    /// Starts looking up the nodes closest to the target. The result is
+    /// available from `lookup_result` once the lookup is done.
+    pub fn find_node(&mut self, target: NodeID) -> Vec<NodeMsg> {
+        let known = self.routing.table.closest(&target, kademlia::K);
+        self.routing.results.remove(&target);
+        self.routing
+            .lookups
+            .insert(target, Lookup::new(self.id(), target, known));
+        self.continue_lookup(target)
+    }
+
+    /// Returns the result of the last finished lookup of the target.
+    pub fn lookup_result(&self, target: &NodeID) -> Option<&LookupResult> {
+        self.routing.results.get(target)
+    }
+
+    /// Returns the other nodes this node knows of.
+    pub fn routing(&self) -> &RoutingTable {
+        &self.routing.table
+    }
+
+    // Asks the next nodes of the lookup, or stores its result if it is done.
+    fn continue_lookup(&mut self, target: NodeID) -> Vec<NodeMsg> {
+        let Some(lookup) = self.routing.lookups.get_mut(&target) else {
+            return vec![];
+        };
+        if lookup.is_done() {
+            let result = lookup.result();
+            debug!("Node {} found {target} in {} hops", self.id(), result.hops);
+            self.routing.lookups.remove(&target);
+            if let Some(key) = self.routing.gets.remove(&target) {
+                self.routing.found.insert(key, DhtGet::NotFound);
+            }
+            let stores = match self.routing.puts.remove(&target) {
+                Some((key, record)) => result
+                    .closest
+                    .iter()
+                    .filter_map(|to| {
+                        let record = record.clone();
+                        self.send(*to, Msg::Store { key, record })
+                    })
+                    .collect(),
+                None => vec![],
+            };
+            self.routing.results.insert(target, result);
+            return stores;
+        }
+        let ask = lookup.next(self.now);
+        let msg = match self.routing.gets.get(&target) {
+            Some(key) => Msg::FindValue { key: *key },
+            None => Msg::FindNode { target },
+        };
+        ask.into_iter()
+            .filter_map(|to| self.send(to, msg.clone()))
+            .collect()
+    }
+
+    // Passes a receipt for the pages this node served on to Trusted.
+    // The receipt can be relayed by another node, like the gateway, as
+    // Trusted checks that the viewer signed it.
+    fn submit_receipt(&self, from: NodeID, receipt: &Receipt) {
+        if receipt.server != self.id() {
+            warn!("Dropping receipt {receipt:?} from {from}");
+            return;
+        }
+        match TReqMsg::Receipt(receipt.clone()).send(&self.trusted) {
+            Ok(TrustedReply::Mana(mana)) => debug!("Node {} has {mana} mana", self.id()),
+            Ok(reply) => debug!("Receipt {receipt:?} refused: {reply:?}"),
+            Err(e) => error!("While submitting receipt: {e:?}"),
+        }
+    }
+
+    /// Starts copying the domain from the other node, replacing the domain
+    /// if this node already has it.
+    pub fn replicate(&mut self, domain: &str, from: NodeID) -> Vec<NodeMsg> {
+        let Some(msg) = self.send(
+            from,
+            Msg::ManifestRequest {
+                domain: domain.to_string(),
+            },
+        ) else {
+            return vec![];
+        };
+        self.replicas.insert(
+            domain.to_string(),
+            PendingReplica {
+                from,
+                manifest: None,
+                chunks: HashMap::new(),
+            },
+        );
+        vec![msg]
+    }
+
+    // Asks for the chunks of the manifest which are not stored yet.
+    fn manifest_reply(
+        &mut self,
+        from: NodeID,
+        domain: &str,
+        manifest: Option<(NodeID, Manifest)>,
+    ) -> Option<NodeMsg> {
+        let replica = self.replicas.get_mut(domain).filter(|r| r.from == from)?;
+        let Some((owner, manifest)) = manifest else {
+            debug!("Node {from} doesn't have domain {domain} anymore");
+            self.replicas.remove(domain);
+            return None;
+        };
+        let mut ids: Vec<Hash256> = manifest
+            .files
+            .values()
+            .flat_map(|f| f.chunks.iter().copied())
+            .filter(|id| self.storage.chunk(id).is_none())
+            .collect();
+        ids.sort();
+        ids.dedup();
+        replica.manifest = Some((owner, manifest));
+        if ids.is_empty() {
+            self.complete_replicas();
+            return None;
+        }
+        self.send(from, Msg::ChunkRequest { ids })
+    }
+
+    // Keeps the chunks needed by the pending replicas.
+    fn chunk_reply(&mut self, chunks: &[Chunk]) {
+        for chunk in chunks {
+            let id = chunk.id();
+            for replica in self.replicas.values_mut() {
+                if let Some((_, manifest)) = &replica.manifest {
+                    if manifest.files.values().any(|f| f.chunks.contains(&id)) {
+                        replica.chunks.insert(id, chunk.clone());
+                    }
+                }
+            }
+        }
+        self.complete_replicas();
+    }
+
+    // Stores the replicas which have all their chunks.
+    fn complete_replicas(&mut self) {
+        let complete: Vec<String> = self
+            .replicas
+            .iter()
+            .filter(|(_, r)| {
+                r.manifest.as_ref().is_some_and(|(_, m)| {
+                    m.files
+                        .values()
+                        .flat_map(|f| &f.chunks)
+                        .all(|id| r.chunks.contains_key(id) || self.storage.chunk(id).is_some())
+                })
+            })
+            .map(|(domain, _)| domain.clone())
+            .collect();
+        for domain in complete {
+            let replica = self.replicas.remove(&domain).expect("replica is pending");
+            let (owner, manifest) = replica.manifest.expect("manifest is complete");
+            match self
+                .storage
+                .store_replica(owner, &domain, manifest, &replica.chunks)
+            {
+                Ok(usage) => debug!("Node {} replicated {domain}: {usage:?}", self.id()),
+                Err(e) => warn!("Node {} cannot replicate {domain}: {e}", self.id()),
+            }
+        }
+    }
+
+    /// Returns true if this node can send messages, so it can serve pages.
+    pub fn has_secret(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// Returns the secret of the node, if the simulation holds it.
+    pub fn secret(&self) -> Option<NodeSecret> {
+        self.secret
+    }
+
+    pub fn from_info(
+        info: NodeInfo,
+        secret: Option<NodeSecret>,
+        trusted: &Sender<TrustedRequest>,
+    ) -> Self {
         let reply = Self {
-            info,
+            secret,
             trusted: trusted.clone(),
+            storage: Storage::new(STORAGE_QUOTA),
+            replicas: HashMap::new(),
+            class: NodeClass::default(),
+            routing: Box::new(Routing {
+                table: RoutingTable::new(info.id),
+                lookups: HashMap::new(),
+                results: HashMap::new(),
+                pings: HashMap::new(),
+                values: HashMap::new(),
+                puts: HashMap::new(),
+                gets: HashMap::new(),
+                found: HashMap::new(),
+            }),
+            now: 0,
+            info,
         };
         reply.update_trusted();
         reply
     }
 
-    pub fn tick(&mut self, _time: u128) -> Vec<NodeMsg> {
-        vec![]
+    /// Stores a page owned by this node, overwriting an existing page with
+    /// the same path.
+    pub fn store_page(&mut self, file: PageFile) -> Result<StorageUsage, StorageError> {
+        self.storage.store(self.info.id, file)
+    }
+
+    /// Returns true if this node hosts pages of the domain.
+    pub fn has_domain(&self, domain: &str) -> bool {
+        self.storage.owner(domain).is_some()
+    }
+
+    pub fn page(&self, domain: &str, path: &str) -> Option<Page> {
+        self.storage.get(domain, path)
+    }
+
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    pub fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
     }
 
-    pub fn action(&mut self, _task: BMNode) -> Vec<BrokerMsg> {
-        todo!()
+    /// Replaces the pinged nodes which didn't answer, continues the
+    /// lookups without the nodes which didn't answer, and drops the expired
+    /// values.
+    pub fn tick(&mut self, time: u128) -> Vec<NodeMsg> {
+        self.now = time;
+        self.routing
+            .values
+            .retain(|_, record| record.expires > time);
+        let expired: Vec<(NodeID, NodeID)> = self
+            .routing
+            .pings
+            .iter()
+            .filter(|(_, (_, since))| since + kademlia::TIMEOUT <= time)
+            .map(|(oldest, (new, _))| (*oldest, *new))
+            .collect();
+        for (oldest, new) in expired {
+            self.routing.pings.remove(&oldest);
+            self.routing.table.remove(&oldest);
+            self.routing.table.seen(new);
+        }
+
+        let mut out = vec![];
+        let targets: Vec<NodeID> = self.routing.lookups.keys().copied().collect();
+        for target in targets {
+            if let Some(lookup) = self.routing.lookups.get_mut(&target) {
+                for failed in lookup.timeout(time) {
+                    self.routing.table.remove(&failed);
+                }
+            }
+            out.extend(self.continue_lookup(target));
+        }
+        out
+    }
+
+    /// Drops the receiver of a message which could not be delivered from
+    /// the routing table, and continues the lookups without it.
+    pub fn undelivered(&mut self, msg: &NodeMsg) -> Vec<NodeMsg> {
+        self.routing.table.remove(&msg.to);
+        if let Some((new, _)) = self.routing.pings.remove(&msg.to) {
+            self.routing.table.seen(new);
+        }
+        let targets: Vec<NodeID> = self
+            .routing
+            .lookups
+            .iter_mut()
+            .filter_map(|(target, lookup)| lookup.fail(&msg.to).then_some(*target))
+            .collect();
+        targets
+            .into_iter()
+            .flat_map(|target| self.continue_lookup(target))
+            .collect()
+    }
+
+    /// Executes an action of the broker, and returns the messages to send.
+    pub fn action(&mut self, action: NodeAction) -> Vec<NodeMsg> {
+        match action {
+            NodeAction::Store(file) => {
+                if let Err(e) = self.store_page(file) {
+                    warn!("Node {} couldn't store the file: {e}", self.id());
+                }
+                vec![]
+            }
+            NodeAction::Fetch { domain, from } => self.replicate(&domain, from),
+            NodeAction::Send { to, msg } => self.send(to, msg).into_iter().collect(),
+            NodeAction::Join { bootstrap } => {
+                self.routing.table.seen(bootstrap);
+                self.find_node(self.id())
+            }
+            NodeAction::FindNode { target } => self.find_node(target),
+            NodeAction::DhtPut { key, value, ttl } => self.dht_put(key, value, ttl),
+            NodeAction::DhtGet { key } => self.dht_get(key),
+            NodeAction::Republish => self.republish(),
+        }
     }
 }
 
-#[derive(Clone, Serialize)]
+impl Storage {
+    pub fn new(quota: u64) -> Self {
+        Self {
+            quota,
+            domains: HashMap::new(),
+            chunks: ChunkStore::default(),
+        }
+    }
+
+    pub fn usage(&self) -> StorageUsage {
+        StorageUsage {
+            used: self.chunks.size(),
+            quota: self.quota,
+        }
+    }
+
+    /// Returns the node owning the domain, if this storage has it.
+    pub fn owner(&self, domain: &str) -> Option<NodeID> {
+        self.domains.get(domain).map(|d| d.owner)
+    }
+
+    pub fn manifest(&self, domain: &str) -> Option<&Manifest> {
+        self.domains.get(domain).map(|d| &d.manifest)
+    }
+
+    pub fn file(&self, domain: &str, path: &str) -> Option<&FileEntry> {
+        self.manifest(domain)?.files.get(path)
+    }
+
+    pub fn chunk(&self, id: &Hash256) -> Option<&Chunk> {
+        self.chunks.get(id)
+    }
+
+    /// Returns the page put together from its chunks.
+    pub fn get(&self, domain: &str, path: &str) -> Option<Page> {
+        let file = self.file(domain, path)?;
+        Some(Page {
+            content_type: file.content_type.clone(),
+            data: file.assemble(|id| self.chunks.get(id))?,
+        })
+    }
+
+    /// Returns all files of the domain, sorted by path.
+    pub fn list(&self, domain: &str) -> Vec<FileInfo> {
+        self.manifest(domain)
+            .map(|m| {
+                m.files
+                    .iter()
+                    .map(|(path, file)| FileInfo {
+                        path: path.clone(),
+                        content_type: file.content_type.clone(),
+                        size: file.size,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Stores the file, replacing an existing file with the same path.
+    /// Only chunks which are not stored yet count against the quota, and the
+    /// chunks freed by the replaced file are available for the new file.
+    pub fn store(&mut self, owner: NodeID, file: PageFile) -> Result<StorageUsage, StorageError> {
+        self.check_owner(owner, &file.domain)?;
+        let used = self.chunks.size();
+        let chunks: Vec<Hash256> = Chunk::split(&file.page.data)
+            .into_iter()
+            .map(|c| self.chunks.insert(c))
+            .collect();
+        let needed = self.chunks.size() - used;
+        let freed = self
+            .file(&file.domain, &file.path)
+            .map(|old| self.chunks.freed_by(&old.chunks))
+            .unwrap_or(0);
+        if self.chunks.size() - freed > self.quota {
+            self.chunks.release(&chunks);
+            return Err(StorageError::QuotaExceeded {
+                needed,
+                available: self.quota - used + freed,
+            });
+        }
+        let entry = FileEntry {
+            content_type: file.page.content_type,
+            size: file.page.data.len() as u64,
+            chunks,
+        };
+        let old = self
+            .domains
+            .entry(file.domain)
+            .or_insert_with(|| Domain {
+                owner,
+                manifest: Manifest::default(),
+            })
+            .manifest
+            .files
+            .insert(file.path, entry);
+        if let Some(old) = old {
+            self.chunks.release(&old.chunks);
+        }
+        Ok(self.usage())
+    }
+
+    /// Stores a copy of the domain of another node, replacing the domain if
+    /// it is already here. The chunks which are not stored yet must be
+    /// given in `chunks`.
+    pub fn store_replica(
+        &mut self,
+        owner: NodeID,
+        domain: &str,
+        manifest: Manifest,
+        chunks: &HashMap<Hash256, Chunk>,
+    ) -> Result<StorageUsage, StorageError> {
+        let used = self.chunks.size();
+        let mut ids = vec![];
+        for id in manifest.files.values().flat_map(|f| &f.chunks) {
+            let Some(chunk) = self.chunks.get(id).or(chunks.get(id)).cloned() else {
+                self.chunks.release(&ids);
+                return Err(StorageError::MissingChunk);
+            };
+            ids.push(self.chunks.insert(chunk));
+        }
+        let needed = self.chunks.size() - used;
+        let freed = self
+            .manifest(domain)
+            .map(|m| self.chunks.freed_by(&m.chunk_ids()))
+            .unwrap_or(0);
+        if self.chunks.size() - freed > self.quota {
+            self.chunks.release(&ids);
+            return Err(StorageError::QuotaExceeded {
+                needed,
+                available: self.quota - used + freed,
+            });
+        }
+        if let Some(old) = self
+            .domains
+            .insert(domain.to_string(), Domain { owner, manifest })
+        {
+            self.chunks.release(&old.manifest.chunk_ids());
+        }
+        Ok(self.usage())
+    }
+
+    /// Removes the domain with all its files, whoever owns it.
+    pub fn remove_domain(&mut self, domain: &str) -> StorageUsage {
+        if let Some(old) = self.domains.remove(domain) {
+            self.chunks.release(&old.manifest.chunk_ids());
+        }
+        self.usage()
+    }
+
+    /// Returns the domains stored here, sorted.
+    pub fn domains(&self) -> Vec<String> {
+        let mut domains: Vec<String> = self.domains.keys().cloned().collect();
+        domains.sort();
+        domains
+    }
+
+    /// Deletes the file. The domain is released with its last file.
+    pub fn delete(
+        &mut self,
+        owner: NodeID,
+        domain: &str,
+        path: &str,
+    ) -> Result<StorageUsage, StorageError> {
+        self.check_owner(owner, domain)?;
+        let files = &mut self
+            .domains
+            .get_mut(domain)
+            .ok_or(StorageError::UnknownFile)?
+            .manifest
+            .files;
+        let file = files.remove(path).ok_or(StorageError::UnknownFile)?;
+        if files.is_empty() {
+            self.domains.remove(domain);
+        }
+        self.chunks.release(&file.chunks);
+        Ok(self.usage())
+    }
+
+    fn check_owner(&self, owner: NodeID, domain: &str) -> Result<(), StorageError> {
+        match self.owner(domain) {
+            Some(o) if o != owner => Err(StorageError::NotOwner),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 pub struct NodeInfo {
     pub id: NodeID,
     pub name: String,
     pub mana: Mana,
+    pub public_key: NodePublicKey,
 }
 
 impl NodeInfo {
     pub fn random() -> Self {
-        Self::with_id(NodeID::random())
+        Self::from_secret(&NodeSecret::random())
+    }
+
+    /// Creates a new node with the id and the public key derived from
+    /// the secret.
+    pub fn from_secret(secret: &NodeSecret) -> Self {
+        Self::from_public_key(secret.public_key())
     }
 
-    pub fn with_id(id: NodeID) -> Self {
+    /// Creates a new node with the id derived from the public key.
+    pub fn from_public_key(public_key: NodePublicKey) -> Self {
         Self {
-            id,
+            id: public_key.into(),
             name: names::Generator::default().next().unwrap(),
             mana: Mana::zero(),
+            public_key,
         }
     }
 }
@@ -140,13 +1150,156 @@ mod test {
         let mut node1 = Node::dummy();
         let mut node2 = Node::dummy();
         node1.tick(100);
-        let mut msgs = node1.receive(NodeMsg {
-            from: node2.id(),
-            to: node1.id(),
-            msg: Msg::Ping,
-        });
+        let ping = node2.send(node1.id(), Msg::Ping).unwrap();
+        let mut msgs = node1.receive(ping);
         assert_eq!(1, msgs.len());
+        assert!(msgs[0].verify(&node1.info().public_key));
         msgs = node2.receive(msgs.remove(0));
         assert_eq!(0, msgs.len());
+
+        // Nodes without a secret don't answer.
+        let mut remote = Node::from_info(node1.info(), None, &node2.trusted);
+        let ping = node2.send(remote.id(), Msg::Ping).unwrap();
+        assert_eq!(0, remote.receive(ping).len());
+    }
+
+    #[test]
+    fn test_page() {
+        let mut host = Node::dummy();
+        let client = Node::dummy();
+        let page = Page::new("index.html", b"<h1>Hi</h1>".to_vec());
+        host.store_page(PageFile {
+            domain: "ineiti".into(),
+            path: "index.html".into(),
+            page: page.clone(),
+        })
+        .unwrap();
+        assert!(host.has_domain("ineiti"));
+        assert!(!host.has_domain("cybernode"));
+
+        for (path, expected) in [("index.html", true), ("about.html", false)] {
+            let request = Msg::PageRequest {
+                domain: "ineiti".into(),
+                path: path.into(),
+            };
+            let reply = host.receive(client.send(host.id(), request).unwrap());
+            assert_eq!(1, reply.len());
+            assert_matches!(&reply[0].msg, Msg::PageReply { file, .. }
+                if file.is_some() == expected);
+        }
+        assert_eq!(Some(page), host.page("ineiti", "index.html"));
+
+        // The chunks of the page are sent by their hashes, unknown hashes
+        // are ignored.
+        let file = host.storage().file("ineiti", "index.html").unwrap().clone();
+        let request = Msg::ChunkRequest {
+            ids: vec![file.chunks[0], Hash256::zero()],
+        };
+        let reply = host.receive(client.send(host.id(), request).unwrap());
+        assert_matches!(&reply[0].msg, Msg::ChunkReply { chunks }
+            if chunks.len() == 1 && chunks[0].id() == file.chunks[0]);
+    }
+
+    #[test]
+    fn test_storage() {
+        let mut storage = Storage::new(10);
+        let (owner, other) = (NodeID::random(), NodeID::random());
+        let file = |path: &str, size: usize| PageFile {
+            domain: "ineiti".into(),
+            path: path.into(),
+            page: Page::new(path, vec![0; size]),
+        };
+
+        assert_eq!(
+            Ok(6),
+            storage.store(owner, file("a.html", 6)).map(|u| u.used)
+        );
+        assert_eq!(
+            Err(StorageError::QuotaExceeded {
+                needed: 5,
+                available: 4
+            }),
+            storage.store(owner, file("b.html", 5))
+        );
+        // Overwriting frees the space of the old file.
+        assert_eq!(
+            Ok(10),
+            storage.store(owner, file("a.html", 10)).map(|u| u.used)
+        );
+        assert_eq!(1, storage.list("ineiti").len());
+        assert_eq!(10, storage.list("ineiti")[0].size);
+
+        // Only the owner can change the domain.
+        assert_eq!(
+            Err(StorageError::NotOwner),
+            storage.store(other, file("b.html", 0))
+        );
+        assert_eq!(
+            Err(StorageError::NotOwner),
+            storage.delete(other, "ineiti", "a.html")
+        );
+        assert_eq!(
+            Err(StorageError::UnknownFile),
+            storage.delete(owner, "ineiti", "b.html")
+        );
+
+        // Deleting the last file releases the domain.
+        assert_eq!(
+            Ok(0),
+            storage.delete(owner, "ineiti", "a.html").map(|u| u.used)
+        );
+        assert_eq!(None, storage.owner("ineiti"));
+        assert!(storage.store(other, file("b.html", 1)).is_ok());
+
+        // Identical data in other files and domains is stored only once.
+        let mut copy = file("copy.html", 1);
+        copy.domain = "other".into();
+        assert_eq!(Ok(1), storage.store(other, copy).map(|u| u.used));
+        assert_eq!(
+            Ok(1),
+            storage.delete(other, "ineiti", "b.html").map(|u| u.used)
+        );
+        assert_eq!(
+            Some(vec![0]),
+            storage.get("other", "copy.html").map(|p| p.data)
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let secret = NodeSecret::random();
+        let key = secret.public_key();
+        let msg = NodeMsg::new(&secret, NodeID::random(), Msg::Ping);
+        assert!(msg.verify(&key));
+        assert!(!msg.verify(&NodeSecret::random().public_key()));
+
+        let mut forged = msg.clone();
+        forged.msg = Msg::Pong;
+        assert!(!forged.verify(&key));
+        let mut forged = msg;
+        forged.to = NodeID::random();
+        assert!(!forged.verify(&key));
+    }
+
+    #[test]
+    fn test_resources() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::simul::{mana_policy::ManaPolicyConfig, trusted};
+
+        // The node is rewarded for the storage it offers.
+        let cfg = trusted::Config {
+            mana_policy: ManaPolicyConfig::Contribution {
+                period: 1_000,
+                storage_unit: STORAGE_QUOTA / 2,
+                bandwidth_unit: 0,
+                cpu_unit: 0,
+            },
+            ..trusted::Config::default()
+        };
+        let tr = trusted::Trusted::new(cfg, 0);
+        let node = Node::new(&tr);
+        TReqMsg::Tick(1_000).send(&tr)?;
+        let reply = TReqMsg::Info(node.id()).send(&tr)?;
+        assert_matches!(reply, TrustedReply::NodeInfo(Some(ni)) if ni.mana == 2.into());
+        Ok(())
     }
 }
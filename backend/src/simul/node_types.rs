@@ -1,11 +1,21 @@
-use std::fmt::Display;
+use std::{
+    fmt::{Display, LowerHex},
+    str::FromStr,
+};
 
-use byte_slice_cast::AsByteSlice;
-use primitive_types::U256;
-use ring::digest;
+use primitive_types::{U256, U512};
+use ring::{
+    digest,
+    signature::{self, Ed25519KeyPair, KeyPair},
+};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash, Copy)]
+/// The ID of a node, serialized as a hexadecimal string.
+#[derive(
+    Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash, Copy, ToSchema, PartialOrd, Ord,
+)]
+#[schema(value_type = String, example = "0x2a")]
 pub struct NodeID(U256);
 
 impl NodeID {
@@ -18,22 +28,108 @@ impl NodeID {
     }
 }
 
+impl NodeID {
+    /// Returns the XOR distance to the other ID, as used by Kademlia.
+    pub fn distance(&self, other: &NodeID) -> U256 {
+        self.0 ^ other.0
+    }
+
+    /// Returns the index of the k-bucket for the other ID, which is the
+    /// highest bit in which the IDs differ, or None for the same ID.
+    pub fn bucket_index(&self, other: &NodeID) -> Option<usize> {
+        let distance = self.distance(other);
+        (!distance.is_zero()).then(|| distance.bits() - 1)
+    }
+
+    /// Returns the big-endian bytes of this ID.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        self.0.to_big_endian(&mut bytes);
+        bytes
+    }
+}
+
 impl Display for NodeID {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(formatter, "{:#018x}", self.0.as_ref()[0],)
     }
 }
 
-impl From<NodeSecret> for NodeID {
-    fn from(value: NodeSecret) -> Self {
+/// The ID of a node is the SHA-256 hash of its public key, so anybody
+/// can check that a public key belongs to a node.
+impl From<NodePublicKey> for NodeID {
+    fn from(value: NodePublicKey) -> Self {
         Self(
-            digest::digest(&digest::SHA256, value.0.as_byte_slice())
+            digest::digest(&digest::SHA256, &value.to_bytes())
                 .as_ref()
                 .into(),
         )
     }
 }
 
+impl From<NodeSecret> for NodeID {
+    fn from(value: NodeSecret) -> Self {
+        value.public_key().into()
+    }
+}
+
+impl From<U256> for NodeID {
+    fn from(value: U256) -> Self {
+        Self(value)
+    }
+}
+
+/// Keys of the DHT are stored at the nodes whose IDs are closest to them.
+impl From<Hash256> for NodeID {
+    fn from(value: Hash256) -> Self {
+        Self(value.0)
+    }
+}
+
+/// The ID of a group of nodes, like the nodes storing a website, serialized
+/// as a hexadecimal string.
+#[derive(
+    Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash, Copy, ToSchema, PartialOrd, Ord,
+)]
+#[schema(value_type = String, example = "0x2a")]
+pub struct GroupID(U256);
+
+impl GroupID {
+    pub fn random() -> Self {
+        Self(rand::random::<[u8; 32]>().into())
+    }
+
+    /// Returns the big-endian bytes of this ID.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        self.0.to_big_endian(&mut bytes);
+        bytes
+    }
+}
+
+impl Display for GroupID {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{:#018x}", self.0.as_ref()[0],)
+    }
+}
+
+impl From<U256> for GroupID {
+    fn from(value: U256) -> Self {
+        Self(value)
+    }
+}
+
+/// Parses a group ID given in hexadecimal, with an optional '0x' prefix.
+impl FromStr for GroupID {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        U256::from_str_radix(hex_digits(s, 64, "group ID")?, 16)
+            .map(Self)
+            .map_err(|e| format!("group ID is not hexadecimal: {e:?}"))
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Copy)]
 pub struct NodeSecret(U256);
 
@@ -47,12 +143,66 @@ impl NodeSecret {
     }
 }
 
+impl NodeSecret {
+    /// Returns the Ed25519 key pair using this secret as the seed.
+    pub fn key_pair(&self) -> Ed25519KeyPair {
+        let mut seed = [0u8; 32];
+        self.0.to_big_endian(&mut seed);
+        Ed25519KeyPair::from_seed_unchecked(&seed).expect("32 bytes are a valid seed")
+    }
+
+    pub fn public_key(&self) -> NodePublicKey {
+        NodePublicKey(self.key_pair().public_key().as_ref().into())
+    }
+
+    /// Signs the message with the key pair of this secret.
+    pub fn sign(&self, msg: &[u8]) -> Signature {
+        Signature(self.key_pair().sign(msg).as_ref().into())
+    }
+}
+
 impl Display for NodeSecret {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(formatter, "{:#018x}", self.0.as_ref()[0],)
     }
 }
 
+/// Parses a secret given as 64 hexadecimal characters, with an optional
+/// '0x' prefix.
+impl FromStr for NodeSecret {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = hex_digits(s, 64, "secret")?;
+        if hex.len() != 64 {
+            return Err(format!(
+                "secret must be 64 hex characters, got {}",
+                hex.len()
+            ));
+        }
+        U256::from_str_radix(hex, 16)
+            .map(Self)
+            .map_err(|e| format!("secret is not hexadecimal: {e:?}"))
+    }
+}
+
+// Returns the hexadecimal digits of s, without the optional '0x' prefix,
+// if there are at most max_len of them.
+fn hex_digits<'a>(s: &'a str, max_len: usize, what: &str) -> Result<&'a str, String> {
+    let s = s.trim();
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    if hex.is_empty() || hex.len() > max_len {
+        return Err(format!(
+            "{what} must be 1 to {max_len} hex characters, got {}",
+            hex.len()
+        ));
+    }
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{what} is not hexadecimal"));
+    }
+    Ok(hex)
+}
+
 #[derive(
     Clone,
     Debug,
@@ -63,7 +213,10 @@ impl Display for NodeSecret {
     PartialEq,
     PartialOrd,
     Copy,
+    ToSchema,
+    Default,
 )]
+#[schema(value_type = String, example = "0x2a")]
 pub struct Mana(U256);
 
 impl Display for Mana {
@@ -82,4 +235,200 @@ impl Mana {
     pub fn zero() -> Self {
         Mana(U256::zero())
     }
+
+    /// Returns `self * num / den`, rounded down.
+    pub fn scale(&self, num: u128, den: u128) -> Self {
+        Mana(self.0 * U256::from(num) / U256::from(den))
+    }
+
+    /// Returns the big-endian bytes of this amount.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        self.0.to_big_endian(&mut bytes);
+        bytes
+    }
+}
+
+/// The Ed25519 public key of a node, serialized as a hexadecimal string.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Copy, ToSchema, Default)]
+#[schema(value_type = String, example = "0x2a")]
+pub struct NodePublicKey(U256);
+
+impl NodePublicKey {
+    /// Returns the big-endian bytes of this key.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        self.0.to_big_endian(&mut bytes);
+        bytes
+    }
+
+    /// Returns true if the signature of the message has been created by
+    /// the secret of this public key.
+    pub fn verify(&self, msg: &[u8], sig: &Signature) -> bool {
+        signature::UnparsedPublicKey::new(&signature::ED25519, self.to_bytes())
+            .verify(msg, &sig.to_bytes())
+            .is_ok()
+    }
+}
+
+impl LowerHex for NodePublicKey {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        LowerHex::fmt(&self.0, formatter)
+    }
+}
+
+/// Parses a public key given in hexadecimal, with an optional '0x' prefix.
+impl FromStr for NodePublicKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        U256::from_str_radix(hex_digits(s, 64, "public key")?, 16)
+            .map(Self)
+            .map_err(|e| format!("public key is not hexadecimal: {e:?}"))
+    }
+}
+
+/// An Ed25519 signature, serialized as a hexadecimal string.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Copy, ToSchema)]
+#[schema(value_type = String, example = "0x2a")]
+pub struct Signature(U512);
+
+impl Signature {
+    /// Returns the big-endian bytes of this signature.
+    pub fn to_bytes(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        self.0.to_big_endian(&mut bytes);
+        bytes
+    }
+}
+
+impl LowerHex for Signature {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        LowerHex::fmt(&self.0, formatter)
+    }
+}
+
+/// Parses a signature given in hexadecimal, with an optional '0x' prefix.
+impl FromStr for Signature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        U512::from_str_radix(hex_digits(s, 128, "signature")?, 16)
+            .map(Self)
+            .map_err(|e| format!("signature is not hexadecimal: {e:?}"))
+    }
+}
+
+/// A SHA-256 hash, serialized as a hexadecimal string.
+#[derive(
+    Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash, Copy, ToSchema, PartialOrd, Ord,
+)]
+#[schema(value_type = String, example = "0x2a")]
+pub struct Hash256(U256);
+
+impl Hash256 {
+    pub fn zero() -> Self {
+        Self(U256::zero())
+    }
+
+    /// Returns the SHA-256 hash of the data.
+    pub fn digest(data: &[u8]) -> Self {
+        Self(digest::digest(&digest::SHA256, data).as_ref().into())
+    }
+
+    /// Returns the big-endian bytes of this hash.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        self.0.to_big_endian(&mut bytes);
+        bytes
+    }
+}
+
+impl Display for Hash256 {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{:#018x}", self.0.as_ref()[0],)
+    }
+}
+
+/// Parses a hash given in hexadecimal, with an optional '0x' prefix.
+impl FromStr for Hash256 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        U256::from_str_radix(hex_digits(s, 64, "hash")?, 16)
+            .map(Self)
+            .map_err(|e| format!("hash is not hexadecimal: {e:?}"))
+    }
+}
+
+impl Default for Hash256 {
+    fn default() -> Self {
+        Self::zero()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_secret_parse() {
+        let hex = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let secret: NodeSecret = hex.parse().unwrap();
+        let secret_prefix: NodeSecret = format!("0x{hex}").parse().unwrap();
+        assert_eq!(NodeID::from(secret), NodeID::from(secret_prefix));
+        assert_ne!(NodeID::from(secret), NodeID::from(NodeSecret::zero()));
+
+        assert!("".parse::<NodeSecret>().is_err());
+        assert!("0x1234".parse::<NodeSecret>().is_err());
+        assert!(format!("0x0x{}", &hex[2..]).parse::<NodeSecret>().is_err());
+        assert!(hex.replace('0', "g").parse::<NodeSecret>().is_err());
+    }
+
+    #[test]
+    fn test_sign() {
+        let secret = NodeSecret::random();
+        let public_key = secret.public_key();
+        let sig = secret.sign(b"message");
+        assert!(public_key.verify(b"message", &sig));
+        assert!(!public_key.verify(b"other message", &sig));
+        assert!(!NodeSecret::random().public_key().verify(b"message", &sig));
+    }
+
+    #[test]
+    fn test_distance() {
+        let id = |n: u64| NodeID(U256::from(n));
+        assert_eq!(U256::from(6), id(3).distance(&id(5)));
+        assert_eq!(id(5).distance(&id(3)), id(3).distance(&id(5)));
+        assert_eq!(None, id(3).bucket_index(&id(3)));
+        assert_eq!(Some(0), id(2).bucket_index(&id(3)));
+        assert_eq!(Some(2), id(3).bucket_index(&id(5)));
+        assert_eq!(Some(255), NodeID::zero().bucket_index(&NodeID(U256::MAX)));
+    }
+
+    #[test]
+    fn test_group_parse() {
+        assert_eq!(GroupID(U256::from(0x2a)), "0x2a".parse().unwrap());
+        assert_eq!(GroupID(U256::from(0x2a)), "2A".parse().unwrap());
+        assert!("".parse::<GroupID>().is_err());
+        assert!("0xg".parse::<GroupID>().is_err());
+        assert!("0x0x2a".parse::<GroupID>().is_err());
+        assert!("1".repeat(65).parse::<GroupID>().is_err());
+    }
+
+    #[test]
+    fn test_id_from_key() {
+        let secret = NodeSecret::random();
+        let key = secret.public_key();
+        assert_eq!(NodeID::from(secret), NodeID::from(key));
+        assert_ne!(NodeID::from(secret), NodeID::from(NodeSecret::random()));
+
+        // Keys and signatures survive the round-trip through hexadecimal.
+        assert_eq!(Ok(key), format!("{key:#x}").parse());
+        let sig = secret.sign(b"message");
+        assert_eq!(Ok(sig), format!("{sig:x}").parse());
+        assert!("".parse::<Signature>().is_err());
+        assert!("0x".parse::<NodePublicKey>().is_err());
+        assert!(format!("{sig:x}").parse::<NodePublicKey>().is_err());
+    }
 }
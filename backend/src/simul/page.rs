@@ -0,0 +1,152 @@
+// Static pages hosted by the nodes.
+// A page is identified by its domain and its path inside the domain, like
+// the `cyno://domain/path` URLs of the frontend.
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The content of a file, together with its MIME type.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Page {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// A page together with where it is found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageFile {
+    pub domain: String,
+    pub path: String,
+    pub page: Page,
+}
+
+impl Page {
+    /// Creates a page with the content type guessed from the extension
+    /// of the path.
+    pub fn new(path: &str, data: Vec<u8>) -> Self {
+        Self {
+            content_type: content_type(path).into(),
+            data,
+        }
+    }
+}
+
+impl std::fmt::Debug for Page {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            formatter,
+            "{}: {} bytes",
+            self.content_type,
+            self.data.len()
+        )
+    }
+}
+
+/// Returns the MIME type for the extension of the path.
+pub fn content_type(path: &str) -> &'static str {
+    let ext = path.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+    match ext.to_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Returns the path of the file to serve: an empty path, or one ending in
+/// a '/', points to the 'index.html' of that directory.
+pub fn normalize_path(path: &str) -> String {
+    let path = path.trim_start_matches('/');
+    if path.is_empty() || path.ends_with('/') {
+        format!("{path}index.html")
+    } else {
+        path.to_string()
+    }
+}
+
+/// Reads all files below `dir`. Every directory in `dir` is a domain, and
+/// the files below it are the pages of this domain.
+/// The returned pages are sorted by domain and path.
+pub fn load_dir(dir: &Path) -> Result<Vec<PageFile>, Box<dyn Error>> {
+    let mut pages = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let domain = entry.file_name().to_string_lossy().to_string();
+        let mut files = vec![];
+        list_files(&entry.path(), &mut files)?;
+        for file in files {
+            let path = file
+                .strip_prefix(entry.path())?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            pages.push(PageFile {
+                domain: domain.clone(),
+                page: Page::new(&path, fs::read(&file)?),
+                path,
+            });
+        }
+    }
+    pages.sort_by(|a, b| (&a.domain, &a.path).cmp(&(&b.domain, &b.path)));
+    Ok(pages)
+}
+
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_dir() -> Result<(), Box<dyn Error>> {
+        let tmp = tempfile::tempdir()?;
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("ineiti/img"))?;
+        fs::write(dir.join("ineiti/index.html"), "<h1>Hi</h1>")?;
+        fs::write(dir.join("ineiti/img/logo.PNG"), [0x89, 0x50])?;
+        fs::write(dir.join("README"), "not a domain")?;
+
+        let pages = load_dir(dir)?;
+        assert_eq!(2, pages.len());
+        assert_eq!(
+            ("ineiti", "img/logo.PNG"),
+            (&*pages[0].domain, &*pages[0].path)
+        );
+        assert_eq!("image/png", pages[0].page.content_type);
+        assert_eq!("index.html", pages[1].path);
+        assert_eq!(b"<h1>Hi</h1>".to_vec(), pages[1].page.data);
+
+        assert_eq!("index.html", normalize_path(""));
+        assert_eq!("blog/index.html", normalize_path("/blog/"));
+        assert_eq!("styles.css", normalize_path("styles.css"));
+        assert_eq!("application/octet-stream", content_type("Makefile"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+// Keeps copies of every domain on `factor` nodes, so that the pages survive
+// nodes going offline.
+// The replicator follows the nodes joining and leaving the network, and on
+// every tick compares the copies of the domains with the latest manifest
+// seen on their owners. If there are too few up-to-date copies, other nodes
+// are asked to fetch the domain from a node which has it.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use tracing::debug;
+
+use crate::api::storage::{DomainReplicas, ReplicationReport};
+
+use super::{
+    broker::{BMNet, BrokerMsg},
+    msgs::NodeAction,
+    network::Network,
+    node::Node,
+    node_types::{Hash256, NodeID},
+};
+
+/// How long a node has to copy a domain before another node is asked,
+/// in milliseconds.
+const REPLICATION_TIMEOUT: u128 = 10_000;
+
+pub struct Replicator {
+    factor: usize,
+    // Online nodes which can hold copies
+    nodes: BTreeSet<NodeID>,
+    // The latest manifest of every domain, as seen on its owner
+    latest: HashMap<String, Hash256>,
+    // Copies which have been asked for, by domain and node, with the time
+    // of the request and the requested manifest
+    pending: HashMap<(String, NodeID), (u128, Hash256)>,
+}
+
+impl Replicator {
+    pub fn new(factor: usize) -> Self {
+        Self {
+            factor,
+            nodes: BTreeSet::new(),
+            latest: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Nodes without a secret cannot send the copies they hold, so they
+    /// don't get any.
+    pub fn node_add(&mut self, node: &Node) {
+        if node.has_secret() {
+            self.nodes.insert(node.id());
+        }
+    }
+
+    pub fn node_del(&mut self, id: &NodeID) {
+        self.nodes.remove(id);
+        self.pending.retain(|(_, node), _| node != id);
+    }
+
+    /// Forgets a domain which has been removed from all nodes.
+    pub fn remove_domain(&mut self, domain: &str) {
+        self.latest.remove(domain);
+        self.pending.retain(|(d, _), _| d != domain);
+    }
+
+    /// Returns the domains which have fewer up-to-date copies than the
+    /// replication factor. Domains without any copy are lost.
+    pub fn report(&self, network: &Network) -> ReplicationReport {
+        let holders = self.holders(network);
+        let mut domains: Vec<&String> = self.latest.keys().collect();
+        domains.sort();
+        ReplicationReport {
+            factor: self.factor,
+            under_replicated: domains
+                .into_iter()
+                .map(|domain| DomainReplicas {
+                    domain: domain.clone(),
+                    holders: holders.get(domain).cloned().unwrap_or_default(),
+                })
+                .filter(|d| d.holders.len() < self.factor)
+                .collect(),
+        }
+    }
+
+    pub fn tick(&mut self, time: u128, network: &Network) -> Vec<BrokerMsg> {
+        self.pending
+            .retain(|_, (since, _)| *since + REPLICATION_TIMEOUT > time);
+        for node in network.nodes() {
+            let storage = node.storage();
+            for domain in storage.domains() {
+                if storage.owner(&domain) == Some(node.id()) {
+                    let manifest = storage.manifest(&domain).expect("domain is stored");
+                    self.latest.insert(domain, manifest.id());
+                }
+            }
+        }
+
+        let mut msgs = vec![];
+        for (domain, holders) in self.holders(network) {
+            // Done, or outdated because the domain changed.
+            let latest = self.latest[&domain];
+            self.pending.retain(|(d, node), (_, manifest)| {
+                d != &domain || (!holders.contains(node) && manifest == &latest)
+            });
+            let pending = self.pending.keys().filter(|(d, _)| d == &domain).count();
+            let missing = self.factor.saturating_sub(holders.len() + pending);
+            if missing == 0 {
+                continue;
+            }
+            let Some(from) = holders.iter().find(|id| self.nodes.contains(id)) else {
+                debug!("No node can send a copy of {domain}");
+                continue;
+            };
+            let size = network
+                .node(from)
+                .and_then(|n| n.storage().manifest(&domain))
+                .map(|m| m.size())
+                .unwrap_or(0);
+            // Outdated copies first, as they share most of the chunks, then
+            // the nodes with the most free space.
+            let mut candidates: Vec<(bool, u64, NodeID)> = self
+                .nodes
+                .iter()
+                .filter(|id| !holders.contains(id))
+                .filter(|id| !self.pending.contains_key(&(domain.clone(), **id)))
+                .filter_map(|id| network.node(id))
+                .map(|n| (n.storage(), n.id()))
+                .filter(|(s, _)| s.usage().quota - s.usage().used >= size)
+                .map(|(s, id)| (s.owner(&domain).is_none(), s.usage().used, id))
+                .collect();
+            candidates.sort();
+            for (_, _, to) in candidates.into_iter().take(missing) {
+                debug!("Replicating {domain} from {from} to {to}");
+                self.pending.insert((domain.clone(), to), (time, latest));
+                msgs.push(
+                    BMNet::NodeAction {
+                        id: to,
+                        action: NodeAction::Fetch {
+                            domain: domain.clone(),
+                            from: *from,
+                        },
+                    }
+                    .into(),
+                );
+            }
+        }
+        msgs
+    }
+
+    // Returns the sorted nodes holding the latest manifest of every domain.
+    fn holders(&self, network: &Network) -> BTreeMap<String, Vec<NodeID>> {
+        let mut holders: BTreeMap<String, Vec<NodeID>> = BTreeMap::new();
+        for node in network.nodes() {
+            let storage = node.storage();
+            for domain in storage.domains() {
+                let manifest = storage.manifest(&domain).expect("domain is stored");
+                if self.latest.get(&domain) == Some(&manifest.id()) {
+                    holders.entry(domain).or_default().push(node.id());
+                }
+            }
+        }
+        for ids in holders.values_mut() {
+            ids.sort();
+        }
+        holders
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::seq::IteratorRandom;
+
+    use super::*;
+    use crate::simul::{
+        page::{Page, PageFile},
+        trusted::{self, Trusted},
+    };
+
+    fn add_node(network: &mut Network, replicator: &mut Replicator, node: Node) -> NodeID {
+        let id = node.id();
+        replicator.node_add(&node);
+        network.action(BMNet::NodeAdd(node));
+        id
+    }
+
+    fn del_node(network: &mut Network, replicator: &mut Replicator, id: NodeID) {
+        replicator.node_del(&id);
+        network.action(BMNet::NodeDel(id));
+    }
+
+    fn tick(network: &mut Network, replicator: &mut Replicator, time: u128) {
+        for msg in replicator.tick(time, network) {
+            if let BrokerMsg::Network(msg) = msg {
+                network.action(msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_churn() {
+        let trusted = Trusted::new(trusted::Config::default(), 0);
+        let mut network = Network::default();
+        let mut replicator = Replicator::new(3);
+        let mut owner = Node::new(&trusted);
+        let page = Page::new("index.html", b"<h1>Hi</h1>".to_vec());
+        owner
+            .store_page(PageFile {
+                domain: "ineiti".into(),
+                path: "index.html".into(),
+                page: page.clone(),
+            })
+            .unwrap();
+        let owner = add_node(&mut network, &mut replicator, owner);
+        for _ in 0..5 {
+            add_node(&mut network, &mut replicator, Node::new(&trusted));
+        }
+
+        tick(&mut network, &mut replicator, 0);
+        assert_eq!(3, network.holders("ineiti").len());
+        assert!(replicator.report(&network).under_replicated.is_empty());
+
+        // Even without the owner, the copies are kept up.
+        del_node(&mut network, &mut replicator, owner);
+        add_node(&mut network, &mut replicator, Node::new(&trusted));
+        tick(&mut network, &mut replicator, 1);
+        for time in 2..50 {
+            let holders = network.holders("ineiti");
+            assert!(!holders.is_empty());
+            for id in holders {
+                assert_eq!(
+                    Some(&page),
+                    network
+                        .node(&id)
+                        .unwrap()
+                        .page("ineiti", "index.html")
+                        .as_ref()
+                );
+            }
+            // Two of the holders go offline, and new nodes come online.
+            let gone = network
+                .holders("ineiti")
+                .into_iter()
+                .choose_multiple(&mut rand::thread_rng(), 2);
+            for id in gone {
+                del_node(&mut network, &mut replicator, id);
+            }
+            assert_eq!(1, replicator.report(&network).under_replicated.len());
+            for _ in 0..2 {
+                add_node(&mut network, &mut replicator, Node::new(&trusted));
+            }
+            tick(&mut network, &mut replicator, time);
+            assert_eq!(3, network.holders("ineiti").len());
+        }
+
+        // Without any copy the domain is reported as lost.
+        for id in network.holders("ineiti") {
+            del_node(&mut network, &mut replicator, id);
+        }
+        let report = replicator.report(&network);
+        assert_eq!("ineiti", report.under_replicated[0].domain);
+        assert!(report.under_replicated[0].holders.is_empty());
+    }
+
+    #[test]
+    fn test_update() {
+        let trusted = Trusted::new(trusted::Config::default(), 0);
+        let mut network = Network::default();
+        let mut replicator = Replicator::new(2);
+        let owner = add_node(&mut network, &mut replicator, Node::new(&trusted));
+        let other = add_node(&mut network, &mut replicator, Node::new(&trusted));
+        let store = |network: &mut Network, data: &[u8]| {
+            network
+                .node_mut(&owner)
+                .unwrap()
+                .store_page(PageFile {
+                    domain: "ineiti".into(),
+                    path: "index.html".into(),
+                    page: Page::new("index.html", data.to_vec()),
+                })
+                .unwrap();
+        };
+
+        store(&mut network, b"first");
+        tick(&mut network, &mut replicator, 0);
+        assert_eq!(2, network.holders("ineiti").len());
+
+        // A changed domain makes the copy outdated, so it is replaced.
+        store(&mut network, b"second");
+        assert_eq!(
+            1,
+            replicator.report(&network).under_replicated[0]
+                .holders
+                .len()
+        );
+        tick(&mut network, &mut replicator, 1);
+        let copy = network.node(&other).unwrap().page("ineiti", "index.html");
+        assert_eq!(Some(b"second".to_vec()), copy.map(|p| p.data));
+        assert!(replicator.report(&network).under_replicated.is_empty());
+    }
+}
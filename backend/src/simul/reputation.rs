@@ -0,0 +1,125 @@
+// The reputation network of Trusted: nodes vote whether the content of a
+// group belongs to a category of undesirable content, and the votes are
+// weighted by the mana of the voters when they are counted.
+
+use std::collections::BTreeMap;
+
+use crate::api::reputation::{Category, CategoryScore, GroupReputation};
+
+use super::node_types::{GroupID, Mana, NodeID};
+
+/// The latest vote of every node, for every group and category.
+#[derive(Debug, Default, Clone)]
+pub struct Reputation {
+    votes: BTreeMap<(GroupID, Category, NodeID), bool>,
+}
+
+/// A vote as stored in the state of Trusted.
+pub type Vote = ((GroupID, Category, NodeID), bool);
+
+impl Reputation {
+    /// Stores the vote of the node, replacing its previous vote for the
+    /// group and the category.
+    pub fn vote(&mut self, voter: NodeID, group: GroupID, category: Category, flag: bool) {
+        self.votes.insert((group, category, voter), flag);
+    }
+
+    /// Removes all votes of the node.
+    pub fn remove_voter(&mut self, voter: &NodeID) {
+        self.votes.retain(|(_, _, id), _| id != voter);
+    }
+
+    /// Adds up the votes for the group, weighted by the mana returned for
+    /// each voter. A category is flagged if the flags outweigh the votes
+    /// against them, and have at least `min_weight`.
+    pub fn group(
+        &self,
+        group: GroupID,
+        weight: impl Fn(&NodeID) -> Mana,
+        min_weight: Mana,
+    ) -> GroupReputation {
+        let mut scores: BTreeMap<Category, (Mana, Mana)> = BTreeMap::new();
+        let first = (group, Category::ALL[0], NodeID::zero());
+        for ((_, category, voter), flag) in self
+            .votes
+            .range(first..)
+            .take_while(|((g, _, _), _)| g == &group)
+        {
+            let (flags, clears) = scores.entry(*category).or_default();
+            if *flag {
+                *flags += weight(voter);
+            } else {
+                *clears += weight(voter);
+            }
+        }
+        GroupReputation {
+            group,
+            categories: scores
+                .into_iter()
+                .map(|(category, (flag, clear))| CategoryScore {
+                    category,
+                    flag,
+                    clear,
+                    flagged: flag > clear && flag >= min_weight,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn votes(&self) -> Vec<Vote> {
+        self.votes.iter().map(|(key, flag)| (*key, *flag)).collect()
+    }
+}
+
+impl From<Vec<Vote>> for Reputation {
+    fn from(votes: Vec<Vote>) -> Self {
+        Self {
+            votes: votes.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_group() {
+        let mut reputation = Reputation::default();
+        let (group, other) = (GroupID::random(), GroupID::random());
+        let voters: Vec<NodeID> = (0..3).map(|_| NodeID::random()).collect();
+        let mana = |id: &NodeID| -> Mana {
+            match voters.iter().position(|v| v == id) {
+                Some(i) => (10 * (i as u128 + 1)).into(),
+                None => Mana::zero(),
+            }
+        };
+        reputation.vote(voters[0], group, Category::Nsfw, true);
+        reputation.vote(voters[1], group, Category::Nsfw, true);
+        reputation.vote(voters[2], group, Category::Nsfw, false);
+        reputation.vote(voters[2], group, Category::Spam, true);
+        reputation.vote(voters[0], other, Category::Scam, true);
+
+        // 30 mana for and against nsfw is not enough to flag it.
+        let rep = reputation.group(group, mana, 20.into());
+        assert_eq!(vec![Category::Spam], rep.flagged());
+        assert_eq!(2, rep.categories.len());
+        reputation.vote(voters[2], group, Category::Nsfw, true);
+        let rep = reputation.group(group, mana, 20.into());
+        assert_eq!(vec![Category::Nsfw, Category::Spam], rep.flagged());
+        assert_eq!(Mana::from(60), rep.categories[0].flag);
+
+        // Votes need the minimum weight, and removed voters don't count.
+        assert!(reputation
+            .group(other, mana, 20.into())
+            .flagged()
+            .is_empty());
+        reputation.remove_voter(&voters[2]);
+        let rep = reputation.group(group, mana, 20.into());
+        assert_eq!(vec![Category::Nsfw], rep.flagged());
+        assert_eq!(
+            rep,
+            Reputation::from(reputation.votes()).group(group, mana, 20.into())
+        );
+    }
+}
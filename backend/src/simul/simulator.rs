@@ -1,25 +1,34 @@
-use std::{error::Error, sync::mpsc::Sender};
+use std::{error::Error, path::PathBuf, sync::mpsc::Sender};
 
 use rand::random;
-use tracing::{error, trace};
+use tracing::{error, trace, warn};
 
 use super::{
     broker::{BMNet, BMSimul, BrokerMsg},
-    node::Node,
-    node_types::NodeID,
+    msgs::NodeAction,
+    network,
+    node::{Node, NodeClass},
+    node_types::{NodeID, NodeSecret},
+    page::{self, PageFile},
     trusted::{TReqMsg, TrustedReply, TrustedRequest},
 };
 
 pub struct Simulator {
     nodes: Vec<NodeFlex>,
     trusted: Sender<TrustedRequest>,
+    // If nodes went on- or offline since the last republishing
+    churn: bool,
 }
 
 pub struct NodeFlex {
     id: NodeID,
+    secret: NodeSecret,
     online: bool,
     p_sign_in: u16,
     p_sign_out: u16,
+    // The pages hosted by this node
+    pages: Vec<PageFile>,
+    class: NodeClass,
 }
 
 #[derive(Debug, Clone)]
@@ -32,15 +41,25 @@ pub struct Config {
     pub p_sign_in: u16,
     // The probability (0..2**16-1) for an online flex-node to go offline after a tick.
     pub p_sign_out: u16,
+    // Directory with one sub-directory per domain, whose pages are hosted
+    // by the root nodes.
+    pub pages_dir: Option<PathBuf>,
+    // On how many online nodes every domain is kept.
+    pub replication_factor: usize,
+    // How the links of the nodes to the network behave.
+    pub network: network::Config,
 }
 
-impl Config {
-    pub fn default() -> Self {
+impl Default for Config {
+    fn default() -> Self {
         Self {
             nodes_root: 5,
             nodes_flex: 10,
             p_sign_in: 0x1000,
             p_sign_out: 0xa00,
+            pages_dir: None,
+            replication_factor: 3,
+            network: network::Config::default(),
         }
     }
 }
@@ -49,36 +68,72 @@ impl Simulator {
     /// TODO: probably this'll need a Trusted tx-channel later on.
     pub fn new(
         config: Config,
-        node_ids: Vec<NodeID>,
+        secrets: Vec<NodeSecret>,
         trusted: Sender<TrustedRequest>,
     ) -> Result<Self, Box<dyn Error>> {
-        if node_ids.len() != config.nodes_root + config.nodes_flex {
+        if secrets.len() != config.nodes_root + config.nodes_flex {
             return Err("wrong number of nodes".into());
         }
+        let pages = match &config.pages_dir {
+            Some(dir) => page::load_dir(dir)?,
+            None => vec![],
+        };
+        let mut nodes = Self::node_flex(config.clone(), secrets);
+        Self::host_pages(&mut nodes[..config.nodes_root], pages)?;
         Ok(Self {
-            nodes: Self::node_flex(config, node_ids),
+            nodes,
             trusted,
+            churn: false,
         })
     }
 
-    fn node_flex(config: Config, ids: Vec<NodeID>) -> Vec<NodeFlex> {
-        let mut nf = Self::node_flex_part(ids[0..config.nodes_root].to_vec(), u16::MAX, 0);
+    // Distributes the domains over the nodes, so that all pages of a domain
+    // are on the same node.
+    fn host_pages(nodes: &mut [NodeFlex], pages: Vec<PageFile>) -> Result<(), Box<dyn Error>> {
+        let mut domains: Vec<String> = pages.iter().map(|p| p.domain.clone()).collect();
+        domains.dedup();
+        if !domains.is_empty() && nodes.is_empty() {
+            return Err("no root nodes to host the pages".into());
+        }
+        for page in pages {
+            let index = domains.iter().position(|d| d == &page.domain).unwrap();
+            nodes[index % nodes.len()].pages.push(page);
+        }
+        Ok(())
+    }
+
+    fn node_flex(config: Config, secrets: Vec<NodeSecret>) -> Vec<NodeFlex> {
+        let mut nf = Self::node_flex_part(
+            secrets[0..config.nodes_root].to_vec(),
+            u16::MAX,
+            0,
+            NodeClass::Root,
+        );
         nf.append(&mut Self::node_flex_part(
-            ids[config.nodes_root..].to_vec(),
+            secrets[config.nodes_root..].to_vec(),
             config.p_sign_in,
             config.p_sign_out,
+            NodeClass::Flex,
         ));
         nf
     }
 
-    fn node_flex_part(node_ids: Vec<NodeID>, p_sign_in: u16, p_sign_out: u16) -> Vec<NodeFlex> {
-        node_ids
+    fn node_flex_part(
+        secrets: Vec<NodeSecret>,
+        p_sign_in: u16,
+        p_sign_out: u16,
+        class: NodeClass,
+    ) -> Vec<NodeFlex> {
+        secrets
             .iter()
-            .map(|n| NodeFlex {
-                id: *n,
+            .map(|s| NodeFlex {
+                id: (*s).into(),
+                secret: *s,
                 online: false,
                 p_sign_in,
                 p_sign_out,
+                pages: vec![],
+                class,
             })
             .collect()
     }
@@ -93,13 +148,22 @@ impl Simulator {
         for node in &mut self.nodes {
             if node.online && node.p_sign_out > 0 && node.p_sign_out > random::<u16>() {
                 node.online = false;
+                self.churn = true;
                 answer.push(BMNet::NodeDel(node.id).into());
-            } else if node.online == false && node.p_sign_in > random::<u16>() {
+            } else if !node.online && node.p_sign_in > random::<u16>() {
                 node.online = true;
+                self.churn = true;
                 match TReqMsg::Info(node.id).send(&self.trusted) {
                     Ok(reply) => {
                         if let TrustedReply::NodeInfo(Some(ni)) = reply {
-                            answer.push(BMNet::NodeAdd(Node::from_info(ni, &self.trusted)).into());
+                            let mut n = Node::from_info(ni, Some(node.secret), &self.trusted)
+                                .with_class(node.class);
+                            for page in &node.pages {
+                                if let Err(e) = n.store_page(page.clone()) {
+                                    warn!("Cannot host {}/{}: {e}", page.domain, page.path);
+                                }
+                            }
+                            answer.push(BMNet::NodeAdd(n).into());
                         }
                     }
                     Err(_) => error!("Didn't find node {:?}", node.id),
@@ -113,6 +177,28 @@ impl Simulator {
         }
         answer
     }
+
+    /// After nodes went on- or offline, asks the online nodes to store
+    /// their values of the DHT again, so they are kept by the nodes which
+    /// are now closest to their keys.
+    /// This must be called once the nodes of the last tick are in the
+    /// network.
+    pub fn republish(&mut self) -> Vec<BrokerMsg> {
+        if !std::mem::take(&mut self.churn) {
+            return vec![];
+        }
+        self.nodes
+            .iter()
+            .filter(|n| n.online)
+            .map(|n| {
+                BMNet::NodeAction {
+                    id: n.id,
+                    action: NodeAction::Republish,
+                }
+                .into()
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -132,11 +218,11 @@ mod test {
     #[test]
     fn test_online() -> Result<(), Box<dyn Error>> {
         let cfg = Config::default();
-        let ids = (0..cfg.nodes_root + cfg.nodes_flex)
-            .map(|_| NodeID::random())
+        let secrets = (0..cfg.nodes_root + cfg.nodes_flex)
+            .map(|_| NodeSecret::random())
             .collect();
         let trusted = Trusted::new_default(0);
-        let mut simul = Simulator::new(cfg.clone(), ids, trusted)?;
+        let mut simul = Simulator::new(cfg.clone(), secrets, trusted)?;
         assert_eq!(0, simul.nodes_online());
 
         // Make sure that the number of nodes fluctuates somehow.
@@ -1,13 +1,31 @@
 use std::{
-    collections::HashMap,
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap},
     error::Error,
+    path::PathBuf,
     sync::mpsc::{self, channel, Receiver, Sender},
     thread,
 };
 
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, trace, warn};
 
-use super::{node::NodeInfo, node_types::{NodeID, Mana}};
+use crate::api::{
+    groups::GroupError,
+    names::{normalize_name, NameError, NameRecord},
+    reputation::{ContentPolicy, GroupReputation, ReputationVote},
+    stats::{NetworkStatus, NodeSummary},
+    storage::DeletableContent,
+    wallet::{TransferHistory, TransferRecord, TransferRequest},
+};
+
+use super::{
+    journal::Journal,
+    ledger::{Block, Ledger, LedgerState, LedgerStatus, Transition},
+    mana_policy::{ManaPolicy, ManaPolicyConfig, NodeState, Resources},
+    node::{NodeInfo, Receipt},
+    node_types::{GroupID, Hash256, Mana, NodeID, Signature},
+    reputation::{Reputation, Vote},
+};
 
 /// Trusted is a blockchain simulation.
 /// In the simulation it replaces a central server with global knowledge.
@@ -17,11 +35,28 @@ use super::{node::NodeInfo, node_types::{NodeID, Mana}};
 ///
 /// Currently, Trusted is responsible for the following:
 /// - mark nodes as inactive if no 'active' message is received
-/// - increase mana for active nodes (1 / s)
-/// - decrease mana for inactive nodes (1 / (86_400 * 7 / 3_600)s)
-///   This means a node running for 1h stays in the list for 1 week
-/// - clean up nodes once they reach 0 mana
-
+/// - change the mana of the nodes according to the `ManaPolicy`, by default:
+///   - increase mana for active nodes (1 / s)
+///   - decrease mana for inactive nodes (1 / (86_400 * 7 / 3_600)s)
+///     This means a node running for 1h stays in the list for 1 week
+///   - charge the owners rent for their content stored in the network
+///     (1 / MB / minute)
+/// - clean up inactive nodes once they cannot pay their debits
+/// - put the content of owners who cannot pay their debits on the list of
+///   deletable content
+/// - transfer mana between nodes, if signed by the sender
+/// - reward nodes for the pages they served, as confirmed by the receipts
+///   of the viewers
+/// - keep the members of the groups of nodes, like the nodes storing a
+///   website
+/// - register names for the groups, which cost mana and expire unless they
+///   are renewed
+/// - count the votes of the nodes on the content of the groups, weighted by
+///   their mana, and keep the content policies of the nodes
+/// - seal all changes of the state in a new block of the `Ledger` every tick
+///
+/// If `Config::data_dir` is set, all requests changing the state are written
+/// to a journal, and the state is restored from there on startup.
 pub struct Trusted {
     // The configuration of this Trusted service
     config: Config,
@@ -29,29 +64,80 @@ pub struct Trusted {
     nodes: HashMap<NodeID, NodeData>,
     // Nodes can send requests here
     ch_request_rx: Receiver<TrustedRequest>,
-    // Last mana increase
-    last_mana_inc: u128,
-    // Last mana decrease
-    last_mana_dec: u128,
+    // How the mana of the nodes changes on every tick
+    policy: Box<dyn ManaPolicy>,
     // Latest tick time
     last_tick_time: u128,
+    // Persistence of the state, if enabled
+    journal: Option<Journal<TrustedState, TReqMsg>>,
+    // All transitions of the state
+    ledger: Ledger,
+    // Owners whose content can be deleted, with the time since when
+    deletable: BTreeMap<NodeID, u128>,
+    // The members of every group, online or not
+    groups: BTreeMap<GroupID, BTreeSet<NodeID>>,
+    // The registered names, including the expired ones in their grace period
+    names: BTreeMap<String, NameRecord>,
+    // The votes on the content of the groups
+    reputation: Reputation,
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
+    // Used by `ManaPolicyConfig::Time`.
     pub time_mana_increase: u128,
     pub time_mana_decrease: u128,
     pub time_node_active: u128,
+    // Where the state is stored. If None, the state is only kept in memory.
+    pub data_dir: Option<PathBuf>,
+    // After how many journal entries a new snapshot is written.
+    pub snapshot_entries: usize,
+    // Which rules are used to credit and debit mana, including the rent for
+    // the content stored in the network.
+    pub mana_policy: ManaPolicyConfig,
+    // How long the content of an owner who cannot pay its debits is kept.
+    pub storage_grace: u128,
+    // Serving nodes get 1 mana for every `served_unit` bytes confirmed by
+    // the receipts of the viewers. 0 disables the reward.
+    pub served_unit: u64,
+    // If true, the viewers pay the reward of the serving nodes, else the
+    // reward is created.
+    pub viewer_pays: bool,
+    // Registering or renewing a name costs `name_fee` mana, and extends it
+    // by `name_period`. Once expired, only the owner can renew the name
+    // during `name_grace`, afterwards anybody can register it.
+    pub name_fee: u128,
+    pub name_period: u128,
+    pub name_grace: u128,
+    // A group is only flagged for a category if the voters flagging it have
+    // at least this much mana together.
+    pub reputation_min_weight: u128,
 }
 
 const TIME_SECOND: u128 = 1_000;
 
-impl Config {
-    pub fn default() -> Self {
+impl Default for Config {
+    fn default() -> Self {
         Self {
             time_mana_increase: TIME_SECOND,
             time_mana_decrease: (86_400 * 7 * TIME_SECOND / 3_600),
             time_node_active: 60 * TIME_SECOND,
+            data_dir: None,
+            snapshot_entries: 10_000,
+            mana_policy: ManaPolicyConfig::Combined(vec![
+                ManaPolicyConfig::Time,
+                ManaPolicyConfig::StorageRent {
+                    period: 60 * TIME_SECOND,
+                    storage_unit: 1024 * 1024,
+                },
+            ]),
+            storage_grace: 86_400 * TIME_SECOND,
+            served_unit: 1024 * 1024,
+            viewer_pays: false,
+            name_fee: 100,
+            name_period: 30 * 86_400 * TIME_SECOND,
+            name_grace: 7 * 86_400 * TIME_SECOND,
+            reputation_min_weight: 100,
         }
     }
 }
@@ -59,20 +145,46 @@ impl Config {
 impl Trusted {
     /// Create a new trusted service.
     /// Communication happens through the returned channel.
+    /// Panics if the state cannot be restored from `config.data_dir`.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(config: Config, now: u128) -> Sender<TrustedRequest> {
+        Self::restore(config, now).expect("Couldn't restore Trusted")
+    }
+
+    /// Create a new trusted service, restoring the state from the snapshot
+    /// and the journal in `config.data_dir`, if set.
+    /// `now` is only used if there is no stored state.
+    pub fn restore(config: Config, now: u128) -> Result<Sender<TrustedRequest>, Box<dyn Error>> {
         let (ch_request_tx, ch_request_rx) = mpsc::channel::<TrustedRequest>();
-        thread::spawn(move || {
-            Self {
-                config,
-                nodes: HashMap::new(),
-                ch_request_rx,
-                last_mana_inc: now,
-                last_mana_dec: now,
-                last_tick_time: now,
+        let mut trusted = Self {
+            policy: config.mana_policy.build(&config),
+            config,
+            nodes: HashMap::new(),
+            ch_request_rx,
+            last_tick_time: now,
+            journal: None,
+            ledger: Ledger::default(),
+            deletable: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            names: BTreeMap::new(),
+            reputation: Reputation::default(),
+        };
+        if let Some(dir) = &trusted.config.data_dir {
+            let (journal, state, entries) = Journal::<TrustedState, TReqMsg>::open(dir)?;
+            let ledger = state.as_ref().map(|s| s.ledger.clone());
+            trusted.ledger = Ledger::open(dir, ledger.unwrap_or_default())?;
+            if let Some(state) = state {
+                trusted.set_state(state);
             }
-            .listen();
-        });
-        ch_request_tx
+            info!("Replaying {} journal entries", entries.len());
+            for entry in &entries {
+                trusted.handle(entry);
+            }
+            trusted.journal = Some(journal);
+            trusted.snapshot();
+        }
+        thread::spawn(move || trusted.listen());
+        Ok(ch_request_tx)
     }
 
     /// Creates a new trusted service with default values.
@@ -83,34 +195,23 @@ impl Trusted {
     fn listen(&mut self) {
         loop {
             match self.ch_request_rx.recv() {
-                Ok(msg) => match &msg.message {
-                    TReqMsg::Register(ni) => {
-                        debug!("Registering node {ni}");
-                        self.nodes.insert(
-                            ni.id,
-                            NodeData {
-                                info: ni.clone(),
-                                active_until: self.last_tick_time + self.config.time_node_active,
-                            },
-                        );
-                        msg.reply(TrustedReply::NodeList(self.get_node_list()));
-                    }
-                    TReqMsg::Close => {
+                Ok(msg) => {
+                    if let TReqMsg::Close = msg.message {
                         warn!("Closing Trusted");
                         return;
                     }
-                    TReqMsg::Tick(now) => {
-                        self.tick(*now);
-                        msg.reply(TrustedReply::OK);
+                    if msg.message.is_update() {
+                        self.journal(&msg.message);
                     }
-                    TReqMsg::Alive(id) => msg.reply(self.alive(id)),
-                    TReqMsg::Info(id) => {
-                        trace!("Got asked for node {id}");
-                        msg.reply(TrustedReply::NodeInfo(
-                            self.nodes.get(id).map(|n| n.info.clone()),
-                        ))
+                    msg.reply(self.handle(&msg.message));
+                    if self
+                        .journal
+                        .as_ref()
+                        .is_some_and(|j| j.entries() >= self.config.snapshot_entries)
+                    {
+                        self.snapshot();
                     }
-                },
+                }
                 Err(e) => {
                     info!("Trusted listener closed with error: {e}");
                     return;
@@ -119,44 +220,607 @@ impl Trusted {
         }
     }
 
+    // Handles all messages except for 'Close', both new ones and the ones
+    // replayed from the journal.
+    fn handle(&mut self, msg: &TReqMsg) -> TrustedReply {
+        match msg {
+            TReqMsg::Register(ni) => {
+                debug!("Registering node {ni}");
+                if ni.id != ni.public_key.into() {
+                    return TrustedReply::ErrorMsg(format!(
+                        "ID of node {ni} doesn't match its public key"
+                    ));
+                }
+                let active_until = self.last_tick_time + self.config.time_node_active;
+                match self.nodes.get_mut(&ni.id) {
+                    // Keep the mana and the nonces: the node only updates
+                    // its name and key, and old transfers and receipts
+                    // cannot be replayed.
+                    Some(nd) => {
+                        nd.info.name = ni.name.clone();
+                        nd.info.public_key = ni.public_key;
+                        nd.active_until = active_until;
+                    }
+                    None => {
+                        self.nodes
+                            .insert(ni.id, NodeData::new(ni.clone(), active_until));
+                    }
+                }
+                self.ledger.record(Transition::Register {
+                    id: ni.id,
+                    name: ni.name.clone(),
+                });
+                TrustedReply::NodeList(self.get_node_list())
+            }
+            TReqMsg::Tick(now) => {
+                self.tick(*now);
+                TrustedReply::OK
+            }
+            TReqMsg::Alive(id) => self.alive(id),
+            TReqMsg::Resources(id, resources) => match self.nodes.get_mut(id) {
+                Some(node) => {
+                    node.resources = *resources;
+                    TrustedReply::OK
+                }
+                None => TrustedReply::ErrorMsg("Node not registered".into()),
+            },
+            TReqMsg::Stored(id, bytes) => {
+                self.stored(*id, *bytes);
+                TrustedReply::OK
+            }
+            TReqMsg::Deletable => TrustedReply::Deletable(
+                self.deletable
+                    .iter()
+                    .map(|(owner, since)| DeletableContent {
+                        owner: *owner,
+                        since: *since,
+                        delete_after: since + self.config.storage_grace,
+                    })
+                    .collect(),
+            ),
+            TReqMsg::Info(id) => {
+                trace!("Got asked for node {id}");
+                TrustedReply::NodeInfo(self.nodes.get(id).map(|n| n.info.clone()))
+            }
+            TReqMsg::NetworkStatus => TrustedReply::NetworkStatus(self.network_status()),
+            TReqMsg::Block(height) => TrustedReply::Block(self.ledger.block(*height)),
+            TReqMsg::LedgerStatus => TrustedReply::LedgerStatus(self.ledger.status()),
+            TReqMsg::Transfer {
+                from,
+                to,
+                amount,
+                nonce,
+                signature,
+            } => match self.transfer(*from, *to, *amount, *nonce, signature) {
+                Ok(mana) => TrustedReply::Mana(mana),
+                Err(e) => TrustedReply::ErrorMsg(e),
+            },
+            TReqMsg::Receipt(receipt) => match self.receipt(receipt) {
+                Ok(mana) => TrustedReply::Mana(mana),
+                Err(e) => TrustedReply::ErrorMsg(e),
+            },
+            TReqMsg::ReceiptNonce(id) => {
+                TrustedReply::Nonce(self.nodes.get(id).map(|nd| nd.receipt_nonce))
+            }
+            TReqMsg::Transfers(id) => {
+                TrustedReply::Transfers(self.nodes.get(id).map(|nd| TransferHistory {
+                    nonce: nd.nonce,
+                    transfers: nd.transfers.clone(),
+                }))
+            }
+            TReqMsg::GroupJoin(id, group) => TrustedReply::Group(self.group_join(*id, *group)),
+            TReqMsg::GroupLeave(id, group) => TrustedReply::Group(self.group_leave(*id, *group)),
+            TReqMsg::GroupMembers(group) => TrustedReply::Members(self.group_members(group)),
+            TReqMsg::NameRegister { name, owner, group } => {
+                TrustedReply::Name(self.name_register(name, *owner, *group))
+            }
+            TReqMsg::NameTransfer { name, from, to } => {
+                TrustedReply::Name(self.name_transfer(name, *from, *to))
+            }
+            TReqMsg::Resolve(name) => TrustedReply::Name(self.resolve(name)),
+            TReqMsg::Vote(vote) => match self.vote(vote) {
+                Ok(()) => TrustedReply::Reputation(self.group_reputation(vote.group)),
+                Err(e) => TrustedReply::ErrorMsg(e),
+            },
+            TReqMsg::Reputation(group) => TrustedReply::Reputation(self.group_reputation(*group)),
+            TReqMsg::Preferences(id, policy) => {
+                TrustedReply::Preferences(self.nodes.get_mut(id).map(|node| {
+                    node.preferences = Some(policy.clone());
+                    policy.clone()
+                }))
+            }
+            TReqMsg::GetPreferences(id) => TrustedReply::Preferences(
+                self.nodes
+                    .get(id)
+                    .map(|node| node.preferences.clone().unwrap_or_default()),
+            ),
+            TReqMsg::Close => TrustedReply::OK,
+        }
+    }
+
+    // Writes the message to the journal before it is handled.
+    fn journal(&mut self, msg: &TReqMsg) {
+        if let Some(journal) = &mut self.journal {
+            if let Err(e) = journal.append(msg) {
+                error!("While writing {msg:?} to the journal: {e:?}");
+            }
+        }
+    }
+
+    fn snapshot(&mut self) {
+        let state = self.state();
+        if let Some(journal) = &mut self.journal {
+            if let Err(e) = journal.snapshot(&state) {
+                error!("While writing snapshot: {e:?}");
+            }
+        }
+    }
+
+    fn state(&self) -> TrustedState {
+        TrustedState {
+            nodes: self.nodes.values().cloned().collect(),
+            last_tick_time: self.last_tick_time,
+            ledger: self.ledger.state(),
+            deletable: self.deletable.iter().map(|(id, t)| (*id, *t)).collect(),
+            groups: self
+                .groups
+                .iter()
+                .map(|(group, ids)| (*group, ids.iter().copied().collect()))
+                .collect(),
+            names: self.names.values().cloned().collect(),
+            reputation: self.reputation.votes(),
+        }
+    }
+
+    fn set_state(&mut self, state: TrustedState) {
+        self.nodes = state.nodes.into_iter().map(|nd| (nd.info.id, nd)).collect();
+        self.last_tick_time = state.last_tick_time;
+        self.deletable = state.deletable.into_iter().collect();
+        self.groups = state
+            .groups
+            .into_iter()
+            .map(|(group, ids)| (group, ids.into_iter().collect()))
+            .collect();
+        self.names = state
+            .names
+            .into_iter()
+            .map(|record| (record.name.clone(), record))
+            .collect();
+        self.reputation = state.reputation.into();
+    }
+
+    // Returns the ids of all nodes in a fixed order.
+    fn sorted_ids(&self) -> Vec<NodeID> {
+        let mut ids: Vec<NodeID> = self.nodes.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    // Hashes the id, mana, and activity of all nodes, and the deletable
+    // content, groups, names, and votes, which the transitions change.
+    fn state_root(&self) -> Hash256 {
+        let mut data = vec![];
+        for id in self.sorted_ids() {
+            let node = &self.nodes[&id];
+            data.extend_from_slice(&id.to_bytes());
+            data.extend_from_slice(&node.info.mana.to_bytes());
+            data.extend_from_slice(&node.active_until.to_be_bytes());
+        }
+        let state = (
+            &self.deletable,
+            &self.groups,
+            &self.names,
+            self.reputation.votes(),
+        );
+        data.extend(serde_json::to_vec(&state).expect("State serializes"));
+        Hash256::digest(&data)
+    }
+
     fn get_node_list(&self) -> Vec<NodeInfo> {
         self.nodes.values().map(|nd| nd.info.clone()).collect()
     }
 
+    fn network_status(&self) -> NetworkStatus {
+        let nodes: Vec<NodeSummary> = self
+            .nodes
+            .values()
+            .map(|nd| NodeSummary {
+                id: nd.info.id,
+                name: nd.info.name.clone(),
+                mana: nd.info.mana,
+                active: nd.is_active(self.last_tick_time),
+            })
+            .collect();
+        let mut mana_total = Mana::zero();
+        nodes.iter().for_each(|n| mana_total += n.mana);
+        NetworkStatus {
+            users_total: nodes.len(),
+            users_active: nodes.iter().filter(|n| n.active).count(),
+            mana_total,
+            nodes,
+        }
+    }
+
     fn tick(&mut self, now: u128) {
-        // Increase the mana for all the nodes which have been active more recent than
-        // config.time_node_active.
-        let mana_inc = (now - self.last_mana_inc) / self.config.time_mana_increase;
-        if mana_inc > 0 {
-            self.nodes.iter_mut().for_each(|(_, n)| {
-                if n.is_active(now) {
-                    n.info.mana += mana_inc.into();
-                }
-            });
-            self.last_mana_inc += mana_inc * self.config.time_mana_increase;
-        }
-
-        // Decrease the mana for all the nodes which have been inactive.
-        let mana_dec = (now - self.last_mana_dec) / self.config.time_mana_decrease;
-        if mana_dec > 0 {
-            let mut nodes_rem = vec![];
-            self.nodes.iter_mut().for_each(|(id, n)| {
-                if !n.is_active(now) {
-                    if n.info.mana >= mana_dec.into() {
-                        n.info.mana -= mana_dec.into();
-                    } else {
-                        n.info.mana = 0.into();
-                        nodes_rem.push(id.clone());
+        // The nodes are visited in a fixed order, so that replaying the
+        // journal gives the same blocks.
+        let ids = self.sorted_ids();
+
+        for id in &ids {
+            let n = self.nodes.get_mut(id).expect("id from nodes");
+            let active = n.is_active(now);
+            let change = self.policy.change(
+                &NodeState {
+                    mana: n.info.mana,
+                    active,
+                    resources: Resources {
+                        stored: n.stored,
+                        ..n.resources
+                    },
+                },
+                self.last_tick_time,
+                now,
+            );
+            if change.credit > Mana::zero() {
+                n.info.mana += change.credit;
+                self.ledger.record(Transition::ManaCredit {
+                    id: *id,
+                    amount: change.credit,
+                });
+            }
+            // Owners who cannot pay for their content in the network lose
+            // it after `storage_grace`, unless they can pay again before.
+            if n.stored > 0 && change.debit > Mana::zero() {
+                if n.info.mana >= change.debit {
+                    if self.deletable.remove(id).is_some() {
+                        self.ledger.record(Transition::Solvent { id: *id });
                     }
+                } else if let Entry::Vacant(e) = self.deletable.entry(*id) {
+                    e.insert(now);
+                    self.ledger.record(Transition::Bankrupt { id: *id });
                 }
-            });
-            self.last_mana_dec += mana_dec * self.config.time_mana_decrease;
-            for node in nodes_rem{
-                self.nodes.remove(&node);
             }
+            if change.debit == Mana::zero() {
+                continue;
+            }
+            if n.info.mana >= change.debit {
+                n.info.mana -= change.debit;
+                self.ledger.record(Transition::ManaDebit {
+                    id: *id,
+                    amount: change.debit,
+                });
+                continue;
+            }
+            // Nodes which cannot pay lose all their mana, and inactive ones
+            // are removed.
+            if n.info.mana > Mana::zero() {
+                self.ledger.record(Transition::ManaDebit {
+                    id: *id,
+                    amount: n.info.mana,
+                });
+                n.info.mana = Mana::zero();
+            }
+            if !active {
+                let stored = self.nodes.remove(id).map(|n| n.stored).unwrap_or(0);
+                self.ledger.record(Transition::Remove { id: *id });
+                // Removed nodes leave all their groups.
+                self.groups.retain(|_, members| {
+                    members.remove(id);
+                    !members.is_empty()
+                });
+                self.reputation.remove_voter(id);
+                // Nobody pays for the content of removed nodes anymore.
+                if stored > 0 && !self.deletable.contains_key(id) {
+                    self.deletable.insert(*id, now);
+                    self.ledger.record(Transition::Bankrupt { id: *id });
+                }
+            }
+        }
+
+        // Names which were not renewed during the grace period are free again.
+        let grace = self.config.name_grace;
+        let expired: Vec<String> = self
+            .names
+            .values()
+            .filter(|record| record.expires + grace <= now)
+            .map(|record| record.name.clone())
+            .collect();
+        for name in expired {
+            self.names.remove(&name);
+            self.ledger.record(Transition::NameExpire { name });
         }
 
         self.last_tick_time = now;
+        let state_root = self.state_root();
+        let block = self.ledger.seal(now, state_root);
+        trace!("Sealed block {} with hash {}", block.height, block.hash);
+    }
+
+    // Sets the bytes stored for the owner. Content of unknown owners can be
+    // deleted, and owners without content are taken off the deletable list.
+    fn stored(&mut self, id: NodeID, bytes: u64) {
+        match self.nodes.get_mut(&id) {
+            Some(node) => node.stored = bytes,
+            None if bytes > 0 && !self.deletable.contains_key(&id) => {
+                self.deletable.insert(id, self.last_tick_time);
+                self.ledger.record(Transition::Bankrupt { id });
+            }
+            None => {}
+        }
+        if bytes == 0 {
+            self.deletable.remove(&id);
+        }
+    }
+
+    /// Moves mana from one node to another.
+    /// The transfer must be signed by the sender, and the nonce must be the
+    /// next nonce of the sender.
+    /// Returns the remaining mana of the sender.
+    fn transfer(
+        &mut self,
+        from: NodeID,
+        to: NodeID,
+        amount: Mana,
+        nonce: u64,
+        signature: &Signature,
+    ) -> Result<Mana, String> {
+        if from == to {
+            return Err("Cannot transfer to the same node".into());
+        }
+        if amount == Mana::zero() {
+            return Err("Cannot transfer 0 mana".into());
+        }
+        if !self.nodes.contains_key(&to) {
+            return Err("Receiver is not registered".into());
+        }
+        let sender = self.nodes.get(&from).ok_or("Sender is not registered")?;
+        if !sender.info.public_key.verify(
+            &TransferRequest::message(&from, &to, &amount, nonce),
+            signature,
+        ) {
+            return Err("Invalid signature".into());
+        }
+        if nonce != sender.nonce {
+            return Err(format!("Wrong nonce, expected {}", sender.nonce));
+        }
+        if sender.info.mana < amount {
+            return Err("Not enough mana".into());
+        }
+
+        let record = TransferRecord {
+            time: self.last_tick_time,
+            from,
+            to,
+            amount,
+            nonce,
+        };
+        let receiver = self.nodes.get_mut(&to).expect("checked above");
+        receiver.info.mana += amount;
+        receiver.transfers.push(record.clone());
+        let sender = self.nodes.get_mut(&from).expect("checked above");
+        sender.info.mana -= amount;
+        sender.nonce += 1;
+        sender.transfers.push(record);
+        self.ledger.record(Transition::Transfer {
+            from,
+            to,
+            amount,
+            nonce,
+        });
+        Ok(sender.info.mana)
+    }
+
+    /// Rewards the server of a receipt signed by the viewer.
+    /// The served bytes are added up, and the server gets 1 mana for every
+    /// full `served_unit`, paid by the viewer if `viewer_pays` is set.
+    /// Returns the mana of the server.
+    fn receipt(&mut self, receipt: &Receipt) -> Result<Mana, String> {
+        let (viewer, server) = (receipt.viewer, receipt.server);
+        if viewer == server {
+            return Err("Cannot view own pages".into());
+        }
+        if !self.nodes.contains_key(&server) {
+            return Err("Server is not registered".into());
+        }
+        let nd = self.nodes.get(&viewer).ok_or("Viewer is not registered")?;
+        if !receipt.verify(&nd.info.public_key) {
+            return Err("Invalid signature".into());
+        }
+        if receipt.nonce < nd.receipt_nonce {
+            return Err(format!("Nonce too low, expected {}", nd.receipt_nonce));
+        }
+        let served = self.nodes[&server].served + receipt.bytes;
+        let amount: Mana = served
+            .checked_div(self.config.served_unit)
+            .map_or(0, u128::from)
+            .into();
+        if self.config.viewer_pays && nd.info.mana < amount {
+            return Err("Viewer has not enough mana".into());
+        }
+
+        self.ledger.record(Transition::Receipt {
+            viewer,
+            server,
+            bytes: receipt.bytes,
+            nonce: receipt.nonce,
+        });
+        let nd = self.nodes.get_mut(&viewer).expect("checked above");
+        nd.receipt_nonce = receipt.nonce + 1;
+        if self.config.viewer_pays && amount > Mana::zero() {
+            nd.info.mana -= amount;
+            self.ledger
+                .record(Transition::ManaDebit { id: viewer, amount });
+        }
+        let nd = self.nodes.get_mut(&server).expect("checked above");
+        nd.served = served.checked_rem(self.config.served_unit).unwrap_or(0);
+        if amount > Mana::zero() {
+            nd.info.mana += amount;
+            self.ledger
+                .record(Transition::ManaCredit { id: server, amount });
+        }
+        Ok(nd.info.mana)
+    }
+
+    /// Adds the node to the group, which is created if it has no members yet.
+    fn group_join(&mut self, id: NodeID, group: GroupID) -> Result<(), GroupError> {
+        if !self.nodes.contains_key(&id) {
+            return Err(GroupError::UnknownNode);
+        }
+        if self.groups.entry(group).or_default().insert(id) {
+            self.ledger.record(Transition::GroupJoin { id, group });
+        }
+        Ok(())
+    }
+
+    /// Removes the node from the group. Groups without members are dropped.
+    fn group_leave(&mut self, id: NodeID, group: GroupID) -> Result<(), GroupError> {
+        let Some(members) = self.groups.get_mut(&group) else {
+            return Err(GroupError::NotMember);
+        };
+        if !members.remove(&id) {
+            return Err(GroupError::NotMember);
+        }
+        if members.is_empty() {
+            self.groups.remove(&group);
+        }
+        self.ledger.record(Transition::GroupLeave { id, group });
+        Ok(())
+    }
+
+    // Returns the members of the group which are currently active.
+    fn group_members(&self, group: &GroupID) -> Vec<NodeID> {
+        self.groups
+            .get(group)
+            .map(|members| {
+                members
+                    .iter()
+                    .filter(|id| {
+                        self.nodes
+                            .get(id)
+                            .is_some_and(|nd| nd.is_active(self.last_tick_time))
+                    })
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Registers the name for the group, or renews it if the node already
+    /// owns it. Other nodes can only take the name once it expired and the
+    /// grace period is over.
+    fn name_register(
+        &mut self,
+        name: &str,
+        owner: NodeID,
+        group: GroupID,
+    ) -> Result<NameRecord, NameError> {
+        let name = normalize_name(name)?;
+        let now = self.last_tick_time;
+        let node = self.nodes.get(&owner).ok_or(NameError::UnknownNode)?;
+        let start = match self.names.get(&name) {
+            Some(record) if record.owner == owner => record.expires.max(now),
+            Some(record) if record.expires + self.config.name_grace > now => {
+                return Err(NameError::Taken)
+            }
+            _ => now,
+        };
+        let fee: Mana = self.config.name_fee.into();
+        if node.info.mana < fee {
+            return Err(NameError::NotEnoughMana { fee });
+        }
+
+        if fee > Mana::zero() {
+            let node = self.nodes.get_mut(&owner).expect("checked above");
+            node.info.mana -= fee;
+            self.ledger.record(Transition::ManaDebit {
+                id: owner,
+                amount: fee,
+            });
+        }
+        let record = NameRecord {
+            name: name.clone(),
+            owner,
+            group,
+            expires: start + self.config.name_period,
+        };
+        self.ledger.record(Transition::NameRegister {
+            name: name.clone(),
+            owner,
+            group,
+            expires: record.expires,
+        });
+        self.names.insert(name, record.clone());
+        Ok(record)
+    }
+
+    /// Gives the name to another node, which can renew it from now on.
+    fn name_transfer(
+        &mut self,
+        name: &str,
+        from: NodeID,
+        to: NodeID,
+    ) -> Result<NameRecord, NameError> {
+        let name = normalize_name(name)?;
+        if !self.nodes.contains_key(&to) {
+            return Err(NameError::UnknownReceiver);
+        }
+        let now = self.last_tick_time;
+        let record = self
+            .names
+            .get_mut(&name)
+            .filter(|record| record.expires > now)
+            .ok_or(NameError::UnknownName)?;
+        if record.owner != from {
+            return Err(NameError::NotOwner);
+        }
+        record.owner = to;
+        self.ledger
+            .record(Transition::NameTransfer { name, from, to });
+        Ok(record.clone())
+    }
+
+    // Returns the record of the name, unless it expired.
+    fn resolve(&self, name: &str) -> Result<NameRecord, NameError> {
+        self.names
+            .get(&normalize_name(name)?)
+            .filter(|record| record.expires > self.last_tick_time)
+            .cloned()
+            .ok_or(NameError::UnknownName)
+    }
+
+    /// Stores the vote of a node on the content of a group.
+    /// The vote must be signed by the voter, and its nonce must not be lower
+    /// than the one of the next vote of the voter.
+    fn vote(&mut self, vote: &ReputationVote) -> Result<(), String> {
+        let nd = self
+            .nodes
+            .get_mut(&vote.voter)
+            .ok_or("Voter is not registered")?;
+        if !vote.verify(&nd.info.public_key) {
+            return Err("Invalid signature".into());
+        }
+        if vote.nonce < nd.vote_nonce {
+            return Err(format!("Nonce too low, expected {}", nd.vote_nonce));
+        }
+        nd.vote_nonce = vote.nonce + 1;
+        self.reputation
+            .vote(vote.voter, vote.group, vote.category, vote.flag);
+        self.ledger.record(Transition::Vote {
+            voter: vote.voter,
+            group: vote.group,
+            category: vote.category,
+            flag: vote.flag,
+        });
+        Ok(())
+    }
+
+    // Counts the votes on the group with the current mana of the voters.
+    fn group_reputation(&self, group: GroupID) -> GroupReputation {
+        self.reputation.group(
+            group,
+            |id| self.nodes.get(id).map_or(Mana::zero(), |nd| nd.info.mana),
+            self.config.reputation_min_weight.into(),
+        )
     }
 
     /// Every node should call this from time to time in order to be kept alive.
@@ -165,6 +829,7 @@ impl Trusted {
     fn alive(&mut self, id: &NodeID) -> TrustedReply {
         if let Some(node) = self.nodes.get_mut(id) {
             node.active_until = self.last_tick_time + self.config.time_node_active;
+            self.ledger.record(Transition::Alive { id: *id });
             TrustedReply::Mana(node.info.mana)
         } else {
             TrustedReply::ErrorMsg("Node not registered".into())
@@ -197,21 +862,96 @@ impl TrustedRequest {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TReqMsg {
     /// Registers a new node
     Register(NodeInfo),
     /// Mark node as alive for the next x ticks
     Alive(NodeID),
+    /// Set the resources a node offers and uses, for the `ManaPolicy`
+    Resources(NodeID, Resources),
+    /// Set the bytes of content the node owns in the network, for the rent
+    Stored(NodeID, u64),
+    /// Get the owners whose content can be deleted
+    Deletable,
     /// Update mana - increase for online nodes, decrease for offline nodes
     Tick(u128),
     /// Get NodeInfo of a node
     Info(NodeID),
+    /// Get aggregated statistics over all nodes
+    NetworkStatus,
+    /// Get the block of the ledger at the given height
+    Block(u64),
+    /// Get the height of the ledger and verify the chain
+    LedgerStatus,
+    /// Move mana from one node to another
+    Transfer {
+        from: NodeID,
+        to: NodeID,
+        amount: Mana,
+        nonce: u64,
+        signature: Signature,
+    },
+    /// Reward the server for the bytes confirmed by the viewer
+    Receipt(Receipt),
+    /// Get the nonce the next receipt of a viewer needs at least
+    ReceiptNonce(NodeID),
+    /// Get the nonce and all transfers of a node
+    Transfers(NodeID),
+    /// Add a node to a group
+    GroupJoin(NodeID, GroupID),
+    /// Remove a node from a group
+    GroupLeave(NodeID, GroupID),
+    /// Get the active members of a group
+    GroupMembers(GroupID),
+    /// Register or renew a name pointing to a group
+    NameRegister {
+        name: String,
+        owner: NodeID,
+        group: GroupID,
+    },
+    /// Give a name to another node
+    NameTransfer {
+        name: String,
+        from: NodeID,
+        to: NodeID,
+    },
+    /// Get the record of a name which didn't expire
+    Resolve(String),
+    /// Vote on the content of a group
+    Vote(ReputationVote),
+    /// Get the votes on a group, weighted by the mana of the voters
+    Reputation(GroupID),
+    /// Set what the node wants to happen with pages of flagged groups
+    Preferences(NodeID, ContentPolicy),
+    /// Get the content policy of a node
+    GetPreferences(NodeID),
     /// Close the channel and stop
     Close,
 }
 
 impl TReqMsg {
+    /// Returns true for the messages which change the state of Trusted.
+    /// Only these are written to the journal.
+    pub fn is_update(&self) -> bool {
+        matches!(
+            self,
+            TReqMsg::Register(_)
+                | TReqMsg::Alive(_)
+                | TReqMsg::Resources(..)
+                | TReqMsg::Stored(..)
+                | TReqMsg::Tick(_)
+                | TReqMsg::Transfer { .. }
+                | TReqMsg::Receipt(_)
+                | TReqMsg::GroupJoin(..)
+                | TReqMsg::GroupLeave(..)
+                | TReqMsg::NameRegister { .. }
+                | TReqMsg::NameTransfer { .. }
+                | TReqMsg::Vote(_)
+                | TReqMsg::Preferences(..)
+        )
+    }
+
     pub fn send(&self, trusted: &Sender<TrustedRequest>) -> Result<TrustedReply, Box<dyn Error>> {
         let (tx, rx) = channel();
         trusted.send(TrustedRequest {
@@ -228,17 +968,84 @@ pub enum TrustedReply {
     NodeList(Vec<NodeInfo>),
     NodeInfo(Option<NodeInfo>),
     Mana(Mana),
+    NetworkStatus(NetworkStatus),
+    Block(Option<Block>),
+    LedgerStatus(LedgerStatus),
+    Transfers(Option<TransferHistory>),
+    Nonce(Option<u64>),
+    Deletable(Vec<DeletableContent>),
+    Members(Vec<NodeID>),
+    Group(Result<(), GroupError>),
+    Name(Result<NameRecord, NameError>),
+    Reputation(GroupReputation),
+    Preferences(Option<ContentPolicy>),
     OK,
     ErrorMsg(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct NodeData {
     info: NodeInfo,
     active_until: u128,
+    // The nonce for the next transfer of this node
+    #[serde(default)]
+    nonce: u64,
+    // All transfers from and to this node
+    #[serde(default)]
+    transfers: Vec<TransferRecord>,
+    // What the node offers and uses, as reported by the node
+    #[serde(default)]
+    resources: Resources,
+    // Bytes of content this node owns in the network
+    #[serde(default)]
+    stored: u64,
+    // The nonce the next receipt of this node as a viewer needs at least
+    #[serde(default)]
+    receipt_nonce: u64,
+    // Bytes served by this node which have not been rewarded yet
+    #[serde(default)]
+    served: u64,
+    // The nonce the next vote of this node needs at least
+    #[serde(default)]
+    vote_nonce: u64,
+    // What the node wants to happen with pages of flagged groups, if set
+    #[serde(default)]
+    preferences: Option<ContentPolicy>,
+}
+
+/// Everything needed to restore Trusted, except for the configuration.
+#[derive(Serialize, Deserialize)]
+struct TrustedState {
+    nodes: Vec<NodeData>,
+    last_tick_time: u128,
+    #[serde(default)]
+    ledger: LedgerState,
+    #[serde(default)]
+    deletable: Vec<(NodeID, u128)>,
+    #[serde(default)]
+    groups: Vec<(GroupID, Vec<NodeID>)>,
+    #[serde(default)]
+    names: Vec<NameRecord>,
+    #[serde(default)]
+    reputation: Vec<Vote>,
 }
 
 impl NodeData {
+    fn new(info: NodeInfo, active_until: u128) -> Self {
+        Self {
+            info,
+            active_until,
+            nonce: 0,
+            transfers: vec![],
+            resources: Resources::default(),
+            stored: 0,
+            receipt_nonce: 0,
+            served: 0,
+            vote_nonce: 0,
+            preferences: None,
+        }
+    }
+
     fn is_active(&self, now: u128) -> bool {
         self.active_until >= now
     }
@@ -247,6 +1054,7 @@ impl NodeData {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{api::reputation::Category, simul::node_types::NodeSecret};
 
     type ResErr = Result<(), Box<dyn Error>>;
 
@@ -262,6 +1070,12 @@ mod test {
         let node2 = NodeInfo::random();
         let reply = Trusted::send(&tr, TReqMsg::Register(node2))?;
         assert_matches!(reply, TrustedReply::NodeList(reply) if reply.len() == 2);
+
+        // The ID must be derived from the public key.
+        let mut forged = NodeInfo::random();
+        forged.id = NodeID::random();
+        let reply = Trusted::send(&tr, TReqMsg::Register(forged))?;
+        assert_matches!(reply, TrustedReply::ErrorMsg(_));
         Ok(())
     }
 
@@ -319,4 +1133,524 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_mana_policy() -> ResErr {
+        let cfg = Config {
+            mana_policy: ManaPolicyConfig::Capped {
+                policy: Box::new(ManaPolicyConfig::Contribution {
+                    period: 1_000,
+                    storage_unit: 1_000,
+                    bandwidth_unit: 0,
+                    cpu_unit: 0,
+                }),
+                max: 10.into(),
+            },
+            ..Config::default()
+        };
+        let tr = Trusted::new(cfg, 0);
+        let node = NodeInfo::random();
+        let reply = TReqMsg::Resources(node.id, Resources::default()).send(&tr)?;
+        assert_matches!(reply, TrustedReply::ErrorMsg(_));
+        TReqMsg::Register(node.clone()).send(&tr)?;
+
+        // Without resources there is no mana.
+        TReqMsg::Tick(1_000).send(&tr)?;
+        let reply = TReqMsg::Alive(node.id).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Mana(m) if m == 0.into());
+
+        let resources = Resources {
+            storage: 3_000,
+            ..Default::default()
+        };
+        TReqMsg::Resources(node.id, resources).send(&tr)?;
+        TReqMsg::Tick(2_000).send(&tr)?;
+        let reply = TReqMsg::Alive(node.id).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Mana(m) if m == 3.into());
+        TReqMsg::Tick(5_000).send(&tr)?;
+        let reply = TReqMsg::Alive(node.id).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Mana(m) if m == 10.into());
+
+        Ok(())
+    }
+
+    fn deletable(tr: &Sender<TrustedRequest>) -> Result<Vec<DeletableContent>, Box<dyn Error>> {
+        match TReqMsg::Deletable.send(tr)? {
+            TrustedReply::Deletable(list) => Ok(list),
+            reply => Err(format!("Wrong reply: {reply:?}").into()),
+        }
+    }
+
+    #[test]
+    fn test_storage_rent() -> ResErr {
+        let cfg = Config {
+            mana_policy: ManaPolicyConfig::Combined(vec![
+                ManaPolicyConfig::Time,
+                ManaPolicyConfig::StorageRent {
+                    period: 1_000,
+                    storage_unit: 1_000,
+                },
+            ]),
+            storage_grace: 10_000,
+            ..Config::default()
+        };
+        let tr = Trusted::new(cfg.clone(), 0);
+        let node = NodeInfo::random();
+        TReqMsg::Register(node.clone()).send(&tr)?;
+
+        // The node earns 1 mana per second, but has to pay 3 for its content.
+        TReqMsg::Stored(node.id, 2_500).send(&tr)?;
+        TReqMsg::Tick(1_000).send(&tr)?;
+        let expected = DeletableContent {
+            owner: node.id,
+            since: 1_000,
+            delete_after: 11_000,
+        };
+        assert_eq!(vec![expected], deletable(&tr)?);
+        let reply = TReqMsg::Alive(node.id).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Mana(m) if m == 0.into());
+
+        // With less content it can pay again.
+        TReqMsg::Stored(node.id, 1_000).send(&tr)?;
+        TReqMsg::Tick(2_000).send(&tr)?;
+        assert!(deletable(&tr)?.is_empty());
+        let reply = TReqMsg::Block(1).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Block(Some(b))
+            if b.transitions.contains(&Transition::Solvent { id: node.id }));
+
+        // Content without a registered owner, or whose owner is removed, can
+        // be deleted.
+        let unknown = NodeID::random();
+        TReqMsg::Stored(unknown, 1).send(&tr)?;
+        TReqMsg::Tick(2_000 + cfg.time_node_active + cfg.time_mana_decrease).send(&tr)?;
+        let owners: Vec<NodeID> = deletable(&tr)?.iter().map(|d| d.owner).collect();
+        assert_eq!(2, owners.len());
+        assert!(owners.contains(&unknown) && owners.contains(&node.id));
+
+        // Once the content is gone, the owner is taken off the list.
+        TReqMsg::Stored(unknown, 0).send(&tr)?;
+        assert_eq!(1, deletable(&tr)?.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_status() -> ResErr {
+        let cfg = Config::default();
+        let tr = Trusted::new(cfg.clone(), 0);
+        let node1 = NodeInfo::random();
+        let node2 = NodeInfo::random();
+        TReqMsg::Register(node1.clone()).send(&tr)?;
+        TReqMsg::Register(node2.clone()).send(&tr)?;
+
+        let now = cfg.time_node_active;
+        TReqMsg::Tick(now).send(&tr)?;
+        TReqMsg::Alive(node1.id).send(&tr)?;
+        TReqMsg::Tick(now + cfg.time_mana_increase).send(&tr)?;
+
+        let reply = TReqMsg::NetworkStatus.send(&tr)?;
+        let TrustedReply::NetworkStatus(status) = reply else {
+            panic!("Wrong reply: {reply:?}");
+        };
+        assert_eq!(2, status.users_total);
+        assert_eq!(1, status.users_active);
+        let mana = 2 * now / cfg.time_mana_increase + 1;
+        assert_eq!(Mana::from(mana), status.mana_total);
+        assert_matches!(status.nodes.iter().find(|n| n.id == node1.id), Some(n) if n.active);
+        assert_matches!(status.nodes.iter().find(|n| n.id == node2.id), Some(n) if !n.active);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ledger() -> ResErr {
+        let cfg = Config::default();
+        let tr = Trusted::new(cfg.clone(), 0);
+        let node = NodeInfo::random();
+        TReqMsg::Register(node.clone()).send(&tr)?;
+        TReqMsg::Tick(cfg.time_mana_increase).send(&tr)?;
+        TReqMsg::Alive(node.id).send(&tr)?;
+        TReqMsg::Tick(2 * cfg.time_mana_increase).send(&tr)?;
+
+        let reply = TReqMsg::Block(0).send(&tr)?;
+        let TrustedReply::Block(Some(block0)) = reply else {
+            panic!("Wrong reply: {reply:?}");
+        };
+        assert_eq!(
+            vec![
+                Transition::Register {
+                    id: node.id,
+                    name: node.name.clone()
+                },
+                Transition::ManaCredit {
+                    id: node.id,
+                    amount: 1.into()
+                }
+            ],
+            block0.transitions
+        );
+        let reply = TReqMsg::Block(1).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Block(Some(b)) if b.parent == block0.hash
+            && b.transitions[0] == Transition::Alive { id: node.id });
+        assert_matches!(TReqMsg::Block(2).send(&tr)?, TrustedReply::Block(None));
+
+        let reply = TReqMsg::LedgerStatus.send(&tr)?;
+        assert_matches!(reply, TrustedReply::LedgerStatus(ls) if ls.height == 2 && ls.error.is_none());
+
+        Ok(())
+    }
+
+    fn transfer(
+        tr: &Sender<TrustedRequest>,
+        req: TransferRequest,
+    ) -> Result<TrustedReply, Box<dyn Error>> {
+        TReqMsg::Transfer {
+            from: req.from,
+            to: req.to,
+            amount: req.amount,
+            nonce: req.nonce,
+            signature: req.signature,
+        }
+        .send(tr)
+    }
+
+    #[test]
+    fn test_transfer() -> ResErr {
+        let cfg = Config::default();
+        let tr = Trusted::new(cfg.clone(), 0);
+        let secret1 = NodeSecret::random();
+        let secret2 = NodeSecret::random();
+        let node1 = NodeInfo::from_secret(&secret1);
+        let node2 = NodeInfo::from_secret(&secret2);
+        TReqMsg::Register(node1.clone()).send(&tr)?;
+        TReqMsg::Register(node2.clone()).send(&tr)?;
+        TReqMsg::Tick(10 * cfg.time_mana_increase).send(&tr)?;
+
+        let req = TransferRequest::new(&secret1, node2.id, 4.into(), 0);
+        assert_matches!(transfer(&tr, req.clone())?, TrustedReply::Mana(m) if m == 6.into());
+
+        // Replaying the same transfer fails.
+        assert_matches!(transfer(&tr, req.clone())?, TrustedReply::ErrorMsg(_));
+        // Also after registering again, which keeps the mana.
+        TReqMsg::Register(node1.clone()).send(&tr)?;
+        assert_matches!(transfer(&tr, req)?, TrustedReply::ErrorMsg(_));
+        let reply = TReqMsg::Info(node1.id).send(&tr)?;
+        assert_matches!(reply, TrustedReply::NodeInfo(Some(ni)) if ni.mana == 6.into());
+
+        // Signed by the wrong node.
+        let mut req = TransferRequest::new(&secret2, node2.id, 1.into(), 1);
+        req.from = node1.id;
+        assert_matches!(transfer(&tr, req)?, TrustedReply::ErrorMsg(e) if e == "Invalid signature");
+
+        // Not enough mana.
+        let req = TransferRequest::new(&secret1, node2.id, 7.into(), 1);
+        assert_matches!(transfer(&tr, req)?, TrustedReply::ErrorMsg(e) if e == "Not enough mana");
+
+        // Both nodes have the transfer in their history.
+        let reply = TReqMsg::Transfers(node1.id).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Transfers(Some(h)) if h.nonce == 1
+            && h.transfers.len() == 1);
+        let reply = TReqMsg::Transfers(node2.id).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Transfers(Some(h)) if h.nonce == 0
+            && h.transfers[0].amount == 4.into());
+        let reply = TReqMsg::Info(node2.id).send(&tr)?;
+        assert_matches!(reply, TrustedReply::NodeInfo(Some(ni)) if ni.mana == 14.into());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_receipt() -> ResErr {
+        let cfg = Config {
+            served_unit: 100,
+            ..Config::default()
+        };
+        let tr = Trusted::new(cfg.clone(), 0);
+        let viewer = NodeSecret::random();
+        let server = NodeInfo::random();
+        TReqMsg::Register(NodeInfo::from_secret(&viewer)).send(&tr)?;
+        TReqMsg::Register(server.clone()).send(&tr)?;
+        let receipt =
+            |bytes, nonce| TReqMsg::Receipt(Receipt::new(&viewer, server.id, bytes, nonce));
+
+        // The served bytes add up until they fill a unit.
+        assert_matches!(receipt(150, 0).send(&tr)?, TrustedReply::Mana(m) if m == 1.into());
+        assert_matches!(receipt(40, 1).send(&tr)?, TrustedReply::Mana(m) if m == 1.into());
+        assert_matches!(receipt(10, 5).send(&tr)?, TrustedReply::Mana(m) if m == 2.into());
+
+        // Receipts cannot be submitted twice, nor forged.
+        assert_matches!(receipt(100, 5).send(&tr)?, TrustedReply::ErrorMsg(_));
+        let mut forged = Receipt::new(&viewer, server.id, 100, 6);
+        forged.bytes = 1_000;
+        let reply = TReqMsg::Receipt(forged).send(&tr)?;
+        assert_matches!(reply, TrustedReply::ErrorMsg(e) if e == "Invalid signature");
+
+        // If the viewer pays, it needs enough mana.
+        let tr = Trusted::new(
+            Config {
+                viewer_pays: true,
+                ..cfg.clone()
+            },
+            0,
+        );
+        TReqMsg::Register(NodeInfo::from_secret(&viewer)).send(&tr)?;
+        TReqMsg::Register(server.clone()).send(&tr)?;
+        let reply = receipt(100, 0).send(&tr)?;
+        assert_matches!(reply, TrustedReply::ErrorMsg(e) if e == "Viewer has not enough mana");
+        TReqMsg::Tick(3 * cfg.time_mana_increase).send(&tr)?;
+        assert_matches!(receipt(250, 0).send(&tr)?, TrustedReply::Mana(m) if m == 5.into());
+        let balances = balances(&tr)?;
+        assert_eq!(Mana::from(1), balances[&NodeID::from(viewer)]);
+
+        Ok(())
+    }
+
+    fn members(tr: &Sender<TrustedRequest>, group: GroupID) -> Result<Vec<NodeID>, Box<dyn Error>> {
+        match TReqMsg::GroupMembers(group).send(tr)? {
+            TrustedReply::Members(members) => Ok(members),
+            reply => Err(format!("Wrong reply: {reply:?}").into()),
+        }
+    }
+
+    #[test]
+    fn test_groups() -> ResErr {
+        let cfg = Config::default();
+        let tr = Trusted::new(cfg.clone(), 0);
+        let (group, other) = (GroupID::random(), GroupID::random());
+        let nodes: Vec<NodeInfo> = (0..3).map(|_| NodeInfo::random()).collect();
+
+        // Only registered nodes can join.
+        let reply = TReqMsg::GroupJoin(nodes[0].id, group).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Group(Err(GroupError::UnknownNode)));
+        for node in &nodes {
+            TReqMsg::Register(node.clone()).send(&tr)?;
+            TReqMsg::GroupJoin(node.id, group).send(&tr)?;
+        }
+        TReqMsg::GroupJoin(nodes[0].id, other).send(&tr)?;
+        let mut ids: Vec<NodeID> = nodes.iter().map(|n| n.id).collect();
+        ids.sort();
+        assert_eq!(ids, members(&tr, group)?);
+        assert_eq!(vec![nodes[0].id], members(&tr, other)?);
+
+        // Leaving twice fails.
+        let reply = TReqMsg::GroupLeave(nodes[1].id, group).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Group(Ok(())));
+        let reply = TReqMsg::GroupLeave(nodes[1].id, group).send(&tr)?;
+        assert_matches!(reply, TrustedReply::Group(Err(GroupError::NotMember)));
+        ids.retain(|id| id != &nodes[1].id);
+        assert_eq!(ids, members(&tr, group)?);
+
+        // Inactive nodes are not returned, but stay members.
+        let mut now = cfg.time_node_active;
+        TReqMsg::Tick(now).send(&tr)?;
+        TReqMsg::Alive(nodes[0].id).send(&tr)?;
+        now += cfg.time_node_active / 2;
+        TReqMsg::Tick(now).send(&tr)?;
+        assert_eq!(vec![nodes[0].id], members(&tr, group)?);
+        TReqMsg::Alive(nodes[2].id).send(&tr)?;
+        assert_eq!(ids, members(&tr, group)?);
+        Ok(())
+    }
+
+    fn name(
+        tr: &Sender<TrustedRequest>,
+        msg: TReqMsg,
+    ) -> Result<Result<NameRecord, NameError>, Box<dyn Error>> {
+        match msg.send(tr)? {
+            TrustedReply::Name(name) => Ok(name),
+            reply => Err(format!("Wrong reply: {reply:?}").into()),
+        }
+    }
+
+    #[test]
+    fn test_names() -> ResErr {
+        let cfg = Config {
+            name_fee: 2,
+            name_period: 10 * TIME_SECOND,
+            name_grace: 5 * TIME_SECOND,
+            ..Config::default()
+        };
+        let tr = Trusted::new(cfg.clone(), 0);
+        let (alice, bob) = (NodeInfo::random(), NodeInfo::random());
+        let group = GroupID::random();
+        let register = |owner: NodeID| TReqMsg::NameRegister {
+            name: "Ineiti".into(),
+            owner,
+            group,
+        };
+        let resolve = || TReqMsg::Resolve("ineiti".into());
+
+        TReqMsg::Register(alice.clone()).send(&tr)?;
+        TReqMsg::Register(bob.clone()).send(&tr)?;
+        let fee = NameError::NotEnoughMana { fee: 2.into() };
+        assert_eq!(Err(fee), name(&tr, register(alice.id))?);
+        TReqMsg::Tick(3 * TIME_SECOND).send(&tr)?;
+        let record = name(&tr, register(alice.id))??;
+        assert_eq!(
+            ("ineiti", 13 * TIME_SECOND),
+            (record.name.as_str(), record.expires)
+        );
+        assert_eq!(Ok(record.clone()), name(&tr, resolve())?);
+        assert_matches!(
+            TReqMsg::Info(alice.id).send(&tr)?,
+            TrustedReply::NodeInfo(Some(ni)) if ni.mana == 1.into()
+        );
+
+        // The name is taken until the grace period is over, but the owner
+        // can renew it and give it away.
+        assert_eq!(Err(NameError::Taken), name(&tr, register(bob.id))?);
+        let transfer = |from: NodeID, to: NodeID| TReqMsg::NameTransfer {
+            name: "ineiti".into(),
+            from,
+            to,
+        };
+        assert_eq!(
+            Err(NameError::NotOwner),
+            name(&tr, transfer(bob.id, alice.id))?
+        );
+        assert_eq!(bob.id, name(&tr, transfer(alice.id, bob.id))??.owner);
+        TReqMsg::Tick(14 * TIME_SECOND).send(&tr)?;
+        assert_eq!(Err(NameError::UnknownName), name(&tr, resolve())?);
+        assert_eq!(Err(NameError::Taken), name(&tr, register(alice.id))?);
+        let record = name(&tr, register(bob.id))??;
+        assert_eq!(24 * TIME_SECOND, record.expires);
+
+        // Once the grace period is over, anybody can register it.
+        TReqMsg::Tick(30 * TIME_SECOND).send(&tr)?;
+        TReqMsg::Alive(alice.id).send(&tr)?;
+        assert_eq!(alice.id, name(&tr, register(alice.id))??.owner);
+        let reply = name(&tr, TReqMsg::Resolve("no.name".into()))?;
+        assert_eq!(Err(NameError::InvalidName), reply);
+        Ok(())
+    }
+
+    #[test]
+    fn test_votes() -> ResErr {
+        let cfg = Config {
+            reputation_min_weight: 5,
+            ..Config::default()
+        };
+        let tr = Trusted::new(cfg, 0);
+        let voters: Vec<NodeSecret> = (0..2).map(|_| NodeSecret::random()).collect();
+        for voter in &voters {
+            TReqMsg::Register(NodeInfo::from_secret(voter)).send(&tr)?;
+        }
+        let group = GroupID::random();
+        let vote = |voter: &NodeSecret, flag, nonce| {
+            TReqMsg::Vote(ReputationVote::new(
+                voter,
+                group,
+                Category::Scam,
+                flag,
+                nonce,
+            ))
+        };
+
+        // Votes of nodes without mana don't count.
+        assert_matches!(
+            vote(&voters[0], true, 0).send(&tr)?,
+            TrustedReply::Reputation(rep) if rep.flagged().is_empty()
+        );
+        TReqMsg::Tick(3 * TIME_SECOND).send(&tr)?;
+        assert_matches!(
+            TReqMsg::Reputation(group).send(&tr)?,
+            TrustedReply::Reputation(rep) if rep.flagged().is_empty()
+        );
+        TReqMsg::Tick(6 * TIME_SECOND).send(&tr)?;
+        assert_matches!(
+            TReqMsg::Reputation(group).send(&tr)?,
+            TrustedReply::Reputation(rep) if rep.flagged() == vec![Category::Scam]
+        );
+        assert_matches!(
+            vote(&voters[1], false, 0).send(&tr)?,
+            TrustedReply::Reputation(rep) if rep.flagged().is_empty()
+        );
+
+        // Votes cannot be replayed, nor forged.
+        assert_matches!(
+            vote(&voters[0], false, 0).send(&tr)?,
+            TrustedReply::ErrorMsg(_)
+        );
+        let mut forged = ReputationVote::new(&voters[0], group, Category::Scam, true, 1);
+        forged.voter = NodeID::from(voters[1]);
+        let reply = TReqMsg::Vote(forged).send(&tr)?;
+        assert_matches!(reply, TrustedReply::ErrorMsg(e) if e == "Invalid signature");
+
+        // The content policy is kept for every node.
+        let id = NodeID::from(voters[0]);
+        assert_matches!(
+            TReqMsg::GetPreferences(id).send(&tr)?,
+            TrustedReply::Preferences(Some(p)) if p == ContentPolicy::default()
+        );
+        let policy = ContentPolicy {
+            refuse: vec![Category::Scam],
+            warn: vec![],
+        };
+        TReqMsg::Preferences(id, policy.clone()).send(&tr)?;
+        assert_matches!(
+            TReqMsg::GetPreferences(id).send(&tr)?,
+            TrustedReply::Preferences(Some(p)) if p == policy
+        );
+        Ok(())
+    }
+
+    fn ledger_head(tr: &Sender<TrustedRequest>) -> Result<Option<Hash256>, Box<dyn Error>> {
+        match TReqMsg::LedgerStatus.send(tr)? {
+            TrustedReply::LedgerStatus(ls) => Ok(ls.head),
+            reply => Err(format!("Wrong reply: {reply:?}").into()),
+        }
+    }
+
+    fn balances(tr: &Sender<TrustedRequest>) -> Result<HashMap<NodeID, Mana>, Box<dyn Error>> {
+        match TReqMsg::NetworkStatus.send(tr)? {
+            TrustedReply::NetworkStatus(ns) => {
+                Ok(ns.nodes.iter().map(|n| (n.id, n.mana)).collect())
+            }
+            reply => Err(format!("Wrong reply: {reply:?}").into()),
+        }
+    }
+
+    #[test]
+    fn test_restore() -> ResErr {
+        let dir = tempfile::tempdir()?;
+        let cfg = Config {
+            data_dir: Some(dir.path().to_path_buf()),
+            snapshot_entries: 7,
+            ..Config::default()
+        };
+        let tr = Trusted::new(cfg.clone(), 0);
+        let nodes: Vec<NodeInfo> = (0..3).map(|_| NodeInfo::random()).collect();
+        for node in &nodes {
+            TReqMsg::Register(node.clone()).send(&tr)?;
+        }
+        let mut now = 0;
+        for _ in 0..10 {
+            now += cfg.time_node_active / 2;
+            TReqMsg::Tick(now).send(&tr)?;
+            TReqMsg::Alive(nodes[0].id).send(&tr)?;
+        }
+        let before = balances(&tr)?;
+        assert_eq!(3, before.len());
+        let head = ledger_head(&tr)?;
+        let _ = TReqMsg::Close.send(&tr);
+
+        // Simulate a crash while writing the next journal entry.
+        let mut journal = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("journal.jsonl"))?;
+        std::io::Write::write_all(&mut journal, b"{\"seq\":42,\"entry\":{\"Tick\"")?;
+
+        // The restored Trusted has the same balances and continues
+        // from the same state.
+        let tr = Trusted::new(cfg.clone(), 0);
+        assert_eq!(before, balances(&tr)?);
+        assert_eq!(head, ledger_head(&tr)?);
+        now += cfg.time_node_active / 2;
+        TReqMsg::Tick(now).send(&tr)?;
+        let after = balances(&tr)?;
+        let _ = TReqMsg::Close.send(&tr);
+        assert_eq!(after, balances(&Trusted::new(cfg, 0))?);
+
+        Ok(())
+    }
 }
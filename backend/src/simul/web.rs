@@ -1,10 +1,13 @@
-use std::sync::mpsc::Sender;
+use std::{collections::HashMap, sync::mpsc::Sender};
 
-use tracing::{debug, error, trace};
+use tracing::{debug, error, trace, warn};
 
 use crate::simul::{
     broker::BMNet,
-    node::{Node, NodeInfo},
+    chunk::{Chunk, FileEntry},
+    node::{Msg, Node, NodeClass, NodeInfo, NodeMsg, Receipt, ReceiptRequest},
+    node_types::{Hash256, NodeID, NodeSecret},
+    page::Page,
     trusted::TReqMsg,
 };
 
@@ -13,28 +16,99 @@ use super::{
     trusted::{TrustedReply, TrustedRequest},
 };
 
+/// How long to wait for a node to send a requested page, in milliseconds.
+/// Afterwards, the next node holding the page is asked.
+const PAGE_TIMEOUT: u128 = 10_000;
+
 pub struct Web {
     trusted: Sender<TrustedRequest>,
+    // The node which fetches pages from the network for the web-frontend
+    gateway: NodeSecret,
+    // Page requests waiting for the reply of a node
+    pending: Vec<PendingPage>,
+    // Latest tick time
+    last_tick: u128,
+    // The nonce of the next receipt of every viewer, for the receipts which
+    // have been signed but didn't reach Trusted yet.
+    receipt_nonces: HashMap<NodeID, u64>,
+}
+
+/// Who signs the receipts for the bytes of a page served to a viewer.
+#[derive(Debug, Clone, Copy)]
+pub enum Viewer {
+    /// The simulation holds the secret of the viewer, so the gateway signs
+    /// the receipts and sends them to the holders.
+    Secret(NodeSecret),
+    /// The viewer signs the `ReceiptRequest`s returned with the page itself.
+    Signed(NodeID),
+}
+
+/// A page fetched for the web, with the receipts a `Viewer::Signed` still
+/// has to sign for the holders which served it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServedPage {
+    pub page: Page,
+    pub receipts: Vec<ReceiptRequest>,
+}
+
+struct PendingPage {
+    domain: String,
+    path: String,
+    reply: Sender<Option<ServedPage>>,
+    // The viewer paying for the served bytes, if known
+    viewer: Option<Viewer>,
+    since: u128,
+    // The node which has been asked for the page
+    holder: NodeID,
+    // The nodes to ask if the holder fails to answer
+    fallbacks: Vec<NodeID>,
+    // The entry sent by the holder, once it replied
+    file: Option<FileEntry>,
+    // The chunks received so far, each verified against its hash
+    chunks: HashMap<Hash256, Chunk>,
+    // The bytes served by every holder, which a `Viewer::Signed` pays
+    served: HashMap<NodeID, u64>,
 }
 
 impl Web {
-    pub fn new(trusted: Sender<TrustedRequest>) -> Self {
-        Self { trusted }
+    pub fn new(trusted: Sender<TrustedRequest>, gateway: NodeSecret, now: u128) -> Self {
+        Self {
+            trusted,
+            gateway,
+            pending: vec![],
+            last_tick: now,
+            receipt_nonces: HashMap::new(),
+        }
+    }
+
+    /// Returns the gateway node, which needs to be added to the network.
+    /// A gateway restored from Trusted keeps its name.
+    pub fn gateway(&self) -> Node {
+        let info = match TReqMsg::Info(self.gateway_id()).send(&self.trusted) {
+            Ok(TrustedReply::NodeInfo(Some(info))) => info,
+            _ => NodeInfo::from_secret(&self.gateway),
+        };
+        Node::from_info(info, Some(self.gateway), &self.trusted).with_class(NodeClass::Root)
+    }
+
+    pub fn gateway_id(&self) -> NodeID {
+        self.gateway.into()
     }
 
     pub fn action(&mut self, action: BMWeb) -> Vec<BrokerMsg> {
         match action {
-            BMWeb::WebRegister(secret) => {
-                let id = secret.into();
+            BMWeb::WebRegister(public_key, secret) => {
+                let id = public_key.into();
                 match TReqMsg::Info(id).send(&self.trusted) {
                     Ok(reply) => {
                         if let TrustedReply::NodeInfo(info_op) = reply {
                             let info = info_op.unwrap_or_else(|| {
                                 debug!("Creating new node with id {id}");
-                                NodeInfo::with_id(id)
+                                NodeInfo::from_public_key(public_key)
                             });
                             return vec![BrokerMsg::Network(BMNet::NodeAdd(Node::from_info(
                                 info,
+                                secret,
                                 &self.trusted,
                             )))];
                         }
@@ -44,12 +118,273 @@ impl Web {
                     }
                 }
             }
+            BMWeb::PageRequest {
+                domain,
+                path,
+                holders,
+                viewer,
+                reply,
+            } => {
+                let mut fallbacks = holders.into_iter();
+                let Some(holder) = fallbacks.next() else {
+                    debug!("No node holds domain {domain}");
+                    if reply.send(None).is_err() {
+                        warn!("Requester of {domain}/{path} went away");
+                    }
+                    return vec![];
+                };
+                let pending = PendingPage {
+                    domain,
+                    path,
+                    reply,
+                    viewer,
+                    since: self.last_tick,
+                    holder,
+                    fallbacks: fallbacks.collect(),
+                    file: None,
+                    chunks: HashMap::new(),
+                    served: HashMap::new(),
+                };
+                let msg = pending.request(&self.gateway);
+                self.pending.push(pending);
+                return vec![BMNet::NodeMsg(msg).into()];
+            }
+            BMWeb::GatewayMsg(msg) => match msg.msg {
+                Msg::PageReply { domain, path, file } => {
+                    return self.page_reply(msg.from, &domain, &path, file)
+                }
+                Msg::ChunkReply { chunks } => return self.chunk_reply(msg.from, chunks),
+                _ => debug!("Gateway ignores {msg:?}"),
+            },
+            BMWeb::GatewayUndelivered(msg) => return self.undelivered(msg),
         }
         vec![]
     }
 
+    // Asks the next holder for the requests waiting on this holder if it
+    // doesn't have the page, or asks the holder for the chunks of the page.
+    fn page_reply(
+        &mut self,
+        holder: NodeID,
+        domain: &str,
+        path: &str,
+        file: Option<FileEntry>,
+    ) -> Vec<BrokerMsg> {
+        let Some(file) = file else {
+            let (gateway, now) = (self.gateway, self.last_tick);
+            let mut msgs = vec![];
+            self.pending.retain_mut(|p| {
+                if p.holder != holder || p.domain != domain || p.path != path || p.file.is_some() {
+                    return true;
+                }
+                debug!("{holder} doesn't have {domain}/{path}");
+                p.next_holder(&gateway, now, &mut msgs)
+            });
+            return msgs;
+        };
+        let mut ids = vec![];
+        for p in self
+            .pending
+            .iter_mut()
+            .filter(|p| p.domain == domain && p.path == path && p.file.is_none())
+        {
+            ids.extend(file.chunks.iter().copied());
+            p.file = Some(file.clone());
+            p.holder = holder;
+        }
+        ids.sort();
+        ids.dedup();
+        self.complete();
+        if ids.is_empty() {
+            return vec![];
+        }
+        let msg = NodeMsg::new(&self.gateway, holder, Msg::ChunkRequest { ids });
+        vec![BMNet::NodeMsg(msg).into()]
+    }
+
+    // Keeps the chunks which have been asked for, and drops all others.
+    // The holder gets a receipt for the bytes of the kept chunks from every
+    // viewer who asked for them. Chunks shared by several requests are only
+    // paid by the first viewer, and anonymous views are not paid.
+    // Viewers whose secret is in the simulation pay right away, the others
+    // get the bytes to sign with the page.
+    fn chunk_reply(&mut self, holder: NodeID, chunks: Vec<Chunk>) -> Vec<BrokerMsg> {
+        let mut viewers: HashMap<NodeID, (NodeSecret, u64)> = HashMap::new();
+        for chunk in chunks {
+            let id = chunk.id();
+            let bytes = chunk.data.len() as u64;
+            let mut paid = false;
+            let mut wanted = false;
+            for p in &mut self.pending {
+                if !p.file.as_ref().is_some_and(|f| f.chunks.contains(&id)) {
+                    continue;
+                }
+                p.chunks.insert(id, chunk.clone());
+                wanted = true;
+                match p.viewer {
+                    Some(Viewer::Secret(secret)) if !paid => {
+                        viewers.entry(secret.into()).or_insert((secret, 0)).1 += bytes;
+                        paid = true;
+                    }
+                    Some(Viewer::Signed(_)) if !paid => {
+                        *p.served.entry(holder).or_default() += bytes;
+                        paid = true;
+                    }
+                    _ => {}
+                }
+            }
+            if !wanted {
+                warn!("Dropping chunk {id} which has not been requested");
+            }
+        }
+        self.complete();
+        let mut msgs = vec![];
+        for (id, (secret, bytes)) in viewers {
+            let nonce = self.receipt_nonce(id);
+            let receipt = Receipt::new(&secret, holder, bytes, nonce);
+            let msg = NodeMsg::new(&self.gateway, holder, Msg::Receipt(Box::new(receipt)));
+            msgs.push(BMNet::NodeMsg(msg).into());
+        }
+        msgs
+    }
+
+    // Returns the nonce for the next receipt of the viewer. Trusted knows the
+    // nonces of the submitted receipts, also from before a restart.
+    fn receipt_nonce(&mut self, viewer: NodeID) -> u64 {
+        let submitted = match TReqMsg::ReceiptNonce(viewer).send(&self.trusted) {
+            Ok(TrustedReply::Nonce(nonce)) => nonce.unwrap_or(0),
+            reply => {
+                error!("While getting the receipt nonce of {viewer}: {reply:?}");
+                0
+            }
+        };
+        let nonce = self
+            .receipt_nonces
+            .get(&viewer)
+            .map_or(submitted, |&n| n.max(submitted));
+        self.receipt_nonces.insert(viewer, nonce + 1);
+        nonce
+    }
+
+    // Asks the next holder for the requests which wait for the reply to the
+    // message, or fails them if there is none.
+    fn undelivered(&mut self, msg: NodeMsg) -> Vec<BrokerMsg> {
+        let (gateway, now) = (self.gateway, self.last_tick);
+        let mut msgs = vec![];
+        self.pending.retain_mut(|p| {
+            let waiting = p.holder == msg.to
+                && match (&msg.msg, &p.file) {
+                    (Msg::PageRequest { domain, path }, None) => {
+                        &p.domain == domain && &p.path == path
+                    }
+                    (Msg::ChunkRequest { ids }, Some(file)) => {
+                        file.chunks.iter().any(|id| ids.contains(id))
+                    }
+                    _ => false,
+                };
+            if !waiting {
+                return true;
+            }
+            debug!("Couldn't fetch {}/{} from {}", p.domain, p.path, p.holder);
+            p.next_holder(&gateway, now, &mut msgs)
+        });
+        msgs
+    }
+
+    // Sends the pages which have all their chunks, together with the
+    // receipts a `Viewer::Signed` has to sign.
+    fn complete(&mut self) {
+        let mut i = 0;
+        while i < self.pending.len() {
+            let Some(page) = self.pending[i].page() else {
+                i += 1;
+                continue;
+            };
+            let p = self.pending.remove(i);
+            let receipts = match p.viewer {
+                Some(Viewer::Signed(id)) => p
+                    .served
+                    .into_iter()
+                    .filter(|(server, _)| *server != id)
+                    .map(|(server, bytes)| ReceiptRequest {
+                        server,
+                        bytes,
+                        nonce: self.receipt_nonce(id),
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            let _ = p.reply.send(Some(ServedPage { page, receipts }));
+        }
+    }
+
     pub fn tick(&mut self, time: u128) -> Vec<BrokerMsg> {
         trace!("Tick @ {time}");
-        vec![]
+        self.last_tick = time;
+        // The gateway stays active like the root nodes.
+        if let Err(e) = TReqMsg::Alive(self.gateway_id()).send(&self.trusted) {
+            error!("While keeping the gateway alive: {e:?}");
+        }
+        let gateway = self.gateway;
+        let mut msgs = vec![];
+        self.pending.retain_mut(|p| {
+            if p.since + PAGE_TIMEOUT > time {
+                return true;
+            }
+            debug!(
+                "Timeout while fetching {}/{} from {}",
+                p.domain, p.path, p.holder
+            );
+            p.next_holder(&gateway, time, &mut msgs)
+        });
+        msgs
+    }
+}
+
+impl PendingPage {
+    // Returns the page once all its chunks arrived.
+    fn page(&self) -> Option<Page> {
+        let file = self.file.as_ref()?;
+        let data = file.assemble(|id| self.chunks.get(id))?;
+        Some(Page {
+            content_type: file.content_type.clone(),
+            data,
+        })
+    }
+
+    // Asks the holder for the page, or for the missing chunks once it is
+    // known which ones the page has.
+    fn request(&self, gateway: &NodeSecret) -> NodeMsg {
+        let msg = match &self.file {
+            None => Msg::PageRequest {
+                domain: self.domain.clone(),
+                path: self.path.clone(),
+            },
+            Some(file) => {
+                let mut ids: Vec<Hash256> = file
+                    .chunks
+                    .iter()
+                    .filter(|id| !self.chunks.contains_key(id))
+                    .copied()
+                    .collect();
+                ids.sort();
+                ids.dedup();
+                Msg::ChunkRequest { ids }
+            }
+        };
+        NodeMsg::new(gateway, self.holder, msg)
+    }
+
+    // Asks the next holder after the current one failed, and returns true.
+    // If there is none, the request fails, and false is returned.
+    fn next_holder(&mut self, gateway: &NodeSecret, now: u128, msgs: &mut Vec<BrokerMsg>) -> bool {
+        if self.fallbacks.is_empty() {
+            let _ = self.reply.send(None);
+            return false;
+        }
+        self.holder = self.fallbacks.remove(0);
+        self.since = now;
+        msgs.push(BMNet::NodeMsg(self.request(gateway)).into());
+        true
     }
 }
@@ -0,0 +1,11 @@
+use std::{error::Error, fs};
+use tempfile::TempDir;
+
+/// Returns a temporary pages directory with the 'index.html' of the
+/// 'ineiti' domain. It is removed when dropped, even if the test fails.
+pub fn pages_dir() -> Result<TempDir, Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir_all(dir.path().join("ineiti"))?;
+    fs::write(dir.path().join("ineiti/index.html"), "<h1>Hi</h1>")?;
+    Ok(dir)
+}
@@ -0,0 +1,40 @@
+use std::error::Error;
+use test_log::test;
+
+use backend::simul::{broker::Broker, kademlia::DhtGet, node_types::Hash256, simulator, trusted};
+
+#[test]
+fn test_dht_churn() -> Result<(), Box<dyn Error>> {
+    let sim = simulator::Config {
+        nodes_root: 0,
+        nodes_flex: 10,
+        p_sign_in: 0x8000,
+        p_sign_out: 0x2000,
+        ..simulator::Config::default()
+    };
+    let mut broker = Broker::new(trusted::Config::default(), sim, 0)?;
+    for now in 1..=5 {
+        broker.tick(now * 1_000);
+    }
+    let key = Hash256::digest(b"ineiti");
+    broker.dht_put(key, b"group".to_vec(), 20_000);
+
+    // The flex nodes come back without the values they held, but the nodes
+    // which stayed online store them again.
+    for now in 6..=20 {
+        broker.tick(now * 1_000);
+        broker.dht_get(key);
+        match broker.dht_result(&key) {
+            DhtGet::Found(record) => assert_eq!(b"group".to_vec(), record.value),
+            result => panic!("got {result:?} at {now}"),
+        }
+    }
+
+    // Once the TTL passed, the value is gone.
+    for now in 21..=25 {
+        broker.tick(now * 1_000);
+    }
+    broker.dht_get(key);
+    assert_eq!(DhtGet::NotFound, broker.dht_result(&key));
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use std::{error::Error, sync::mpsc::channel};
+use test_log::test;
+
+mod common;
+
+use backend::{
+    api::faults::{Fault, FaultRequest, MsgFilter},
+    simul::{broker::Broker, node_types::NodeSecret, simulator, trusted},
+};
+
+#[test]
+fn test_partition() -> Result<(), Box<dyn Error>> {
+    let dir = common::pages_dir()?;
+    let sim = simulator::Config {
+        pages_dir: Some(dir.path().to_path_buf()),
+        nodes_flex: 0,
+        ..simulator::Config::default()
+    };
+    let cfg = trusted::Config {
+        served_unit: 1,
+        ..trusted::Config::default()
+    };
+    let mut broker = Broker::new(cfg, sim, 0)?;
+    for now in 1..=3 {
+        broker.tick(now * 1_000);
+    }
+
+    // The gateway is cut off from the holders until the partition heals,
+    // so every holder times out in turn.
+    let holders = broker.holders("ineiti");
+    let timeouts = holders.len() as u128;
+    broker.add_fault(FaultRequest {
+        fault: Fault::Partition {
+            partitions: vec![holders],
+        },
+        heal_at: Some(100_000),
+    })?;
+    let (tx, rx) = channel();
+    broker.request_page(None, "ineiti", "index.html", tx.clone());
+    for timeout in 1..timeouts {
+        broker.tick(3_000 + timeout * 10_000);
+        assert!(rx.try_recv().is_err());
+    }
+    broker.tick(3_000 + timeouts * 10_000);
+    assert_eq!(None, rx.try_recv()?);
+    assert_eq!(1, broker.faults().len());
+    broker.tick(100_000);
+    assert!(broker.faults().is_empty());
+
+    // Duplicated receipts are only rewarded once.
+    broker.add_fault(FaultRequest {
+        fault: Fault::Duplicate {
+            filter: MsgFilter {
+                msg: Some("Receipt".into()),
+                ..Default::default()
+            },
+        },
+        heal_at: None,
+    })?;
+    let holder = broker.holders("ineiti")[0];
+    let viewer = broker.register(NodeSecret::random());
+    let mut expected = broker.get_node_info(holder)?.mana;
+    broker.request_page(Some(viewer), "ineiti", "index.html", tx);
+    assert_eq!(b"<h1>Hi</h1>".to_vec(), rx.try_recv()?.unwrap().page.data);
+    expected += 11.into();
+    assert_eq!(expected, broker.get_node_info(holder)?.mana);
+
+    Ok(())
+}
+
+#[test]
+fn test_isolated_holder() -> Result<(), Box<dyn Error>> {
+    let dir = common::pages_dir()?;
+    let sim = simulator::Config {
+        pages_dir: Some(dir.path().to_path_buf()),
+        nodes_flex: 0,
+        ..simulator::Config::default()
+    };
+    let mut broker = Broker::new(trusted::Config::default(), sim, 0)?;
+    for now in 1..=3 {
+        broker.tick(now * 1_000);
+    }
+
+    // The first holder doesn't answer, so the page comes from the next one.
+    let holders = broker.holders("ineiti");
+    assert!(holders.len() > 1);
+    broker.add_fault(FaultRequest {
+        fault: Fault::Isolate { id: holders[0] },
+        heal_at: None,
+    })?;
+    let (tx, rx) = channel();
+    broker.request_page(None, "ineiti", "index.html", tx);
+    assert!(rx.try_recv().is_err());
+    broker.tick(13_000);
+    assert_eq!(b"<h1>Hi</h1>".to_vec(), rx.try_recv()?.unwrap().page.data);
+
+    Ok(())
+}
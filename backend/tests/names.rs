@@ -0,0 +1,55 @@
+use std::error::Error;
+use test_log::test;
+
+use backend::{
+    api::names::NameError,
+    simul::{
+        broker::Broker,
+        node_types::{GroupID, NodeSecret},
+        simulator, trusted,
+    },
+};
+
+#[test]
+fn test_resolve() -> Result<(), Box<dyn Error>> {
+    let trusted = trusted::Config {
+        name_fee: 5,
+        ..trusted::Config::default()
+    };
+    let sim = simulator::Config {
+        nodes_root: 0,
+        nodes_flex: 0,
+        ..simulator::Config::default()
+    };
+    let mut broker = Broker::new(trusted, sim, 0)?;
+    let owner = broker.register(NodeSecret::random());
+    let other = broker.register(NodeSecret::random());
+    let group = GroupID::random();
+    broker.group_join(owner, group)??;
+    broker.group_join(other, group)??;
+
+    // The name can only be registered once the owner has enough mana.
+    assert!(matches!(
+        broker.register_name(owner, "ineiti", group)?,
+        Err(NameError::NotEnoughMana { .. })
+    ));
+    for now in 1..=5 {
+        broker.tick(now * 1_000);
+    }
+    let record = broker.register_name(owner, "ineiti", group)??;
+    assert_eq!(
+        Err(NameError::Taken),
+        broker.register_name(other, "ineiti", GroupID::random())?
+    );
+
+    // cyno://ineiti/ is served by the online members of the group.
+    let resolved = broker.resolve("ineiti")??;
+    assert_eq!(record, resolved.record);
+    let mut members = vec![owner, other];
+    members.sort();
+    assert_eq!(members, resolved.members);
+    broker.group_leave(other, group)??;
+    assert_eq!(vec![owner], broker.resolve("ineiti")??.members);
+    assert_eq!(Err(NameError::UnknownName), broker.resolve("unknown")?);
+    Ok(())
+}
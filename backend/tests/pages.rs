@@ -0,0 +1,200 @@
+use std::{error::Error, fs, sync::mpsc::channel};
+use test_log::test;
+
+mod common;
+
+use backend::simul::{
+    broker::Broker, chunk::CHUNK_SIZE, network, node_types::NodeSecret, simulator, trusted,
+};
+
+#[test]
+fn test_request_page() -> Result<(), Box<dyn Error>> {
+    let dir = common::pages_dir()?;
+    fs::write(dir.path().join("ineiti/styles.css"), "h1 {}")?;
+    // Spans several chunks, which are fetched separately.
+    let logo: Vec<u8> = (0..3 * CHUNK_SIZE / 2).map(|i| (i % 251) as u8).collect();
+    fs::write(dir.path().join("ineiti/logo.png"), &logo)?;
+    let sim = simulator::Config {
+        pages_dir: Some(dir.path().to_path_buf()),
+        ..simulator::Config::default()
+    };
+    let mut broker = Broker::new(trusted::Config::default(), sim, 0)?;
+
+    // The root nodes hosting the pages go online while ticking.
+    for now in 1..=3 {
+        broker.tick(now * 1_000);
+    }
+
+    let (tx, rx) = channel();
+    broker.request_page(None, "ineiti", "", tx.clone());
+    let page = rx.try_recv()?.expect("index.html is hosted").page;
+    assert_eq!("text/html; charset=utf-8", page.content_type);
+    assert_eq!(b"<h1>Hi</h1>".to_vec(), page.data);
+
+    broker.request_page(None, "ineiti", "styles.css", tx.clone());
+    assert_eq!(
+        "text/css; charset=utf-8",
+        rx.try_recv()?.unwrap().page.content_type
+    );
+    broker.request_page(None, "ineiti", "logo.png", tx.clone());
+    assert_eq!(logo, rx.try_recv()?.unwrap().page.data);
+    broker.request_page(None, "ineiti", "about.html", tx.clone());
+    assert_eq!(None, rx.try_recv()?);
+    broker.request_page(None, "cybernode", "index.html", tx);
+    assert_eq!(None, rx.try_recv()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_view_reward() -> Result<(), Box<dyn Error>> {
+    let dir = common::pages_dir()?;
+    let logo: Vec<u8> = (0..3 * CHUNK_SIZE / 2).map(|i| (i % 251) as u8).collect();
+    fs::write(dir.path().join("ineiti/logo.png"), &logo)?;
+    let sim = simulator::Config {
+        pages_dir: Some(dir.path().to_path_buf()),
+        ..simulator::Config::default()
+    };
+    let cfg = trusted::Config {
+        served_unit: 1024,
+        ..trusted::Config::default()
+    };
+    let mut broker = Broker::new(cfg, sim, 0)?;
+    for now in 1..=3 {
+        broker.tick(now * 1_000);
+    }
+
+    // Mana only changes on ticks, except for the reward of the holder which
+    // served the page.
+    let holder = broker.holders("ineiti")[0];
+    let viewer = broker.register(NodeSecret::random());
+    let before = broker.get_node_info(holder)?.mana;
+    let (tx, rx) = channel();
+    broker.request_page(Some(viewer), "ineiti", "logo.png", tx.clone());
+    assert_eq!(logo, rx.try_recv()?.unwrap().page.data);
+    let mut expected = before;
+    expected += (logo.len() as u128 / 1024).into();
+    assert_eq!(expected, broker.get_node_info(holder)?.mana);
+
+    // Anonymous views are not rewarded.
+    broker.request_page(None, "ineiti", "logo.png", tx);
+    assert_eq!(logo, rx.try_recv()?.unwrap().page.data);
+    assert_eq!(expected, broker.get_node_info(holder)?.mana);
+
+    Ok(())
+}
+
+#[test]
+fn test_viewer_pays() -> Result<(), Box<dyn Error>> {
+    let dir = common::pages_dir()?;
+    // The viewer doesn't get a copy of the page, so it doesn't view its own.
+    let sim = simulator::Config {
+        pages_dir: Some(dir.path().to_path_buf()),
+        nodes_flex: 0,
+        replication_factor: 1,
+        ..simulator::Config::default()
+    };
+    let cfg = trusted::Config {
+        served_unit: 1,
+        viewer_pays: true,
+        ..trusted::Config::default()
+    };
+    let mut broker = Broker::new(cfg, sim, 0)?;
+    let viewer = broker.register(NodeSecret::random());
+    for now in 1..=30 {
+        broker.tick(now * 1_000);
+    }
+
+    // The mana of the holder goes from the viewer to the holder.
+    let holder = broker.holders("ineiti")[0];
+    let viewer_before = broker.get_node_info(viewer)?.mana;
+    let holder_before = broker.get_node_info(holder)?.mana;
+    let (tx, rx) = channel();
+    broker.request_page(Some(viewer), "ineiti", "index.html", tx);
+    assert_eq!(b"<h1>Hi</h1>".to_vec(), rx.try_recv()?.unwrap().page.data);
+    let mut expected = viewer_before;
+    expected -= 11.into();
+    assert_eq!(expected, broker.get_node_info(viewer)?.mana);
+    let mut expected = holder_before;
+    expected += 11.into();
+    assert_eq!(expected, broker.get_node_info(holder)?.mana);
+
+    Ok(())
+}
+
+#[test]
+fn test_signed_viewer() -> Result<(), Box<dyn Error>> {
+    let dir = common::pages_dir()?;
+    let sim = simulator::Config {
+        pages_dir: Some(dir.path().to_path_buf()),
+        nodes_flex: 0,
+        replication_factor: 1,
+        ..simulator::Config::default()
+    };
+    let cfg = trusted::Config {
+        served_unit: 1,
+        ..trusted::Config::default()
+    };
+    let mut broker = Broker::new(cfg, sim, 0)?;
+    // Like a browser, the viewer only gives its public key.
+    let secret = NodeSecret::random();
+    let viewer = broker.register_public_key(secret.public_key());
+    for now in 1..=3 {
+        broker.tick(now * 1_000);
+    }
+
+    // The viewer gets the bytes to sign with the page, and the holder is
+    // paid once it submits the receipt, but only once.
+    let holder = broker.holders("ineiti")[0];
+    let before = broker.get_node_info(holder)?.mana;
+    let (tx, rx) = channel();
+    broker.request_page(Some(viewer), "ineiti", "index.html", tx);
+    let served = rx.try_recv()?.unwrap();
+    assert_eq!(b"<h1>Hi</h1>".to_vec(), served.page.data);
+    assert_eq!(1, served.receipts.len());
+    assert_eq!(
+        (holder, 11),
+        (served.receipts[0].server, served.receipts[0].bytes)
+    );
+    let receipt = served.receipts[0].sign(&secret);
+    let mut expected = before;
+    expected += 11.into();
+    assert_eq!(expected, broker.submit_receipt(receipt.clone())?);
+    assert_eq!(expected, broker.get_node_info(holder)?.mana);
+    assert!(broker.submit_receipt(receipt).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_latency() -> Result<(), Box<dyn Error>> {
+    let dir = common::pages_dir()?;
+    let sim = simulator::Config {
+        pages_dir: Some(dir.path().to_path_buf()),
+        network: network::Config {
+            root: network::LinkConfig {
+                latency: network::Latency::Fixed(100),
+                bandwidth: network::BANDWIDTH_1MBPS,
+                loss: 0,
+            },
+            ..network::Config::default()
+        },
+        ..simulator::Config::default()
+    };
+    let mut broker = Broker::new(trusted::Config::default(), sim, 0)?;
+    for now in 1..=3 {
+        broker.tick(now * 1_000);
+    }
+
+    // The page request, the page reply, the chunk request, and the chunk
+    // reply each take more than 200ms.
+    let (tx, rx) = channel();
+    broker.request_page(None, "ineiti", "index.html", tx);
+    for now in 1..=4 {
+        assert!(rx.try_recv().is_err());
+        broker.tick(3_000 + now * 205);
+    }
+    assert_eq!(b"<h1>Hi</h1>".to_vec(), rx.try_recv()?.unwrap().page.data);
+
+    Ok(())
+}
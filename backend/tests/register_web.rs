@@ -2,7 +2,10 @@ use std::error::Error;
 use tracing::info;
 use test_log::test;
 
-use backend::simul::{broker::Broker, node_types::NodeSecret};
+use backend::simul::{
+    broker::Broker,
+    node_types::{Mana, NodeSecret},
+};
 
 #[test]
 fn test_register() -> Result<(), Box<dyn Error>>{
@@ -17,3 +20,18 @@ fn test_register() -> Result<(), Box<dyn Error>>{
 
     Ok(())
 }
+
+#[test]
+fn test_tick() -> Result<(), Box<dyn Error>> {
+    let mut broker = Broker::default(0).expect("Couldn't start broker");
+    let id = broker.register(NodeSecret::random());
+    assert_eq!(Mana::zero(), broker.alive(id)?);
+
+    // Ticking the broker drives Trusted, so mana accumulates.
+    for now in 1..=5 {
+        broker.tick(now * 1_000);
+    }
+    assert_eq!(Mana::from(5), broker.alive(id)?);
+
+    Ok(())
+}
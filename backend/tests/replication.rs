@@ -0,0 +1,43 @@
+use std::{collections::HashSet, error::Error, sync::mpsc::channel};
+use test_log::test;
+
+mod common;
+
+use backend::simul::{broker::Broker, simulator, trusted};
+
+#[test]
+fn test_churn() -> Result<(), Box<dyn Error>> {
+    let dir = common::pages_dir()?;
+    let sim = simulator::Config {
+        nodes_root: 1,
+        nodes_flex: 10,
+        p_sign_in: 0x8000,
+        p_sign_out: 0x2000,
+        pages_dir: Some(dir.path().to_path_buf()),
+        replication_factor: 3,
+        ..simulator::Config::default()
+    };
+    let mut broker = Broker::new(trusted::Config::default(), sim, 0)?;
+
+    // The flex nodes keep going on- and offline, but after every tick the
+    // domain is back on enough nodes.
+    let (tx, rx) = channel();
+    let mut all_holders = HashSet::new();
+    for now in 1..=100 {
+        broker.tick(now * 1_000);
+        let holders = broker.holders("ineiti");
+        if now > 5 {
+            assert!(holders.len() >= 3, "only {holders:?} at {now}");
+            assert!(broker.replication().under_replicated.is_empty());
+        }
+        all_holders.extend(holders);
+
+        broker.request_page(None, "ineiti", "", tx.clone());
+        let page = rx.try_recv()?.expect("index.html is hosted").page;
+        assert_eq!(b"<h1>Hi</h1>".to_vec(), page.data);
+    }
+    // The copies moved to new nodes while the old ones went offline.
+    assert!(all_holders.len() > 3);
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use std::error::Error;
+use test_log::test;
+
+use backend::{
+    api::{
+        blocklist::Blocklist,
+        names::NameError,
+        reputation::{Category, ContentCheck, ContentPolicy, ReputationVote},
+    },
+    simul::{
+        broker::Broker,
+        node_types::{GroupID, NodeSecret},
+        simulator, trusted,
+    },
+};
+
+#[test]
+fn test_flagged_pages() -> Result<(), Box<dyn Error>> {
+    let trusted = trusted::Config {
+        name_fee: 1,
+        reputation_min_weight: 5,
+        ..trusted::Config::default()
+    };
+    let sim = simulator::Config {
+        nodes_root: 0,
+        nodes_flex: 0,
+        ..simulator::Config::default()
+    };
+    let mut broker = Broker::new(trusted, sim, 0)?;
+    let voter = NodeSecret::random();
+    let (owner, viewer) = (NodeSecret::random(), NodeSecret::random());
+    for secret in [voter, owner, viewer] {
+        broker.register(secret);
+    }
+    for now in 1..=5 {
+        broker.tick(now * 1_000);
+    }
+    let group = GroupID::random();
+    broker.register_name(owner.into(), "ineiti", group)??;
+    assert_eq!(ContentCheck::Serve, broker.content_check(None, "ineiti")?);
+
+    // Once flagged, anonymous viewers get a warning, and viewers can refuse
+    // the pages.
+    let rep = broker.vote(ReputationVote::new(&voter, group, Category::Nsfw, true, 0))?;
+    assert_eq!(vec![Category::Nsfw], rep.flagged());
+    let nsfw = vec![Category::Nsfw];
+    assert_eq!(
+        ContentCheck::Warn(nsfw.clone()),
+        broker.content_check(None, "ineiti")?
+    );
+    let policy = ContentPolicy {
+        refuse: nsfw.clone(),
+        warn: vec![],
+    };
+    broker.set_preferences(viewer.into(), policy)?;
+    assert_eq!(
+        ContentCheck::Refuse(nsfw),
+        broker.content_check(Some(viewer.into()), "ineiti")?
+    );
+    assert_eq!(
+        ContentCheck::Serve,
+        broker.content_check(Some(viewer.into()), "other")?
+    );
+
+    // Votes against the flag with more mana clear it.
+    broker.vote(ReputationVote::new(&owner, group, Category::Nsfw, false, 0))?;
+    let rep = broker.vote(ReputationVote::new(
+        &viewer,
+        group,
+        Category::Nsfw,
+        false,
+        0,
+    ))?;
+    assert!(rep.flagged().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_blocklist() -> Result<(), Box<dyn Error>> {
+    let trusted = trusted::Config {
+        name_fee: 1,
+        reputation_min_weight: 1,
+        ..trusted::Config::default()
+    };
+    let sim = simulator::Config {
+        nodes_root: 0,
+        nodes_flex: 0,
+        ..simulator::Config::default()
+    };
+    let mut broker = Broker::new(trusted, sim, 0)?;
+    let owner = NodeSecret::random();
+    broker.register(owner);
+    broker.tick(5_000);
+    let group = GroupID::random();
+    broker.register_name(owner.into(), "ineiti", group)??;
+    broker.vote(ReputationVote::new(
+        &owner,
+        group,
+        Category::Malware,
+        true,
+        0,
+    ))?;
+    assert!(broker.resolve("ineiti")?.is_ok());
+
+    // Names are blocked by the domain, or the categories of their group.
+    broker.set_blocklist(Blocklist::parse("ineiti spam\n")?);
+    let blocked = NameError::Blocked("spam".into());
+    assert_eq!(Err(blocked), broker.resolve("Ineiti")?);
+    broker.set_blocklist(Blocklist::parse("@malware\n")?);
+    let blocked = NameError::Blocked("flagged as malware".into());
+    assert_eq!(Err(blocked.clone()), broker.resolve("ineiti")?);
+    assert_eq!(
+        ContentCheck::Blocked("flagged as malware".into()),
+        broker.content_check(None, "ineiti")?
+    );
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use std::{error::Error, sync::mpsc::channel};
+use test_log::test;
+
+mod common;
+
+use backend::{
+    api::stats::NetworkStatus,
+    simul::{broker::Broker, node_types::NodeSecret, simulator, trusted},
+};
+
+#[test]
+fn test_restart() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let trust = trusted::Config {
+        data_dir: Some(dir.path().to_path_buf()),
+        ..trusted::Config::default()
+    };
+    let sim = simulator::Config {
+        nodes_root: 2,
+        nodes_flex: 2,
+        ..simulator::Config::default()
+    };
+    let mut broker = Broker::new(trust.clone(), sim.clone(), 0)?;
+    broker.tick(1_000);
+    let before = broker.network_status()?;
+    assert_eq!(5, before.users_total);
+    drop(broker);
+
+    // The restarted broker simulates the same nodes and gateway, instead of
+    // leaving the old ones behind in Trusted.
+    let mut broker = Broker::new(trust.clone(), sim.clone(), 1_000)?;
+    broker.tick(2_000);
+    let after = broker.network_status()?;
+    let names = |ns: &NetworkStatus| {
+        let mut names: Vec<_> = ns.nodes.iter().map(|n| (n.id, n.name.clone())).collect();
+        names.sort();
+        names
+    };
+    // The nodes also keep their names.
+    assert_eq!(names(&before), names(&after));
+    drop(broker);
+
+    // More simulated nodes are added to the stored ones.
+    let sim = simulator::Config {
+        nodes_flex: 3,
+        ..sim
+    };
+    let mut broker = Broker::new(trust, sim, 2_000)?;
+    broker.tick(3_000);
+    assert_eq!(6, broker.network_status()?.users_total);
+
+    Ok(())
+}
+
+#[test]
+fn test_restart_receipts() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let pages = common::pages_dir()?;
+    let trust = trusted::Config {
+        data_dir: Some(dir.path().to_path_buf()),
+        served_unit: 1,
+        ..trusted::Config::default()
+    };
+    // The viewer doesn't get a copy of the page, so it doesn't view its own.
+    let sim = simulator::Config {
+        pages_dir: Some(pages.path().to_path_buf()),
+        nodes_flex: 0,
+        replication_factor: 1,
+        ..simulator::Config::default()
+    };
+    let secret = NodeSecret::random();
+    let view = |now: u128| -> Result<(), Box<dyn Error>> {
+        let mut broker = Broker::new(trust.clone(), sim.clone(), now)?;
+        let viewer = broker.register(secret);
+        for tick in 1..=3 {
+            broker.tick(now + tick * 1_000);
+        }
+        let holder = broker.holders("ineiti")[0];
+        let before = broker.get_node_info(holder)?.mana;
+        let (tx, rx) = channel();
+        broker.request_page(Some(viewer), "ineiti", "index.html", tx);
+        assert!(rx.try_recv()?.is_some());
+        let mut expected = before;
+        expected += 11.into();
+        assert_eq!(expected, broker.get_node_info(holder)?.mana);
+        Ok(())
+    };
+
+    // The receipts of the viewer are still paid after a restart with an
+    // earlier clock.
+    view(10_000)?;
+    view(0)
+}
@@ -0,0 +1,59 @@
+use std::{error::Error, sync::mpsc::channel};
+use test_log::test;
+
+use backend::simul::{
+    broker::Broker,
+    mana_policy::ManaPolicyConfig,
+    node_types::NodeSecret,
+    page::{Page, PageFile},
+    simulator, trusted,
+};
+
+#[test]
+fn test_eviction() -> Result<(), Box<dyn Error>> {
+    // Without any mana, the owner cannot pay the rent of its page.
+    let trust = trusted::Config {
+        mana_policy: ManaPolicyConfig::StorageRent {
+            period: 1_000,
+            storage_unit: 1_000,
+        },
+        storage_grace: 5_000,
+        ..trusted::Config::default()
+    };
+    let mut broker = Broker::new(trust, simulator::Config::default(), 0)?;
+    let owner = broker.register(NodeSecret::random());
+    broker.store_file(
+        owner,
+        PageFile {
+            domain: "ineiti".into(),
+            path: "index.html".into(),
+            page: Page::new("index.html", b"<h1>Hi</h1>".to_vec()),
+        },
+    )?;
+
+    broker.tick(1_000);
+    let deletable = broker.deletable()?;
+    assert_eq!(1, deletable.len());
+    assert_eq!(
+        (owner, 6_000),
+        (deletable[0].owner, deletable[0].delete_after)
+    );
+    assert!(broker.holders("ineiti").len() > 1);
+
+    // During the grace period the page is still served, afterwards it is
+    // gone from all nodes.
+    let (tx, rx) = channel();
+    for now in 2..=6 {
+        broker.request_page(None, "ineiti", "", tx.clone());
+        assert!(rx.try_recv()?.is_some());
+        broker.tick(now * 1_000);
+    }
+    assert!(broker.holders("ineiti").is_empty());
+    broker.request_page(None, "ineiti", "", tx);
+    assert_eq!(None, rx.try_recv()?);
+
+    // And the owner is taken off the list.
+    broker.tick(7_000);
+    assert!(broker.deletable()?.is_empty());
+    Ok(())
+}
@@ -1,5 +1,5 @@
 import { Injectable } from '@angular/core';
-import { BehaviorSubject, Observable, interval } from 'rxjs';
+import { BehaviorSubject, Observable } from 'rxjs';
 import { NetworkStatus, NodeStatus } from '../lib/structs';
 import { NodeConnection } from '../lib/connect';
 
@@ -22,10 +22,10 @@ export class ConnectorService {
   connection = new NodeConnection();
 
   constructor() {
-    interval(1000).subscribe(async () => {
-      this._nodeStatus.next(await this.connection.getNodeStatus());
-      this._networkStatus.next(await this.connection.getNetworkStatus());
-    });
+    this.connection.subscribe(
+      (status) => this._nodeStatus.next(status),
+      (status) => this._networkStatus.next(status),
+    );
   }
 
   async getPage(url: string): Promise<string> {
@@ -1,3 +1,4 @@
 export const environment = {
+    backendUrl: "http://localhost:8080",
     useBackend: false,
 };
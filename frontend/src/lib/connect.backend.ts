@@ -1,27 +1,248 @@
+import { environment } from "../environments/environment";
 import { NetworkStatus, NodeStatus } from "./structs";
 
+// Where the secret of this node is kept in the browser.
+const SECRET_KEY = "cybernode-secret";
+// The requests are signed with the Ed25519 key of the secret, which never
+// leaves the browser, see HttpSignature in the backend.
+const KEY_HEADER = "X-Node-Key";
+const TIME_HEADER = "X-Node-Time";
+const SIGNATURE_HEADER = "X-Node-Signature";
+// The bytes the nodes served for a signed page request, which this node
+// pays for with the receipts it signs.
+const RECEIPTS_HEADER = "X-Receipts";
+// Wraps the secret as the seed of a PKCS#8 Ed25519 private key.
+const PKCS8_ED25519 = "302e020100300506032b657004220420";
+// The backend sees the node as active if it hears from it within a minute.
+const ALIVE_MS = 20_000;
+const RECONNECT_MS = 5_000;
+
 export class NodeConnectionBackend {
-    constructor(private mana = 0) { }
+    private key = loadKey(loadSecret());
+    // Every signature of the node needs its own time.
+    private lastTime = 0;
+    // Receipts are submitted one after the other, as Trusted refuses
+    // nonces lower than the last one.
+    private receipts = Promise.resolve();
 
     async getText(path: string): Promise<string> {
-        return "Not implemented";
+        const reply = await this.getPage(path);
+        if (!reply.ok) {
+            return `<h1>404 Page not found</h1><p>Sorry, don't know page ${path}`;
+        }
+        return reply.text();
     }
 
     async getBlob(path: string): Promise<Blob> {
-        return new Blob();
+        return (await this.getPage(path)).blob();
+    }
+
+    // Fetches the page signed by this node, and pays the nodes which
+    // served it.
+    private async getPage(path: string): Promise<Response> {
+        const url = new URL(`${environment.backendUrl}/v1/page/${path}`);
+        const reply = await fetch(url, {
+            headers: await this.signedHeaders("GET", url.pathname + url.search),
+        });
+        const requests: ReceiptRequest[] = JSON.parse(reply.headers.get(RECEIPTS_HEADER) ?? "[]");
+        for (const request of requests) {
+            this.receipts = this.receipts.then(() => this.submitReceipt(request))
+                .catch((e) => console.error("While submitting a receipt:", e));
+        }
+        return reply;
+    }
+
+    // Signs the receipt like Receipt::message in the backend, and submits it.
+    private async submitReceipt(request: ReceiptRequest) {
+        const key = await this.key;
+        const numbers = new DataView(new ArrayBuffer(16));
+        numbers.setBigUint64(0, BigInt(request.bytes));
+        numbers.setBigUint64(8, BigInt(request.nonce));
+        const message = concat([
+            new TextEncoder().encode("cybernode-receipt"), key.id, fromHex(request.server),
+            new Uint8Array(numbers.buffer),
+        ]);
+        const signature = await crypto.subtle.sign("Ed25519", key.privateKey, message);
+        const reply = await fetch(`${environment.backendUrl}/v1/receipt`, {
+            method: "POST",
+            headers: { "Content-Type": "application/json" },
+            body: JSON.stringify({
+                viewer: `0x${toHex(key.id)}`,
+                ...request,
+                signature: `0x${toHex(new Uint8Array(signature))}`,
+            }),
+        });
+        if (!reply.ok) {
+            console.warn(`Receipt refused: ${await reply.text()}`);
+        }
     }
 
     async getNetworkStatus(): Promise<NetworkStatus> {
+        const reply = await fetch(`${environment.backendUrl}/v1/network`);
+        const status = await reply.json();
         return {
-            users_total: 0,
-            users_active: 0,
+            users_total: status.users_total,
+            users_active: status.users_active,
         };
     }
 
+    // Registers this node, if it isn't yet, and returns its status.
     async getNodeStatus(): Promise<NodeStatus> {
+        const reply = await fetch(`${environment.backendUrl}/v1/register`, {
+            headers: await this.signedHeaders("GET", "/v1/register"),
+        });
+        if (!reply.ok) {
+            throw new Error(`Couldn't register: ${await reply.text()}`);
+        }
+        const info = await reply.json();
         return {
-            mana: 0,
-            name: "test",
+            mana: toNumber(info.mana),
+            name: info.name,
+        };
+    }
+
+    // Listens to the updates of the backend on its websocket, and keeps the
+    // node active by sending 'alive' on it. If the websocket closes, it
+    // connects again.
+    subscribe(node: (status: NodeStatus) => void, network: (status: NetworkStatus) => void) {
+        const connect = async () => {
+            let status: NodeStatus;
+            try {
+                status = await this.getNodeStatus();
+                node(status);
+                network(await this.getNetworkStatus());
+            } catch (e) {
+                console.error("While connecting to the backend:", e);
+                setTimeout(connect, RECONNECT_MS);
+                return;
+            }
+            this.listen(status, node, network, () => setTimeout(connect, RECONNECT_MS));
         };
+        connect();
     }
-}
\ No newline at end of file
+
+    private listen(status: NodeStatus, node: (status: NodeStatus) => void,
+        network: (status: NetworkStatus) => void, closed: () => void) {
+        const ws = new WebSocket(`${environment.backendUrl.replace(/^http/, "ws")}/v1/updates`);
+        let alive: ReturnType<typeof setInterval> | undefined;
+        ws.onopen = async () => {
+            ws.send(JSON.stringify(await this.sign("GET", "/v1/updates")));
+            alive = setInterval(() => ws.send("alive"), ALIVE_MS);
+        };
+        ws.onmessage = async (event) => {
+            const update = JSON.parse(event.data);
+            switch (update.type) {
+                case "Mana":
+                    status = { ...status, mana: toNumber(update.mana) };
+                    node(status);
+                    break;
+                case "NodeJoined":
+                case "NodeLeft":
+                    network(await this.getNetworkStatus());
+                    break;
+            }
+        };
+        ws.onclose = (event) => {
+            console.warn(`Websocket closed: ${event.reason}`);
+            clearInterval(alive);
+            closed();
+        };
+    }
+
+    private async signedHeaders(method: string, path: string): Promise<Record<string, string>> {
+        const sig = await this.sign(method, path);
+        return {
+            [KEY_HEADER]: sig.key,
+            [TIME_HEADER]: sig.time.toString(),
+            [SIGNATURE_HEADER]: sig.signature,
+        };
+    }
+
+    // Signs the request like HttpSignature::message in the backend: the
+    // time as 16 big-endian bytes, the SHA-256 of the body, the method, and
+    // the path with the query.
+    private async sign(method: string, path: string, body = new Uint8Array()): Promise<HttpSignature> {
+        const key = await this.key;
+        const time = Math.max(Date.now(), this.lastTime + 1);
+        this.lastTime = time;
+        const timeBytes = new Uint8Array(16);
+        new DataView(timeBytes.buffer).setBigUint64(8, BigInt(time));
+        const digest = new Uint8Array(await crypto.subtle.digest("SHA-256", body));
+        const text = new TextEncoder();
+        const message = concat([
+            text.encode("cybernode-http"), timeBytes, digest, text.encode(`${method} ${path}`),
+        ]);
+        const signature = await crypto.subtle.sign("Ed25519", key.privateKey, message);
+        return { key: key.publicKey, time, signature: `0x${toHex(new Uint8Array(signature))}` };
+    }
+}
+
+// The signature of a request, as the backend parses it from JSON.
+interface HttpSignature {
+    key: string;
+    time: number;
+    signature: string;
+}
+
+// The bytes a node served for a page, to be signed in a receipt.
+interface ReceiptRequest {
+    server: string;
+    bytes: number;
+    nonce: number;
+}
+
+interface NodeKey {
+    privateKey: CryptoKey;
+    // Hexadecimal public key, as in the X-Node-Key header.
+    publicKey: string;
+    // The ID of the node is the SHA-256 of its public key.
+    id: Uint8Array;
+}
+
+// Imports the secret as the Ed25519 key of the node, the same as the
+// backend derives it.
+async function loadKey(secret: string): Promise<NodeKey> {
+    const privateKey = await crypto.subtle.importKey("pkcs8", fromHex(PKCS8_ED25519 + secret),
+        "Ed25519", true, ["sign"]);
+    const jwk = await crypto.subtle.exportKey("jwk", privateKey);
+    const x = atob(jwk.x!.replace(/-/g, "+").replace(/_/g, "/"));
+    const publicKey = Uint8Array.from(x, (c) => c.charCodeAt(0));
+    return {
+        privateKey,
+        publicKey: `0x${toHex(publicKey)}`,
+        id: new Uint8Array(await crypto.subtle.digest("SHA-256", publicKey)),
+    };
+}
+
+// Returns the secret of this node, or creates a new one for a new browser.
+function loadSecret(): string {
+    let secret = localStorage.getItem(SECRET_KEY);
+    if (secret === null) {
+        const bytes = crypto.getRandomValues(new Uint8Array(32));
+        secret = toHex(bytes);
+        localStorage.setItem(SECRET_KEY, secret);
+    }
+    return secret;
+}
+
+// Mana is sent as a hexadecimal string.
+function toNumber(mana: string): number {
+    return Number(BigInt(mana));
+}
+
+function toHex(bytes: Uint8Array): string {
+    return Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+// Returns the bytes of a hexadecimal number, padded to at least 32 bytes, as
+// the backend leaves out the leading zeros of IDs.
+function fromHex(hex: string): Uint8Array {
+    const digits = hex.replace(/^0x/, "").padStart(64, "0");
+    return Uint8Array.from(digits.match(/../g) ?? [], (b) => parseInt(b, 16));
+}
+
+function concat(parts: Uint8Array[]): Uint8Array {
+    const all = new Uint8Array(parts.reduce((len, part) => len + part.length, 0));
+    parts.reduce((offset, part) => (all.set(part, offset), offset + part.length), 0);
+    return all;
+}
@@ -35,4 +35,11 @@ export class NodeConnectionMock {
             mana: this.mana++,
         }), 500 + Math.random() * 500));
     }
+
+    subscribe(node: (status: NodeStatus) => void, network: (status: NetworkStatus) => void) {
+        setInterval(async () => {
+            node(await this.getNodeStatus());
+            network(await this.getNetworkStatus());
+        }, 1000);
+    }
 }
\ No newline at end of file
@@ -29,4 +29,8 @@ export class NodeConnection {
     async getNodeStatus(): Promise<NodeStatus> {
         return this.impl!.getNodeStatus();
     }
+
+    subscribe(node: (status: NodeStatus) => void, network: (status: NetworkStatus) => void) {
+        this.impl!.subscribe(node, network);
+    }
 }
\ No newline at end of file
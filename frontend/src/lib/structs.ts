@@ -13,4 +13,6 @@ export interface INodeConnection {
     getBlob(path: string): Promise<Blob>;
     getNetworkStatus(): Promise<NetworkStatus>;
     getNodeStatus(): Promise<NodeStatus>;
+    // Calls the listeners with the current status, and again whenever it changes.
+    subscribe(node: (status: NodeStatus) => void, network: (status: NetworkStatus) => void): void;
 }
\ No newline at end of file